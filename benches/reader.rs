@@ -0,0 +1,48 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use wavevm_assembler::reader::{Reader, TokenKind};
+
+/// reads every token in `src`, returning the number of tokens
+fn read_all(src: &str) -> usize {
+    let mut reader = Reader::new(src);
+    let mut count = 0;
+    while reader.next_token().kind() != TokenKind::EoF {
+        count += 1;
+    }
+    count
+}
+
+/// a large program with a mix of instructions, comments, and indentation
+fn large_file() -> String {
+    let mut src = String::from("# a large program\n.const c1, 1, 2, 3, 4\n");
+    for idx in 0..20_000 {
+        src.push_str(match idx % 4 {
+            0 => "    add.w r0, r0, c1\n",
+            1 => "\tlsl.b r1, 3 # shift the low bytes\n",
+            2 => "    mov [r2.x]+, r0.xy\n",
+            _ => "\n# a comment on its own line\n",
+        });
+    }
+    src
+}
+
+fn lex_large_file(c: &mut Criterion) {
+    let src = large_file();
+    let mut group = c.benchmark_group("reader");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("large file", |b| b.iter(|| read_all(black_box(&src))));
+    group.finish();
+}
+
+fn lex_long_identifier(c: &mut Criterion) {
+    // a single token, so the time is spent entirely in finding where the identifier ends
+    let src = "r".repeat(1 << 20);
+    let mut group = c.benchmark_group("reader");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("long identifier", |b| b.iter(|| read_all(black_box(&src))));
+    group.finish();
+}
+
+criterion_group!(benches, lex_large_file, lex_long_identifier);
+criterion_main!(benches);
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    instruction::{Instruction, InstructionKind, OperandRole, RegSelector, MAX_REG_IDX},
+    lexer::Span,
+};
+
+/// the peak number of live general purpose registers in a program
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterPressure {
+    pub peak: u32,
+    /// the span of the first instruction where the peak is reached
+    pub span: Span,
+}
+
+/// computes which registers are live before each instruction of straight-line code,
+/// as a bitmask of register indices.
+/// nothing is considered live after the last instruction.
+pub fn liveness(program: &[Instruction]) -> Vec<u16> {
+    let mut live = vec![0; program.len()];
+    let mut current = 0_u16;
+    for (idx, inst) in program.iter().enumerate().rev() {
+        let (uses, kills) = uses_kills(inst.kind());
+        current &= !kills;
+        current |= uses;
+        live[idx] = current;
+    }
+    live
+}
+
+/// finds the maximum number of general purpose registers that are live at once,
+/// and where that happens. returns `None` for an empty program.
+pub fn max_live_registers(program: &[Instruction]) -> Option<RegisterPressure> {
+    let mut peak: Option<RegisterPressure> = None;
+    for (inst, live) in program.iter().zip(liveness(program)) {
+        // the general purpose registers come after all of the const registers
+        let count = (live >> (MAX_REG_IDX + 1)).count_ones();
+        if peak.is_none_or(|p| count > p.peak) {
+            peak = Some(RegisterPressure {
+                peak: count,
+                span: inst.span(),
+            });
+        }
+    }
+    peak
+}
+
+/// counts how many times each mnemonic is used in a program
+pub fn opcode_histogram(program: &[Instruction]) -> BTreeMap<&'static str, usize> {
+    let mut counts = BTreeMap::new();
+    for inst in program {
+        *counts.entry(inst.kind().mnemonic()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// approximate costs of instructions, in cycles.
+/// the VM does not document the timing of its instructions, so the default costs
+/// are only rough estimates, and may be overridden.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostModel {
+    /// the cost of moves, swizzles, math, shifts, and bitops
+    pub alu: u32,
+    /// the cost of a load or store to a single address
+    pub memory: u32,
+    /// the cost of each element of a scatter load or store, which uses
+    /// a separate address for every element
+    pub scatter_elem: u32,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            alu: 1,
+            memory: 2,
+            scatter_elem: 2,
+        }
+    }
+}
+
+impl CostModel {
+    /// the approximate cost of an instruction
+    pub fn cost(&self, kind: &InstructionKind) -> u32 {
+        use InstructionKind::*;
+        match kind {
+            Load { mem, dst: sel } | Store { src: sel, mem } => {
+                if mem.scatter() {
+                    self.scatter_elem * sel.selector().bits().count_ones()
+                } else {
+                    self.memory
+                }
+            }
+            _ => self.alu,
+        }
+    }
+
+    /// the approximate cost of running every instruction in a program once
+    pub fn program_cost(&self, program: &[Instruction]) -> u32 {
+        program.iter().map(|inst| self.cost(inst.kind())).sum()
+    }
+}
+
+/// gets the registers read by an instruction, and the registers that it entirely
+/// overwrites, as bitmasks of register indices. registers that are both read and
+/// overwritten are live before the instruction.
+/// registers that are only partially written are not killed, since their other elements
+/// may still be live.
+fn uses_kills(kind: &InstructionKind) -> (u16, u16) {
+    use InstructionKind::*;
+    let bit = |reg: RegSelector| 1_u16 << reg.idx();
+    let operands = kind.operands();
+
+    let uses = operands
+        .iter()
+        .filter(|(_, role)| role.reads())
+        .fold(0, |uses, (reg, _)| uses | bit(*reg));
+    let kills = match *kind {
+        // xoring or xnoring a register with itself does not depend on its old value
+        BitXor { src, dst } | BitXnor { src, dst } if src == dst => return (0, bit(dst)),
+        Move { dst, .. } | Load { dst, .. } if dst.selector().bits() != 0b1111 => 0,
+        // registers that are also read, such as incremented addresses, are never killed
+        _ => operands
+            .iter()
+            .filter(|(_, role)| *role == OperandRole::Dst)
+            .fold(0, |kills, (reg, _)| kills | bit(*reg)),
+    };
+    (uses, kills)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diag::Context, parser::Parser, source::Source};
+
+    /// parses `src`, which must not have any errors
+    fn program(src: &str) -> Vec<Instruction> {
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let mut parser = Parser::new(src);
+        let mut insts = Vec::new();
+        while let Ok(Some(inst)) = parser.parse_inst(&mut ctx) {
+            insts.push(inst);
+        }
+        assert!(!ctx.had_errs(), "{:?}", ctx.take_diags());
+        insts
+    }
+
+    #[test]
+    fn low_register_pressure() {
+        let program = program(
+            "mov r0.xyzw, c0.xyzw\nmov r1.xyzw, c1.xyzw\nadd.w r0, r0, r1\nadd.w r0, r0, c1\n",
+        );
+        let pressure = max_live_registers(&program).unwrap();
+        assert_eq!(pressure.peak, 2);
+        // both moved registers are first live together before the add that reads them
+        assert_eq!(pressure.span.low(), program[2].span().low());
+    }
+
+    #[test]
+    fn high_register_pressure() {
+        let program =
+            program("add.w r0, r0, r1\nadd.w r2, r2, r3\nadd.w r4, r4, r5\nadd.w r6, r6, r0\n");
+        let pressure = max_live_registers(&program).unwrap();
+        assert_eq!(pressure.peak, 7);
+        assert_eq!(pressure.span.low(), program[0].span().low());
+    }
+
+    #[test]
+    fn const_registers_are_not_counted() {
+        let program = program("add.w r0, r0, c1\nadd.w r0, r0, c2\n");
+        assert_eq!(max_live_registers(&program).unwrap().peak, 1);
+        assert!(max_live_registers(&[]).is_none());
+    }
+
+    #[test]
+    fn program_cost_sums_instructions() {
+        let program =
+            program("add.w r0, r0, c1\nmov r1.xy, [r2.x]\nmov [r2.*], r1.xyzw\nswizzle r0.wzyx\n");
+        let model = CostModel::default();
+        let costs = program
+            .iter()
+            .map(|inst| model.cost(inst.kind()))
+            .collect::<Vec<_>>();
+        // a scatter pays for each of its 4 elements
+        assert_eq!(costs, [1, 2, 8, 1]);
+        assert_eq!(model.program_cost(&program), 12);
+        assert_eq!(model.program_cost(&[]), 0);
+    }
+
+    #[test]
+    fn program_cost_uses_model() {
+        let program = program("add.w r0, r0, c1\nmov r1.x, [r2.x]\nmov r1.xy, [r2.*]\n");
+        let model = CostModel {
+            alu: 3,
+            memory: 10,
+            scatter_elem: 5,
+        };
+        assert_eq!(model.program_cost(&program), 3 + 10 + 5 * 2);
+    }
+
+    #[test]
+    fn histogram_of_a_mixed_program() {
+        let insts = program(
+            "mov r0.xy, c0.xy\n\
+             mov r1.x, [r0]\n\
+             mov [r0], r1.x\n\
+             add.w r0, r0, c1\n\
+             add.b r1, r1, c1\n\
+             sub.w r0, r0, c1\n\
+             sub.w r0, c1, r0\n\
+             not r0\n\
+             zero r1\n",
+        );
+        let histogram = opcode_histogram(&insts);
+        // loads, stores, and moves are all `mov`, and both forms of `sub` are `sub`
+        assert_eq!(
+            histogram.into_iter().collect::<Vec<_>>(),
+            [("add", 2), ("mov", 3), ("not", 1), ("sub", 2), ("xor", 1)]
+        );
+        assert!(opcode_histogram(&[]).is_empty());
+    }
+}
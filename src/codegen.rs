@@ -1,44 +1,87 @@
-use crate::instruction::{Instruction, InstructionKind, OpSize, RegSelector, ShiftAmount};
+use std::io::{self, Write};
+
+use crate::{
+    diag::{Diagnostic, Severity},
+    instruction::{Instruction, InstructionKind, OpSize, RegSelector, ShiftAmount},
+    options::Endianness,
+    validate::validate,
+};
 
 pub fn gen(insts: &[Instruction]) -> Vec<u16> {
-    insts.into_iter().flat_map(|i| gen_inst(*i)).collect()
+    insts.iter().map(|i| gen_word(*i)).collect()
+}
+
+/// converts generated code to bytes in the given byte order
+pub fn to_bytes(code: &[u16], endianness: Endianness) -> Vec<u8> {
+    code.iter()
+        .flat_map(|word| match endianness {
+            Endianness::Little => word.to_le_bytes(),
+            Endianness::Big => word.to_be_bytes(),
+        })
+        .collect()
+}
+
+/// writes generated code to `out` in the given byte order
+pub fn write_code(code: &[u16], endianness: Endianness, out: &mut impl Write) -> io::Result<()> {
+    for word in code {
+        match endianness {
+            Endianness::Little => out.write_all(&word.to_le_bytes())?,
+            Endianness::Big => out.write_all(&word.to_be_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+/// encodes `program` and appends the code to `out` in the given byte order, so that one
+/// buffer can be reused to encode many programs. nothing is written if any instruction
+/// cannot be encoded, and the diagnostics from [`validate`] are returned instead.
+/// warnings do not stop the program from being encoded, and are not returned.
+pub fn encode_into(
+    program: &[Instruction],
+    out: &mut Vec<u8>,
+    endianness: Endianness,
+) -> Result<(), Vec<Diagnostic>> {
+    let diags = validate(program);
+    if diags.iter().any(|d| d.severity() == Severity::Error) {
+        return Err(diags);
+    }
+    // every instruction is a single word
+    out.reserve(program.len() * 2);
+    for inst in program {
+        let word = gen_word(*inst);
+        out.extend_from_slice(&match endianness {
+            Endianness::Little => word.to_le_bytes(),
+            Endianness::Big => word.to_be_bytes(),
+        });
+    }
+    Ok(())
 }
 
-fn gen_inst(inst: Instruction) -> Vec<u16> {
+pub fn gen_inst(inst: Instruction) -> Vec<u16> {
+    vec![gen_word(inst)]
+}
+
+/// encodes a single instruction. every instruction is one word.
+pub fn gen_word(inst: Instruction) -> u16 {
     use InstructionKind::*;
     match *inst.kind() {
         Move { src, dst } => {
             // place a 1 bit everywhere we want to *not* move
             let skip_mask = src.selector().bits() ^ 0b1111;
-            vec![op_from_parts(
-                dst.reg().idx(),
-                src.reg().idx(),
-                skip_mask,
-                opcode::MOVE,
-            )]
+            op_from_parts(dst.reg().idx(), src.reg().idx(), skip_mask, opcode::MOVE)
         }
         Swizzle { reg } => {
             let bits = reg.selector().bits();
             let source = bits >> 4;
             let extra = bits & 0b1111;
-            vec![op_from_parts(
-                reg.reg().idx(),
-                source,
-                extra,
-                opcode::SWIZZLE,
-            )]
+            op_from_parts(reg.reg().idx(), source, extra, opcode::SWIZZLE)
         }
         Load { mem, dst } => {
             let size = 4 - dst.selector().bits().count_ones() as u8;
             let scatter = u8::from(mem.scatter());
             let increment = u8::from(mem.increment());
             let extra = (size << 2) | (scatter << 1) | increment;
-            vec![op_from_parts(
-                dst.reg().idx(),
-                mem.reg().idx(),
-                extra,
-                opcode::LOAD,
-            )]
+            op_from_parts(dst.reg().idx(), mem.reg().idx(), extra, opcode::LOAD)
         }
         Store { src, mem } => {
             let size = 4 - src.selector().bits().count_ones() as u8;
@@ -48,79 +91,36 @@ fn gen_inst(inst: Instruction) -> Vec<u16> {
             // this uses src as the dest operand and mem as the source operand because
             // both load and store use the source as an address and dest as a value
             // https://github.com/Meisaka/MeiVM2/blob/cd687f44a11bc3a0f318dcb1badb23f1f8dce44f/vm.txt#L64-L65
-            vec![op_from_parts(
-                src.reg().idx(),
-                mem.reg().idx(),
-                extra,
-                opcode::STORE,
-            )]
+            op_from_parts(src.reg().idx(), mem.reg().idx(), extra, opcode::STORE)
         }
 
-        Add { size, src, dst } => vec![math_op(math_ops::ADD, size, src, dst)],
-        AddSaturate { size, src, dst } => vec![math_op(math_ops::ADD_SAT, size, src, dst)],
-        Sub { size, src, dst } => vec![math_op(math_ops::SUB, size, src, dst)],
-        SubSaturate { size, src, dst } => vec![math_op(math_ops::SUB_SAT, size, src, dst)],
-        SubRev { size, src, dst } => vec![math_op(math_ops::SUBREV, size, src, dst)],
-        SubRevSaturate { size, src, dst } => vec![math_op(math_ops::SUBREV_SAT, size, src, dst)],
-        CmpEq { size, src, dst } => vec![math_op(math_ops::CMPEQ, size, src, dst)],
-        CmpNeq { size, src, dst } => vec![math_op(math_ops::CMPNEQ, size, src, dst)],
+        Add { size, src, dst } => math_op(math_ops::ADD, size, src, dst),
+        AddSaturate { size, src, dst } => math_op(math_ops::ADD_SAT, size, src, dst),
+        Sub { size, src, dst } => math_op(math_ops::SUB, size, src, dst),
+        SubSaturate { size, src, dst } => math_op(math_ops::SUB_SAT, size, src, dst),
+        SubRev { size, src, dst } => math_op(math_ops::SUBREV, size, src, dst),
+        SubRevSaturate { size, src, dst } => math_op(math_ops::SUBREV_SAT, size, src, dst),
+        CmpEq { size, src, dst } => math_op(math_ops::CMPEQ, size, src, dst),
+        CmpNeq { size, src, dst } => math_op(math_ops::CMPNEQ, size, src, dst),
 
-        ShiftLeft { size, dst, amount } => vec![shift_op(shift_ops::LEFT_SHIFT, size, dst, amount)],
+        ShiftLeft { size, dst, amount } => shift_op(shift_ops::LEFT_SHIFT, size, dst, amount),
         ShiftRightLogical { size, dst, amount } => {
-            vec![shift_op(shift_ops::LOGICAL_RIGHT_SHIFT, size, dst, amount)]
+            shift_op(shift_ops::LOGICAL_RIGHT_SHIFT, size, dst, amount)
         }
         ShiftRightArithmetic { size, dst, amount } => {
-            vec![shift_op(
-                shift_ops::ARITHMETIC_RIGHT_SHIFT,
-                size,
-                dst,
-                amount,
-            )]
-        }
-        RotateLeft { size, dst, amount } => {
-            vec![shift_op(shift_ops::ROTATE_LEFT, size, dst, amount)]
-        }
-        RotateRight { size, dst, amount } => {
-            vec![shift_op(shift_ops::ROTATE_RIGHT, size, dst, amount)]
+            shift_op(shift_ops::ARITHMETIC_RIGHT_SHIFT, size, dst, amount)
         }
+        RotateLeft { size, dst, amount } => shift_op(shift_ops::ROTATE_LEFT, size, dst, amount),
+        RotateRight { size, dst, amount } => shift_op(shift_ops::ROTATE_RIGHT, size, dst, amount),
 
-        BitAnd { src, dst } => vec![op_from_parts(
-            dst.idx(),
-            src.idx(),
-            bit_ops::AND,
-            opcode::BITOP,
-        )],
-        BitOr { src, dst } => vec![op_from_parts(
-            dst.idx(),
-            src.idx(),
-            bit_ops::OR,
-            opcode::BITOP,
-        )],
-        BitXor { src, dst } => vec![op_from_parts(
-            dst.idx(),
-            src.idx(),
-            bit_ops::XOR,
-            opcode::BITOP,
-        )],
-        BitNand { src, dst } => vec![op_from_parts(
-            dst.idx(),
-            src.idx(),
-            bit_ops::NAND,
-            opcode::BITOP,
-        )],
-        BitNor { src, dst } => vec![op_from_parts(
-            dst.idx(),
-            src.idx(),
-            bit_ops::NOR,
-            opcode::BITOP,
-        )],
-        BitXnor { src, dst } => vec![op_from_parts(
-            dst.idx(),
-            src.idx(),
-            bit_ops::XNOR,
-            opcode::BITOP,
-        )],
-        UnaryBitNot { dst } => vec![op_from_parts(dst.idx(), 0, bit_ops::NOT_DST, opcode::BITOP)],
+        BitAnd { src, dst } => op_from_parts(dst.idx(), src.idx(), bit_ops::AND, opcode::BITOP),
+        BitOr { src, dst } => op_from_parts(dst.idx(), src.idx(), bit_ops::OR, opcode::BITOP),
+        BitXor { src, dst } => op_from_parts(dst.idx(), src.idx(), bit_ops::XOR, opcode::BITOP),
+        BitNand { src, dst } => op_from_parts(dst.idx(), src.idx(), bit_ops::NAND, opcode::BITOP),
+        BitNor { src, dst } => op_from_parts(dst.idx(), src.idx(), bit_ops::NOR, opcode::BITOP),
+        BitXnor { src, dst } => op_from_parts(dst.idx(), src.idx(), bit_ops::XNOR, opcode::BITOP),
+        UnaryBitNot { dst } => op_from_parts(dst.idx(), 0, bit_ops::NOT_DST, opcode::BITOP),
+        BitNot { src, dst } => op_from_parts(dst.idx(), src.idx(), bit_ops::NOT_SRC, opcode::BITOP),
     }
 }
 
@@ -148,60 +148,161 @@ fn shift_op(op: u8, size: OpSize, dst: RegSelector, amount: ShiftAmount) -> u16
     op_from_parts(dst.idx(), src, op, size)
 }
 
-mod opcode {
+pub(crate) mod opcode {
     /// TODO: implement
     #[expect(dead_code, reason = "not yet implemented by the assembler")]
-    pub(super) const SYSTEM: u8 = 0b0000;
+    pub(crate) const SYSTEM: u8 = 0b0000;
     #[expect(dead_code, reason = "not yet used by the VM")]
-    pub(super) const EXTRA1: u8 = 0b0001;
+    pub(crate) const EXTRA1: u8 = 0b0001;
     #[expect(dead_code, reason = "not yet used by the VM")]
-    pub(super) const EXTRA2: u8 = 0b0010;
+    pub(crate) const EXTRA2: u8 = 0b0010;
     #[expect(dead_code, reason = "not yet used by the VM")]
-    pub(super) const EXTRA3: u8 = 0b0011;
-    pub(super) const MOVE: u8 = 0b0100;
-    pub(super) const SWIZZLE: u8 = 0b0101;
-    pub(super) const LOAD: u8 = 0b0110;
-    pub(super) const STORE: u8 = 0b0111;
-    pub(super) const MATH8: u8 = 0b1000;
-    pub(super) const MATH16: u8 = 0b1001;
-    pub(super) const SHIFT8: u8 = 0b1010;
-    pub(super) const SHIFT16: u8 = 0b1011;
-    pub(super) const BITOP: u8 = 0b1100;
+    pub(crate) const EXTRA3: u8 = 0b0011;
+    pub(crate) const MOVE: u8 = 0b0100;
+    pub(crate) const SWIZZLE: u8 = 0b0101;
+    pub(crate) const LOAD: u8 = 0b0110;
+    pub(crate) const STORE: u8 = 0b0111;
+    pub(crate) const MATH8: u8 = 0b1000;
+    pub(crate) const MATH16: u8 = 0b1001;
+    pub(crate) const SHIFT8: u8 = 0b1010;
+    pub(crate) const SHIFT16: u8 = 0b1011;
+    pub(crate) const BITOP: u8 = 0b1100;
     /// TODO: implement
     #[expect(dead_code, reason = "not yet implemented by the assembler")]
-    pub(super) const SPECOP: u8 = 0b1101;
+    pub(crate) const SPECOP: u8 = 0b1101;
     #[expect(dead_code, reason = "not yet used by the VM")]
-    pub(super) const EXTRA14: u8 = 0b1110;
+    pub(crate) const EXTRA14: u8 = 0b1110;
     #[expect(dead_code, reason = "not yet used by the VM")]
-    pub(super) const EXTRA15: u8 = 0b1111;
+    pub(crate) const EXTRA15: u8 = 0b1111;
 }
 
-mod math_ops {
-    pub(super) const ADD: u8 = 0x0;
-    pub(super) const SUB: u8 = 0x1;
-    pub(super) const SUBREV: u8 = 0x2;
-    pub(super) const CMPEQ: u8 = 0x3;
+pub(crate) mod math_ops {
+    pub(crate) const ADD: u8 = 0x0;
+    pub(crate) const SUB: u8 = 0x1;
+    pub(crate) const SUBREV: u8 = 0x2;
+    pub(crate) const CMPEQ: u8 = 0x3;
+
+    pub(crate) const CMPNEQ: u8 = 0x7;
+    pub(crate) const ADD_SAT: u8 = 0x8;
+    pub(crate) const SUB_SAT: u8 = 0x9;
+    pub(crate) const SUBREV_SAT: u8 = 0xA;
+}
 
-    pub(super) const CMPNEQ: u8 = 0x7;
-    pub(super) const ADD_SAT: u8 = 0x8;
-    pub(super) const SUB_SAT: u8 = 0x9;
-    pub(super) const SUBREV_SAT: u8 = 0xA;
+pub(crate) mod shift_ops {
+    pub(crate) const LEFT_SHIFT: u8 = 0b0000;
+    pub(crate) const LOGICAL_RIGHT_SHIFT: u8 = 0b0001;
+    pub(crate) const ARITHMETIC_RIGHT_SHIFT: u8 = 0b0010;
+    pub(crate) const ROTATE_LEFT: u8 = 0b0011;
+    pub(crate) const ROTATE_RIGHT: u8 = 0b0111;
 }
 
-mod shift_ops {
-    pub(super) const LEFT_SHIFT: u8 = 0b0000;
-    pub(super) const LOGICAL_RIGHT_SHIFT: u8 = 0b0001;
-    pub(super) const ARITHMETIC_RIGHT_SHIFT: u8 = 0b0010;
-    pub(super) const ROTATE_LEFT: u8 = 0b0011;
-    pub(super) const ROTATE_RIGHT: u8 = 0b0111;
+/// bitops are encoded as a truth table, where the bit at `(dst << 1) | src` is the result
+pub(crate) mod bit_ops {
+    pub(crate) const AND: u8 = 0b1000;
+    pub(crate) const OR: u8 = 0b1110;
+    pub(crate) const XOR: u8 = 0b0110;
+    pub(crate) const NAND: u8 = 0b0111;
+    pub(crate) const NOR: u8 = 0b0001;
+    pub(crate) const XNOR: u8 = 0b1001;
+    pub(crate) const NOT_DST: u8 = 0b0011;
+    pub(crate) const NOT_SRC: u8 = 0b0101;
 }
 
-mod bit_ops {
-    pub(super) const AND: u8 = 0b1000;
-    pub(super) const OR: u8 = 0b1110;
-    pub(super) const XOR: u8 = 0b0110;
-    pub(super) const NAND: u8 = 0b0111;
-    pub(super) const NOR: u8 = 0b0001;
-    pub(super) const XNOR: u8 = 0b1001;
-    pub(super) const NOT_DST: u8 = 0b0011;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Span, options::AssembleOptions};
+
+    /// encodes a single instruction written in assembly
+    fn encode(src: &str) -> Vec<u16> {
+        gen_inst(
+            crate::parse_instruction(src, &AssembleOptions::default())
+                .expect("instruction should parse"),
+        )
+    }
+
+    #[test]
+    fn unary_and_binary_not() {
+        let unary = crate::parse_instruction("not r0", &AssembleOptions::default()).unwrap();
+        assert!(matches!(unary.kind(), InstructionKind::UnaryBitNot { .. }));
+        let binary = crate::parse_instruction("not r0, r1", &AssembleOptions::default()).unwrap();
+        assert!(matches!(binary.kind(), InstructionKind::BitNot { .. }));
+
+        // the unary form inverts the dst, and the binary form inverts the src into the dst
+        assert_eq!(encode("not r0"), [0x803C]);
+        assert_eq!(encode("not r0, r1"), [0x895C]);
+        assert_ne!(encode("not r0, r0"), encode("not r0"));
+    }
+
+    #[test]
+    fn zero_lowers_to_xor() {
+        assert_eq!(encode("zero r0"), [0x886C]);
+        assert_eq!(encode("zero r0"), encode("xor r0, r0"));
+        assert_eq!(encode("zero r3"), encode("xor r3, r3"));
+        assert!(crate::parse_instruction("zero c0", &AssembleOptions::default()).is_err());
+    }
+
+    #[test]
+    fn encode_into_reuses_the_buffer() {
+        let program = ["not r0", "add.w r0, r0, c1", "not r0, r1"]
+            .map(|src| crate::parse_instruction(src, &AssembleOptions::default()).unwrap());
+        let mut out = Vec::with_capacity(64);
+        let ptr = out.as_ptr();
+        for _ in 0..10 {
+            encode_into(&program, &mut out, Endianness::Little).unwrap();
+        }
+        assert_eq!(out.len(), 60);
+        assert_eq!(out.capacity(), 64);
+        assert_eq!(out.as_ptr(), ptr);
+        assert_eq!(out[..6], [0x3C, 0x80, 0x09, 0x81, 0x5C, 0x89]);
+
+        // encoding after clearing reuses the same allocation
+        out.clear();
+        encode_into(&program, &mut out, Endianness::Big).unwrap();
+        assert_eq!(out, [0x80, 0x3C, 0x81, 0x09, 0x89, 0x5C]);
+        assert_eq!(out.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn encode_into_writes_nothing_on_error() {
+        let bad = Instruction::new(
+            InstructionKind::UnaryBitNot {
+                dst: RegSelector::new_const(0, Span::DUMMY),
+            },
+            Span::DUMMY,
+        );
+        let mut out = vec![1, 2];
+        let good = crate::parse_instruction("not r0", &AssembleOptions::default()).unwrap();
+        assert!(encode_into(&[good, bad], &mut out, Endianness::Little).is_err());
+        assert_eq!(out, [1, 2]);
+    }
+
+    #[test]
+    fn encode_into_allows_warnings() {
+        for src in ["swizzle ri.yzwx", "mov r0.x, [ri.x]+"] {
+            let inst = crate::parse_instruction(src, &AssembleOptions::default()).unwrap();
+            let diags = validate(&[inst]);
+            assert!(
+                !diags.is_empty() && diags.iter().all(|d| d.severity() == Severity::Warning),
+                "{}",
+                src
+            );
+            let mut out = Vec::new();
+            encode_into(&[inst], &mut out, Endianness::Little).unwrap();
+            assert_eq!(out, to_bytes(&gen(&[inst]), Endianness::Little), "{}", src);
+        }
+    }
+
+    #[test]
+    fn gen_word_matches_gen_inst() {
+        for src in [
+            "not r0",
+            "add.w r0, r0, c1",
+            "mov r0.x, [ri]+",
+            "lsl.b r1, 3",
+        ] {
+            let inst = crate::parse_instruction(src, &AssembleOptions::default()).unwrap();
+            assert_eq!(gen_inst(inst), [gen_word(inst)], "{}", src);
+        }
+    }
 }
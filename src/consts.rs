@@ -0,0 +1,28 @@
+use crate::instruction::MAX_REG_IDX;
+
+/// the initial values of the const registers, as set by `.const` directives
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConstRegs {
+    values: [Option<[u16; 4]>; MAX_REG_IDX as usize + 1],
+}
+
+impl ConstRegs {
+    /// gets the value of the const register with the given index, if it was set
+    pub fn get(&self, idx: u8) -> Option<[u16; 4]> {
+        self.values[idx as usize]
+    }
+
+    /// sets the value of the const register with the given index, returning whether
+    /// it was already set
+    pub fn set(&mut self, idx: u8, value: [u16; 4]) -> bool {
+        self.values[idx as usize].replace(value).is_some()
+    }
+
+    /// iterates over the indexes and values of every const register that was set
+    pub fn iter(&self) -> impl Iterator<Item = (u8, [u16; 4])> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, val)| val.map(|val| (idx as u8, val)))
+    }
+}
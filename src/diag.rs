@@ -3,81 +3,221 @@ use std::mem;
 
 use crate::{lexer::Span, source::Source};
 
+/// receives diagnostics as soon as they are reported, for consumers that handle them
+/// while assembling instead of once it is done
+pub trait DiagnosticSink {
+    fn emit(&mut self, diag: Diagnostic);
+}
+
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn emit(&mut self, diag: Diagnostic) {
+        self.push(diag);
+    }
+}
+
 pub struct Context {
-    source: Source,
+    /// every file that has been loaded, indexed by file id.
+    /// the first file is the main source file.
+    files: Vec<Source>,
+    /// the diagnostics that have not been emitted yet, which is always empty when
+    /// there is a sink
     diags: Vec<Diagnostic>,
+    /// where diagnostics are sent as they are reported, if anywhere
+    sink: Option<Box<dyn DiagnosticSink>>,
     /// whether this has ever had any errors
     had_errs: bool,
+    /// whether warnings should be reported as errors
+    warnings_as_errors: bool,
+    /// the number of columns between tab stops when reporting columns
+    tab_width: u32,
 }
 
 impl Context {
     pub fn new(source: Source) -> Self {
         Self {
-            source,
+            files: vec![source],
             diags: Vec::new(),
+            sink: None,
             had_errs: false,
+            warnings_as_errors: false,
+            tab_width: 1,
         }
     }
 
+    /// gets the main source file
     pub fn source(&self) -> &Source {
-        &self.source
+        &self.files[0]
+    }
+
+    /// gets every file, indexed by file id
+    pub fn files(&self) -> &[Source] {
+        self.files.as_slice()
     }
 
-    pub fn add_diag(&mut self, diag: Diagnostic) {
-        self.diags.push(diag);
-        self.had_errs = true;
+    /// gets the source of the file with the given id
+    pub fn file(&self, file: u32) -> &Source {
+        &self.files[file as usize]
+    }
+
+    /// adds a file to the file table, returning its id
+    pub fn add_file(&mut self, source: Source) -> u32 {
+        self.files.push(source);
+        (self.files.len() - 1) as u32
+    }
+
+    /// sends every diagnostic to `sink` as soon as it is reported, instead of keeping it
+    /// to be emitted later
+    pub fn with_sink(mut self, sink: impl DiagnosticSink + 'static) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    pub fn set_warnings_as_errors(&mut self, warnings_as_errors: bool) {
+        self.warnings_as_errors = warnings_as_errors;
+    }
+
+    /// sets the number of columns between tab stops when reporting columns. a width of 1
+    /// counts tabs like any other character.
+    pub fn set_tab_width(&mut self, tab_width: u32) {
+        self.tab_width = tab_width.max(1);
+    }
+
+    pub fn add_diag(&mut self, mut diag: Diagnostic) {
+        if self.warnings_as_errors {
+            diag.severity = Severity::Error;
+        }
+        if diag.severity() == Severity::Error {
+            self.had_errs = true;
+        }
+        match &mut self.sink {
+            Some(sink) => sink.emit(diag),
+            None => self.diags.push(diag),
+        }
+    }
+
+    /// removes every diagnostic that has not been emitted yet, to be handled by the caller
+    pub fn take_diags(&mut self) -> Vec<Diagnostic> {
+        mem::take(&mut self.diags)
     }
 
     pub fn had_errs(&mut self) -> bool {
         self.had_errs
     }
 
-    pub fn emit_errs(&mut self) {
+    pub fn emit_diags(&mut self) {
         // replace the current diagnostics with an empty list and then process them
         // this is done to be able to pass ctx to format
         let diags = mem::take(&mut self.diags);
 
         for d in diags {
-            println!("ERROR: {}", d.format(self));
+            println!("{}: {}", d.severity(), d.format(self));
         }
     }
 
-    /// gets the 1-indexed line and column of the start of the given span
+    /// emits every diagnostic as a single JSON array, for editors and other tools.
+    /// see [`Context::format_json`] for the format.
+    pub fn emit_diags_json(&mut self) {
+        let diags = mem::take(&mut self.diags);
+        println!("{}", self.format_json(diags.as_slice()));
+    }
+
+    /// formats `diags` as a JSON array with an object for each diagnostic, of the form
+    /// `{"severity", "message", "file", "start", "end", "line", "column", "notes"}`.
+    /// `start` and `end` are byte offsets into the file, and `line` and `column` are
+    /// the same as in the human readable format. `notes` is an array of objects with
+    /// the same fields other than `severity` and `notes`. each location field is `null`
+    /// for diagnostics and notes that have no location.
+    pub fn format_json(&self, diags: &[Diagnostic]) -> String {
+        let diags = diags
+            .iter()
+            .map(|d| {
+                let notes = d
+                    .notes
+                    .iter()
+                    .map(|note| {
+                        format!(
+                            "{{\"message\":{},{}}}",
+                            json_str(&note.msg),
+                            self.json_location(note.span.unwrap_or(Span::DUMMY))
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!(
+                    "{{\"severity\":{},\"message\":{},{},\"notes\":[{}]}}",
+                    json_str(match d.severity {
+                        Severity::Error => "error",
+                        Severity::Warning => "warning",
+                    }),
+                    json_str(&d.msg),
+                    self.json_location(d.span),
+                    notes
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("[{}]", diags.join(","))
+    }
+
+    /// formats the location fields of a diagnostic or note in JSON
+    fn json_location(&self, span: Span) -> String {
+        let source = self.file(span.file());
+        if span.is_dummy() {
+            return format!(
+                "\"file\":{},\"start\":null,\"end\":null,\"line\":null,\"column\":null",
+                json_str(source.filename())
+            );
+        }
+        let (line_idx, col) = self.line_info(span);
+        format!(
+            "\"file\":{},\"start\":{},\"end\":{},\"line\":{},\"column\":{}",
+            json_str(source.filename()),
+            span.low(),
+            span.high(),
+            // human lines are 1 indexed
+            source.first_line() + line_idx + 1,
+            col
+        )
+    }
+
+    /// gets the 0-indexed line in the span's source and column of the start of the span
     fn line_info(&self, span: Span) -> (u32, u32) {
+        let source = self.file(span.file());
         // get the current line (0-indexed) by finding the first line that is past
         // the span, and then going back one
-        let line_idx = self
-            .source
+        let line_idx = source
             .line_starts()
             .partition_point(|start| *start <= span.low())
             .saturating_sub(1);
 
-        let line = line_idx as u32 + 1; // human lines are 1 indexed
-
-        let col = span
-            .low()
-            .checked_sub(
-                *self
-                    .source
-                    .line_starts()
-                    .get(line_idx)
-                    .expect("line should exist"),
-            )
+        let line_start = *source
+            .line_starts()
+            .get(line_idx)
+            .expect("line should exist");
+        let before = source
+            .src()
+            .get(line_start as usize..span.low() as usize)
             .expect("span low should be >= line start");
+        // columns count chars, and tabs move to the next tab stop
+        let col = before.chars().fold(0, |col, c| {
+            if c == '\t' {
+                col + self.tab_width - col % self.tab_width
+            } else {
+                col + 1
+            }
+        });
 
-        // TODO: column number
-        (line, col)
+        (line_idx as u32, col)
     }
 
-    fn get_line_text(&self, line_idx: u32) -> Option<&str> {
-        let line_start = *self.source.line_starts().get(line_idx as usize)? as usize;
-        let line_end = self
-            .source
+    fn get_line_text(&self, file: u32, line_idx: u32) -> Option<&str> {
+        let source = self.file(file);
+        let line_start = *source.line_starts().get(line_idx as usize)? as usize;
+        let line_end = source
             .line_starts()
             .get((line_idx + 1) as usize)
-            .map_or(self.source.src().len(), |end| *end as usize);
+            .map_or(source.src().len(), |end| *end as usize);
 
-        Some(&self.source.src()[line_start..line_end])
+        Some(&source.src()[line_start..line_end])
     }
 }
 
@@ -89,11 +229,45 @@ impl fmt::Debug for Context {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// warnings are reported but do not cause assembly to fail
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "ERROR"),
+            Severity::Warning => write!(f, "WARNING"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Diagnostic {
     msg: String,
     span: Span,
-    notes: Vec<String>,
+    severity: Severity,
+    notes: Vec<Note>,
+}
+
+/// extra information attached to a diagnostic, which may point to its own location
+#[derive(Debug)]
+pub struct Note {
+    msg: String,
+    span: Option<Span>,
+}
+
+impl Note {
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
 }
 
 impl Diagnostic {
@@ -101,29 +275,229 @@ impl Diagnostic {
         Self {
             msg,
             span,
+            severity: Severity::Error,
             notes: Vec::new(),
         }
     }
 
+    pub fn new_warning(msg: String, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(msg, span)
+        }
+    }
+
     pub fn with_note(mut self, note: String) -> Self {
-        self.notes.push(note);
+        self.notes.push(Note {
+            msg: note,
+            span: None,
+        });
         self
     }
 
+    /// adds a note that points to `span`, such as a previous definition
+    pub fn with_note_at(mut self, note: String, span: Span) -> Self {
+        self.notes.push(Note {
+            msg: note,
+            span: Some(span),
+        });
+        self
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
     pub fn span(&self) -> Span {
         self.span
     }
 
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
     fn format(&self, ctx: &Context) -> String {
-        let (line, col) = ctx.line_info(self.span);
-        let src_str = ctx.get_line_text(line - 1).unwrap_or("");
-        format!(
-            "{}\n  {}:{}:{}  {}",
-            self.msg,
-            ctx.source().filename(),
-            line,
-            col,
-            src_str,
-        )
+        let mut out = format!("{}\n{}", self.msg, location(ctx, self.span));
+        for note in &self.notes {
+            out.push_str(&format!("  note: {}\n", note.msg));
+            if let Some(span) = note.span {
+                out.push_str(&location(ctx, span));
+            }
+        }
+        out
+    }
+}
+
+/// quotes `s` as a JSON string
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// formats the location of `span` and the line it is on, ending with a newline
+pub(crate) fn location(ctx: &Context, span: Span) -> String {
+    let source = ctx.file(span.file());
+    // diagnostics about generated code or the whole program have no location
+    if span.is_dummy() {
+        return format!("  {}: <no location>\n", source.filename());
+    }
+
+    let (line_idx, col) = ctx.line_info(span);
+    let src_str = ctx.get_line_text(span.file(), line_idx).unwrap_or("");
+    // human lines are 1 indexed
+    let line = source.first_line() + line_idx + 1;
+    format!(
+        "  {}:{}:{}  {}\n",
+        source.filename(),
+        line,
+        col,
+        src_str.trim_end_matches(['\r', '\n']),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_files() -> Context {
+        let mut ctx = Context::new(Source::new(
+            String::from("main.s"),
+            String::from("not r0\n.include \"inc.s\"\n"),
+        ));
+        ctx.add_file(Source::new(
+            String::from("inc.s"),
+            String::from("not r1\nnot r2\n"),
+        ));
+        ctx
+    }
+
+    #[test]
+    fn spans_render_with_their_filename() {
+        let ctx = two_files();
+        assert_eq!(
+            location(&ctx, Span::new_in_file(4, 6, 0)),
+            "  main.s:1:4  not r0\n"
+        );
+        assert_eq!(
+            location(&ctx, Span::new_in_file(11, 13, 1)),
+            "  inc.s:2:4  not r2\n"
+        );
+    }
+
+    #[test]
+    fn notes_render_in_their_own_file() {
+        let ctx = two_files();
+        let diag = Diagnostic::new(String::from("error"), Span::new_in_file(7, 15, 0))
+            .with_note_at(String::from("here"), Span::new_in_file(0, 3, 1));
+        assert_eq!(
+            diag.format(&ctx),
+            "error\n  main.s:2:0  .include \"inc.s\"\n  note: here\n  inc.s:1:0  not r1\n"
+        );
+    }
+
+    #[test]
+    fn synthetic_span_renders_without_location() {
+        let ctx = two_files();
+        let diag = Diagnostic::new(String::from("generated"), Span::DUMMY)
+            .with_note_at(String::from("from here"), Span::DUMMY);
+        assert_eq!(
+            diag.format(&ctx),
+            "generated\n  main.s: <no location>\n  note: from here\n  main.s: <no location>\n"
+        );
+        let json = ctx.format_json(&[diag]);
+        assert!(json.contains("\"start\":null,\"end\":null,\"line\":null,\"column\":null"));
+    }
+
+    #[test]
+    fn empty_span_at_start_has_location() {
+        let ctx = Context::new(Source::new(String::from("empty.s"), String::new()));
+        assert_eq!(location(&ctx, Span::new(0, 0)), "  empty.s:1:0  \n");
+        let json = ctx.format_json(&[Diagnostic::new(String::from("e"), Span::new(0, 0))]);
+        assert!(
+            json.contains("\"start\":0,\"end\":0,\"line\":1,\"column\":0"),
+            "{}",
+            json
+        );
+    }
+
+    #[test]
+    fn register_range_error_has_a_note() {
+        for (src, expected) in [
+            ("not r8\n", "invalid register `8`\n  test.s:1:4  not r8\n"),
+            (
+                "add.w r0, r0, c9\n",
+                "invalid register `9`\n  test.s:1:14  add.w r0, r0, c9\n",
+            ),
+        ] {
+            let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+            crate::parse(&mut ctx, &crate::options::AssembleOptions::default());
+            let diags = ctx.take_diags();
+            assert_eq!(
+                diags[0].format(&ctx),
+                format!("{}  note: maximum register index is 7\n", expected)
+            );
+        }
+    }
+
+    /// the line and column of the first `not` on each line of `src` with the given tab width
+    fn not_columns(src: &str, tab_width: u32) -> Vec<(u32, u32)> {
+        let mut ctx = Context::new(Source::new(String::from("tabs.s"), src.to_string()));
+        ctx.set_tab_width(tab_width);
+        src.match_indices("not")
+            .map(|(idx, _)| ctx.line_info(Span::new(idx as u32, idx as u32 + 3)))
+            .collect()
+    }
+
+    #[test]
+    fn tab_width_columns() {
+        let src = "\tnot r0\nab\tnot r1\n\t\tnot r2\n";
+        assert_eq!(not_columns(src, 1), [(0, 1), (1, 3), (2, 2)]);
+        // tabs move to the next multiple of the tab width
+        assert_eq!(not_columns(src, 8), [(0, 8), (1, 8), (2, 16)]);
+        // a width of 0 is treated as 1
+        assert_eq!(not_columns(src, 0), not_columns(src, 1));
+    }
+
+    #[test]
+    fn json_shape() {
+        let ctx = Context::new(Source::new(
+            String::from("main.s"),
+            String::from("not r0\nmov r1, \"x\"\n"),
+        ));
+        let diag = Diagnostic::new(String::from("unexpected `\"x\"`"), Span::new(15, 18))
+            .with_note_at(String::from("while parsing `mov`"), Span::new(7, 10))
+            .with_note(String::from("strings are not operands"));
+        assert_eq!(
+            ctx.format_json(&[
+                diag,
+                Diagnostic::new_warning(String::from("w"), Span::new(0, 3))
+            ]),
+            concat!(
+                "[{\"severity\":\"error\",\"message\":\"unexpected `\\\"x\\\"`\",",
+                "\"file\":\"main.s\",\"start\":15,\"end\":18,\"line\":2,\"column\":8,",
+                "\"notes\":[{\"message\":\"while parsing `mov`\",",
+                "\"file\":\"main.s\",\"start\":7,\"end\":10,\"line\":2,\"column\":0},",
+                "{\"message\":\"strings are not operands\",",
+                "\"file\":\"main.s\",\"start\":null,\"end\":null,\"line\":null,\"column\":null}]},",
+                "{\"severity\":\"warning\",\"message\":\"w\",",
+                "\"file\":\"main.s\",\"start\":0,\"end\":3,\"line\":1,\"column\":0,\"notes\":[]}]"
+            )
+        );
     }
 }
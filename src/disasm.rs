@@ -0,0 +1,321 @@
+use crate::{
+    codegen::{bit_ops, math_ops, opcode, shift_ops},
+    instruction::{
+        InstructionKind, MemoryOperand, OpSize, RegSelector, SetRegSelector, SetSelector,
+        ShiftAmount, SwizzleRegSelector, SwizzleSelector, MAX_REG_IDX,
+    },
+    lexer::Span,
+    object::Object,
+};
+
+/// the number of bytes in every instruction
+const INST_LEN: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeError {
+    #[error("expected {INST_LEN} bytes for an instruction, found {0}")]
+    Truncated(usize),
+    #[error("invalid instruction {0:04X}")]
+    Invalid(u16),
+}
+
+/// decodes every instruction in little endian `bytes`
+pub fn disassemble(bytes: &[u8]) -> Result<Vec<InstructionKind>, DecodeError> {
+    Disassembler::new(bytes).collect()
+}
+
+/// decodes the instructions in little endian `bytes` one at a time, for large programs
+/// that do not need to be decoded all at once. after an error, no more instructions are
+/// decoded.
+#[derive(Debug, Clone)]
+pub struct Disassembler<'a> {
+    bytes: &'a [u8],
+    /// the offset of the next instruction in `bytes`
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            offset: 0,
+            failed: false,
+        }
+    }
+
+    /// the offset in bytes of the next instruction to decode. after an error, this is
+    /// the offset of the instruction that could not be decoded.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl Iterator for Disassembler<'_> {
+    type Item = Result<InstructionKind, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.bytes[self.offset..];
+        if self.failed || rest.is_empty() {
+            return None;
+        }
+        match disassemble_one(rest) {
+            Ok((inst, len)) => {
+                self.offset += len;
+                Some(Ok(inst))
+            }
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for Disassembler<'_> {}
+
+/// decodes the first instruction in little endian `bytes`, returning it and the number
+/// of bytes it was encoded in
+pub fn disassemble_one(bytes: &[u8]) -> Result<(InstructionKind, usize), DecodeError> {
+    let Some(&[low, high]) = bytes.get(..INST_LEN) else {
+        return Err(DecodeError::Truncated(bytes.len()));
+    };
+    let inst = decode(u16::from_le_bytes([low, high]))?;
+    Ok((inst, INST_LEN))
+}
+
+/// decodes the code of `object` into assembly, with one instruction on each line. if
+/// `comments` is set and the object includes comments, each comment is written before
+/// its instruction, with every line starting with `#`.
+pub fn disassemble_object(object: &Object, comments: bool) -> Result<String, DecodeError> {
+    let mut out = String::new();
+    for (idx, &word) in object.code.iter().enumerate() {
+        let comment = object
+            .comments
+            .as_ref()
+            .filter(|_| comments)
+            .and_then(|comments| comments.get(idx)?.as_deref());
+        if let Some(comment) = comment {
+            for line in comment.lines() {
+                if line.is_empty() {
+                    out.push_str("#\n");
+                } else {
+                    out.push_str(&format!("# {}\n", line));
+                }
+            }
+        }
+        out.push_str(&format!("{}\n", decode(word)?));
+    }
+    Ok(out)
+}
+
+/// decodes a single instruction word. every decoded register and selector has a
+/// dummy span.
+pub fn decode(word: u16) -> Result<InstructionKind, DecodeError> {
+    use InstructionKind::*;
+
+    let dst = (word >> 12) as u8;
+    let src = ((word >> 8) & 0b1111) as u8;
+    let extra = ((word >> 4) & 0b1111) as u8;
+    let op = (word & 0b1111) as u8;
+
+    let inst = match op {
+        opcode::MOVE => {
+            // the extra bits are set for every element that is *not* moved
+            let selector = SetSelector::from_bits(extra ^ 0b1111, Span::DUMMY);
+            Move {
+                src: set_reg(src, selector),
+                dst: set_reg(dst, selector),
+            }
+        }
+        opcode::SWIZZLE => {
+            let selector = SwizzleSelector::from_bits((src << 4) | extra, Span::DUMMY);
+            Swizzle {
+                reg: SwizzleRegSelector::new(reg(dst), selector, Span::DUMMY),
+            }
+        }
+        opcode::LOAD | opcode::STORE => {
+            // the element count is encoded as `4 - count` and always starts at `x`
+            let count = 4 - (extra >> 2);
+            let selector = SetSelector::from_bits((1 << count) - 1, Span::DUMMY);
+            let scatter = extra & 0b0010 != 0;
+            let increment = extra & 0b0001 != 0;
+            let mem = MemoryOperand::new(reg(src), scatter, increment, Span::DUMMY);
+            let value = set_reg(dst, selector);
+            if op == opcode::LOAD {
+                Load { mem, dst: value }
+            } else {
+                Store { src: value, mem }
+            }
+        }
+        opcode::MATH8 | opcode::MATH16 => {
+            let size = if op == opcode::MATH8 {
+                OpSize::Byte
+            } else {
+                OpSize::Word
+            };
+            let (src, dst) = (reg(src), reg(dst));
+            match extra {
+                math_ops::ADD => Add { size, src, dst },
+                math_ops::SUB => Sub { size, src, dst },
+                math_ops::SUBREV => SubRev { size, src, dst },
+                math_ops::CMPEQ => CmpEq { size, src, dst },
+                math_ops::CMPNEQ => CmpNeq { size, src, dst },
+                math_ops::ADD_SAT => AddSaturate { size, src, dst },
+                math_ops::SUB_SAT => SubSaturate { size, src, dst },
+                math_ops::SUBREV_SAT => SubRevSaturate { size, src, dst },
+                _ => return Err(DecodeError::Invalid(word)),
+            }
+        }
+        opcode::SHIFT8 | opcode::SHIFT16 => {
+            let size = if op == opcode::SHIFT8 {
+                OpSize::Byte
+            } else {
+                OpSize::Word
+            };
+            let dst = reg(dst);
+            // the high bit selects a constant amount instead of a register
+            let amount = if extra & 0b1000 != 0 {
+                ShiftAmount::Const(src, Span::DUMMY)
+            } else {
+                ShiftAmount::Register(reg(src))
+            };
+            match extra & 0b0111 {
+                shift_ops::LEFT_SHIFT => ShiftLeft { size, dst, amount },
+                shift_ops::LOGICAL_RIGHT_SHIFT => ShiftRightLogical { size, dst, amount },
+                shift_ops::ARITHMETIC_RIGHT_SHIFT => ShiftRightArithmetic { size, dst, amount },
+                shift_ops::ROTATE_LEFT => RotateLeft { size, dst, amount },
+                shift_ops::ROTATE_RIGHT => RotateRight { size, dst, amount },
+                _ => return Err(DecodeError::Invalid(word)),
+            }
+        }
+        opcode::BITOP => {
+            let (src, dst) = (reg(src), reg(dst));
+            match extra {
+                bit_ops::AND => BitAnd { src, dst },
+                bit_ops::OR => BitOr { src, dst },
+                bit_ops::XOR => BitXor { src, dst },
+                bit_ops::NAND => BitNand { src, dst },
+                bit_ops::NOR => BitNor { src, dst },
+                bit_ops::XNOR => BitXnor { src, dst },
+                bit_ops::NOT_DST => UnaryBitNot { dst },
+                bit_ops::NOT_SRC => BitNot { src, dst },
+                _ => return Err(DecodeError::Invalid(word)),
+            }
+        }
+        _ => return Err(DecodeError::Invalid(word)),
+    };
+    Ok(inst)
+}
+
+fn reg(idx: u8) -> RegSelector {
+    if idx <= MAX_REG_IDX {
+        RegSelector::new_const(idx, Span::DUMMY)
+    } else {
+        RegSelector::new_gpr(idx - (MAX_REG_IDX + 1), Span::DUMMY)
+    }
+}
+
+fn set_reg(idx: u8, selector: SetSelector) -> SetRegSelector {
+    SetRegSelector::new(reg(idx), selector, Span::DUMMY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diag::Context, options::AssembleOptions, source::Source};
+
+    #[test]
+    fn disassemble_one_reports_length() {
+        // every instruction is a single word, so only the first 2 bytes are decoded
+        let (inst, len) = disassemble_one(&[0x09, 0x81, 0xFF, 0xFF]).unwrap();
+        assert_eq!(inst.to_string(), "add.w r0, r0, c1");
+        assert_eq!(len, 2);
+        let (inst, len) = disassemble_one(&[0x3C, 0x80]).unwrap();
+        assert_eq!(inst.to_string(), "not r0");
+        assert_eq!(len, 2);
+    }
+
+    #[test]
+    fn disassemble_one_truncated() {
+        assert!(matches!(
+            disassemble_one(&[]),
+            Err(DecodeError::Truncated(0))
+        ));
+        assert!(matches!(
+            disassemble_one(&[0x09]),
+            Err(DecodeError::Truncated(1))
+        ));
+    }
+
+    #[test]
+    fn disassemble_one_invalid() {
+        // opcode 0 is not implemented
+        assert!(matches!(
+            disassemble_one(&[0x00, 0x00]),
+            Err(DecodeError::Invalid(0x0000))
+        ));
+    }
+
+    fn object(src: &str) -> (Context, Object) {
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let opts = AssembleOptions::default();
+        let assembled = crate::assemble(&mut ctx, &opts).expect("program should assemble");
+        let object = Object::new(&ctx, &assembled, opts.endianness);
+        (ctx, object)
+    }
+
+    #[test]
+    fn disassemble_object_comments() {
+        let src = "# inverts r0\n#\n# twice\nnot r0\nadd.w r0, r0, c1\n";
+        let (ctx, object) = object(src);
+        let plain = "not r0\nadd.w r0, r0, c1\n";
+        // without comments in the object, there is nothing to write
+        assert_eq!(disassemble_object(&object, true).unwrap(), plain);
+
+        let object = Object::from_bytes(&object.with_comments(&ctx).to_bytes().unwrap()).unwrap();
+        assert_eq!(disassemble_object(&object, false).unwrap(), plain);
+        assert_eq!(
+            disassemble_object(&object, true).unwrap(),
+            "# inverts r0\n#\n# twice\nnot r0\nadd.w r0, r0, c1\n"
+        );
+    }
+
+    #[test]
+    fn disassembler_decodes_every_instruction() {
+        let mut disassembler = Disassembler::new(&[0x3C, 0x80, 0x09, 0x81, 0x6C, 0x88]);
+        let mut texts = Vec::new();
+        while let Some(inst) = disassembler.next() {
+            texts.push(inst.unwrap().to_string());
+            assert_eq!(disassembler.offset(), texts.len() * INST_LEN);
+        }
+        assert_eq!(texts, ["not r0", "add.w r0, r0, c1", "xor r0, r0"]);
+        // the end stays the end
+        assert!(disassembler.next().is_none());
+        assert_eq!(disassembler.offset(), 6);
+
+        assert!(Disassembler::new(&[]).next().is_none());
+    }
+
+    #[test]
+    fn disassembler_stops_at_an_error() {
+        let mut disassembler = Disassembler::new(&[0x3C, 0x80, 0x00, 0x00, 0x3C, 0x80]);
+        assert_eq!(disassembler.next().unwrap().unwrap().to_string(), "not r0");
+        assert!(matches!(
+            disassembler.next(),
+            Some(Err(DecodeError::Invalid(0x0000)))
+        ));
+        // the offset points at the instruction that failed, and nothing else is decoded
+        assert_eq!(disassembler.offset(), 2);
+        assert!(disassembler.next().is_none());
+
+        let mut disassembler = Disassembler::new(&[0x3C, 0x80, 0x09]);
+        assert!(disassembler.next().unwrap().is_ok());
+        assert!(matches!(
+            disassembler.next(),
+            Some(Err(DecodeError::Truncated(1)))
+        ));
+        assert_eq!(disassembler.offset(), 2);
+        assert!(disassembler.next().is_none());
+    }
+}
@@ -0,0 +1,111 @@
+//! associates comments with the instructions that follow them, for tools that generate
+//! annotated listings. comments are skipped by the lexer, so this works on the tokens
+//! from the reader.
+
+use std::collections::HashMap;
+
+use crate::{
+    instruction::Instruction,
+    lexer::Span,
+    reader::{Reader, TokenKind},
+};
+
+/// gets the comment directly before each instruction of `program`, which was parsed
+/// from `src`. a comment is every line that only contains a comment, up to the line of
+/// the instruction, with the `#` and a single following space removed from each line.
+/// an empty line between the comment and the instruction separates them, and comments
+/// after code on the same line are not included.
+///
+/// instructions that were not parsed from `src`, such as ones from included files, have
+/// no comment.
+pub fn doc_comments(src: &str, program: &[Instruction]) -> Vec<Option<String>> {
+    let comments = comment_blocks(src);
+    program
+        .iter()
+        .map(|inst| {
+            let span = inst.span();
+            if span.is_dummy() || span.file() != 0 {
+                return None;
+            }
+            comments.get(&span.low()).cloned()
+        })
+        .collect()
+}
+
+/// finds every block of comment lines in `src`, keyed by the offset of the first token
+/// after it
+pub(crate) fn comment_blocks(src: &str) -> HashMap<u32, String> {
+    let mut blocks = HashMap::new();
+    let mut reader = Reader::new(src);
+    let mut pos = 0;
+    let mut pending: Vec<&str> = Vec::new();
+    let (mut line_has_content, mut line_has_comment) = (false, false);
+    loop {
+        let token = reader.next_token();
+        let span = Span::from_offsets(pos, pos + token.len(), 0);
+        pos += token.len();
+
+        match token.kind() {
+            TokenKind::EoF => break,
+            TokenKind::Whitespace => {}
+            TokenKind::Comment => {
+                if !line_has_content {
+                    let text = span.span_text(src).trim_end();
+                    let text = text.strip_prefix('#').unwrap_or(text);
+                    pending.push(text.strip_prefix(' ').unwrap_or(text));
+                    line_has_comment = true;
+                }
+            }
+            TokenKind::Newline => {
+                // an empty line separates a comment from the code after it
+                if !line_has_content && !line_has_comment {
+                    pending.clear();
+                }
+                line_has_content = false;
+                line_has_comment = false;
+            }
+            _ => {
+                if !line_has_content && !pending.is_empty() {
+                    blocks.insert(span.low(), pending.join("\n"));
+                    pending.clear();
+                }
+                line_has_content = true;
+            }
+        }
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diag::Context, options::AssembleOptions, source::Source};
+
+    fn comments(src: &str) -> Vec<Option<String>> {
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let program = crate::parse(&mut ctx, &AssembleOptions::default());
+        assert!(!ctx.had_errs(), "{:?}", ctx.take_diags());
+        doc_comments(src, program.instructions())
+    }
+
+    #[test]
+    fn comment_before_instruction() {
+        let src = "# inverts r0\nnot r0\n#   keeps indentation\n#second line\nnot r1\n";
+        assert_eq!(
+            comments(src),
+            [
+                Some(String::from("inverts r0")),
+                Some(String::from("  keeps indentation\nsecond line")),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_that_are_not_docs() {
+        let src = "# separated\n\nnot r0\nnot r1 # trailing\nnot r2\n    # indented\n    not r3\n";
+        assert_eq!(
+            comments(src),
+            [None, None, None, Some(String::from("indented"))]
+        );
+    }
+}
@@ -0,0 +1,535 @@
+use crate::{
+    consts::ConstRegs,
+    instruction::{
+        Instruction, InstructionKind, MemoryOperand, OpSize, RegSelector, SetSelector, ShiftAmount,
+        MAX_REG_IDX,
+    },
+    lexer::Span,
+};
+
+/// the number of registers, including both const and general purpose registers
+const REG_COUNT: usize = (MAX_REG_IDX as usize + 1) * 2;
+
+/// the values of every register
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegFile {
+    regs: [[u16; 4]; REG_COUNT],
+}
+
+impl RegFile {
+    /// creates a register file with the const registers set to the values in `consts`.
+    /// const registers that were not set and every general purpose register are 0.
+    pub fn with_consts(consts: &ConstRegs) -> Self {
+        let mut regs = Self::default();
+        for (idx, value) in consts.iter() {
+            regs.set(RegSelector::new_const(idx, Span::DUMMY), value);
+        }
+        regs
+    }
+
+    pub fn get(&self, reg: RegSelector) -> [u16; 4] {
+        self.regs[reg.idx() as usize]
+    }
+
+    pub fn set(&mut self, reg: RegSelector, value: [u16; 4]) {
+        self.regs[reg.idx() as usize] = value;
+    }
+}
+
+/// flat memory of 16 bit words, addressed by word
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Memory {
+    words: Vec<u16>,
+}
+
+impl Memory {
+    /// creates a memory of `len` words that are all 0
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len],
+        }
+    }
+
+    /// creates a memory containing `words`, starting at address 0
+    pub fn from_words(words: Vec<u16>) -> Self {
+        Self { words }
+    }
+
+    pub fn words(&self) -> &[u16] {
+        self.words.as_slice()
+    }
+
+    pub fn read(&self, addr: usize) -> Result<u16, EvalError> {
+        self.words
+            .get(addr)
+            .copied()
+            .ok_or(EvalError::OutOfBounds(addr))
+    }
+
+    pub fn write(&mut self, addr: usize, value: u16) -> Result<(), EvalError> {
+        let word = self
+            .words
+            .get_mut(addr)
+            .ok_or(EvalError::OutOfBounds(addr))?;
+        *word = value;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum EvalError {
+    #[error("`{0}` writes to `ri`, which jumps")]
+    Jump(InstructionKind),
+    #[error("address {0:#06X} is out of bounds")]
+    OutOfBounds(usize),
+}
+
+/// runs straight-line code starting with the registers in `initial`, returning the
+/// registers after the last instruction. instructions that write to `ri` jump, which
+/// is an error, including incrementing `ri` in a memory operand.
+pub fn interpret(
+    program: &[Instruction],
+    initial: RegFile,
+    mem: &mut Memory,
+) -> Result<RegFile, EvalError> {
+    program.iter().try_fold(initial, |regs, inst| {
+        if inst.kind().writes_ri() {
+            return Err(EvalError::Jump(*inst.kind()));
+        }
+        eval(inst.kind(), &regs, mem)
+    })
+}
+
+/// evaluates a single instruction, returning the registers after it runs.
+///
+/// math operates on each element of the given size separately, so a byte operation
+/// does not carry between the bytes of a word. results that do not fit wrap, and
+/// saturating operations clamp to the unsigned range of the element instead.
+/// compares set every bit of an element to 1 if the compare is true, and to 0
+/// otherwise. shifts by at least the size of an element shift out every bit, and
+/// rotates use the amount modulo the size. a register shift amount shifts each element
+/// by the corresponding element of the register.
+///
+/// loads and stores move the selected elements to or from consecutive words starting at
+/// the `x` element of the address, or to or from the address in each element when they
+/// are scattered. incrementing the address adds the number of elements moved to `x`, or
+/// adds 1 to each element when scattered.
+pub fn eval(
+    kind: &InstructionKind,
+    regs: &RegFile,
+    mem: &mut Memory,
+) -> Result<RegFile, EvalError> {
+    use InstructionKind::*;
+
+    let mut regs = regs.clone();
+    match *kind {
+        Move { src, dst } => {
+            let (src_val, mut dst_val) = (regs.get(src.reg()), regs.get(dst.reg()));
+            for (idx, elem) in dst_val.iter_mut().enumerate() {
+                if dst.selector().bits() & (1 << idx) != 0 {
+                    *elem = src_val[idx];
+                }
+            }
+            regs.set(dst.reg(), dst_val);
+        }
+        Swizzle { reg } => {
+            let old = regs.get(reg.reg());
+            let bits = reg.selector().bits();
+            // each element is selected by 2 bits, starting with `x` in the low bits
+            let new = std::array::from_fn(|idx| old[((bits >> (idx * 2)) & 0b11) as usize]);
+            regs.set(reg.reg(), new);
+        }
+        Load { mem: operand, dst } => {
+            let count = elem_count(dst.selector());
+            let addrs = mem_addrs(&mut regs, operand, count);
+            let mut value = regs.get(dst.reg());
+            for (elem, addr) in value.iter_mut().zip(addrs).take(count) {
+                *elem = mem.read(addr)?;
+            }
+            regs.set(dst.reg(), value);
+        }
+        Store { src, mem: operand } => {
+            let count = elem_count(src.selector());
+            let value = regs.get(src.reg());
+            let addrs = mem_addrs(&mut regs, operand, count);
+            for (elem, addr) in value.into_iter().zip(addrs).take(count) {
+                mem.write(addr, elem)?;
+            }
+        }
+
+        Add { size, src, dst } => math(&mut regs, size, src, dst, |s, d, _| s + d),
+        Sub { size, src, dst } => math(&mut regs, size, src, dst, |s, d, _| s.wrapping_sub(d)),
+        SubRev { size, src, dst } => math(&mut regs, size, src, dst, |s, d, _| d.wrapping_sub(s)),
+        CmpEq { size, src, dst } => math(
+            &mut regs,
+            size,
+            src,
+            dst,
+            |s, d, mask| if s == d { mask } else { 0 },
+        ),
+        CmpNeq { size, src, dst } => math(
+            &mut regs,
+            size,
+            src,
+            dst,
+            |s, d, mask| if s != d { mask } else { 0 },
+        ),
+        AddSaturate { size, src, dst } => {
+            math(&mut regs, size, src, dst, |s, d, mask| (s + d).min(mask))
+        }
+        SubSaturate { size, src, dst } => {
+            math(&mut regs, size, src, dst, |s, d, _| s.saturating_sub(d))
+        }
+        SubRevSaturate { size, src, dst } => {
+            math(&mut regs, size, src, dst, |s, d, _| d.saturating_sub(s))
+        }
+
+        ShiftLeft { size, dst, amount } => shift(&mut regs, size, dst, amount, |d, amt, bits| {
+            if amt >= bits {
+                0
+            } else {
+                d << amt
+            }
+        }),
+        ShiftRightLogical { size, dst, amount } => {
+            shift(&mut regs, size, dst, amount, |d, amt, bits| {
+                if amt >= bits {
+                    0
+                } else {
+                    d >> amt
+                }
+            })
+        }
+        ShiftRightArithmetic { size, dst, amount } => {
+            shift(&mut regs, size, dst, amount, |d, amt, bits| {
+                // sign extend to 32 bits so that the sign is shifted in
+                let extended = ((d << (32 - bits)) as i32) >> (32 - bits);
+                (extended >> amt.min(bits - 1)) as u32
+            })
+        }
+        RotateLeft { size, dst, amount } => shift(&mut regs, size, dst, amount, |d, amt, bits| {
+            let amt = amt % bits;
+            (d << amt) | (d >> ((bits - amt) % bits))
+        }),
+        RotateRight { size, dst, amount } => shift(&mut regs, size, dst, amount, |d, amt, bits| {
+            let amt = amt % bits;
+            (d >> amt) | (d << ((bits - amt) % bits))
+        }),
+
+        BitAnd { src, dst } => bitwise(&mut regs, src, dst, |s, d| s & d),
+        BitOr { src, dst } => bitwise(&mut regs, src, dst, |s, d| s | d),
+        BitXor { src, dst } => bitwise(&mut regs, src, dst, |s, d| s ^ d),
+        BitNand { src, dst } => bitwise(&mut regs, src, dst, |s, d| !(s & d)),
+        BitNor { src, dst } => bitwise(&mut regs, src, dst, |s, d| !(s | d)),
+        BitXnor { src, dst } => bitwise(&mut regs, src, dst, |s, d| !(s ^ d)),
+        UnaryBitNot { dst } => bitwise(&mut regs, dst, dst, |_, d| !d),
+        BitNot { src, dst } => bitwise(&mut regs, src, dst, |s, _| !s),
+    }
+    Ok(regs)
+}
+
+/// the number of elements moved by a load or store, which always start at `x`
+fn elem_count(selector: SetSelector) -> usize {
+    selector.bits().count_ones() as usize
+}
+
+/// gets the address of each element of a memory operand that moves `count` elements,
+/// and increments the address register if the operand is incremented
+fn mem_addrs(regs: &mut RegFile, operand: MemoryOperand, count: usize) -> [usize; 4] {
+    let mut reg = regs.get(operand.reg());
+    let addrs = if operand.scatter() {
+        reg.map(|addr| addr as usize)
+    } else {
+        std::array::from_fn(|idx| reg[0] as usize + idx)
+    };
+    if operand.increment() {
+        if operand.scatter() {
+            reg = reg.map(|addr| addr.wrapping_add(1));
+        } else {
+            reg[0] = reg[0].wrapping_add(count as u16);
+        }
+        regs.set(operand.reg(), reg);
+    }
+    addrs
+}
+
+/// sets `dst` to the result of `op` on each pair of elements of `src` and `dst`
+fn math(
+    regs: &mut RegFile,
+    size: OpSize,
+    src: RegSelector,
+    dst: RegSelector,
+    op: impl Fn(u32, u32, u32) -> u32,
+) {
+    let result = elementwise(size, regs.get(src), regs.get(dst), op);
+    regs.set(dst, result);
+}
+
+/// sets `dst` to the result of `op` on each element of `dst` and its shift amount.
+/// `op` is given the element, the amount, and the number of bits in the element.
+fn shift(
+    regs: &mut RegFile,
+    size: OpSize,
+    dst: RegSelector,
+    amount: ShiftAmount,
+    op: impl Fn(u32, u32, u32) -> u32,
+) {
+    let amounts = match amount {
+        ShiftAmount::Register(reg) => regs.get(reg),
+        // constant amounts apply to every element
+        ShiftAmount::Const(val, _) => {
+            let word = (0..16)
+                .step_by(size.bits() as usize)
+                .fold(0, |word, shift| word | (val as u16) << shift);
+            [word; 4]
+        }
+    };
+    let bits = size.bits() as u32;
+    let result = elementwise(size, regs.get(dst), amounts, |d, amt, _| op(d, amt, bits));
+    regs.set(dst, result);
+}
+
+/// sets `dst` to the result of `op` on each word of `src` and `dst`
+fn bitwise(regs: &mut RegFile, src: RegSelector, dst: RegSelector, op: impl Fn(u16, u16) -> u16) {
+    let (src_val, dst_val) = (regs.get(src), regs.get(dst));
+    let result = std::array::from_fn(|idx| op(src_val[idx], dst_val[idx]));
+    regs.set(dst, result);
+}
+
+/// applies `op` to each pair of elements of `size` in `a` and `b`. `op` is given the
+/// elements zero extended and the mask of the bits in an element, and its result is
+/// truncated to the element.
+fn elementwise(
+    size: OpSize,
+    a: [u16; 4],
+    b: [u16; 4],
+    op: impl Fn(u32, u32, u32) -> u32,
+) -> [u16; 4] {
+    let mask = (1_u32 << size.bits()) - 1;
+    let mut out = [0; 4];
+    for (out, (a, b)) in out.iter_mut().zip(a.into_iter().zip(b)) {
+        // each word contains `16 / size` elements, starting with the low bits
+        for shift in (0..16).step_by(size.bits() as usize) {
+            let a = (a as u32 >> shift) & mask;
+            let b = (b as u32 >> shift) & mask;
+            *out |= ((op(a, b, mask) & mask) << shift) as u16;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{diag::Context, options::AssembleOptions, source::Source};
+
+    fn gpr(idx: u8) -> RegSelector {
+        RegSelector::new_gpr(idx, Span::DUMMY)
+    }
+
+    fn konst(idx: u8) -> RegSelector {
+        RegSelector::new_const(idx, Span::DUMMY)
+    }
+
+    /// evaluates a single instruction written in assembly
+    fn eval_one(src: &str, regs: &RegFile) -> RegFile {
+        let inst = crate::parse_instruction(src, &AssembleOptions::default())
+            .expect("instruction should parse");
+        eval(inst.kind(), regs, &mut Memory::new(0)).unwrap()
+    }
+
+    #[test]
+    fn add_wraps_at_the_element_size() {
+        let mut regs = RegFile::default();
+        regs.set(gpr(0), [0x12FF, 0xFFFF, 0x00FF, 0x0000]);
+        regs.set(konst(1), [0x0001, 0x0001, 0x0101, 0x0000]);
+
+        // 0xFF + 1 wraps to 0 without carrying into the next byte
+        let bytes = eval_one("add.b r0, r0, c1", &regs);
+        assert_eq!(bytes.get(gpr(0)), [0x1200, 0xFF00, 0x0100, 0x0000]);
+
+        let words = eval_one("add.w r0, r0, c1", &regs);
+        assert_eq!(words.get(gpr(0)), [0x1300, 0x0000, 0x0200, 0x0000]);
+    }
+
+    #[test]
+    fn sub_wraps_at_the_element_size() {
+        let mut regs = RegFile::default();
+        regs.set(gpr(0), [0x0100, 0x0000, 0, 0]);
+        regs.set(konst(1), [0x0001, 0x0001, 0, 0]);
+
+        // `sub dst, dst, src` is dst - src
+        let bytes = eval_one("sub.b r0, r0, c1", &regs);
+        assert_eq!(bytes.get(gpr(0)), [0x01FF, 0x00FF, 0, 0]);
+
+        let words = eval_one("sub.w r0, r0, c1", &regs);
+        assert_eq!(words.get(gpr(0)), [0x00FF, 0xFFFF, 0, 0]);
+    }
+
+    #[test]
+    fn compares_set_every_bit_of_true_lanes() {
+        let mut regs = RegFile::default();
+        regs.set(gpr(0), [0x1234, 0x1200, 0x0000, 0xABCD]);
+        regs.set(konst(1), [0x1234, 0x1234, 0x0000, 0xABCE]);
+
+        let words = eval_one("cmpeq.w r0, r0, c1", &regs);
+        assert_eq!(words.get(gpr(0)), [0xFFFF, 0x0000, 0xFFFF, 0x0000]);
+
+        let bytes = eval_one("cmpeq.b r0, r0, c1", &regs);
+        assert_eq!(bytes.get(gpr(0)), [0xFFFF, 0xFF00, 0xFFFF, 0xFF00]);
+
+        let neq = eval_one("cmpneq.b r0, r0, c1", &regs);
+        assert_eq!(neq.get(gpr(0)), [0x0000, 0x00FF, 0x0000, 0x00FF]);
+    }
+
+    /// parses `src` and runs it with the const registers it sets and every other register 0
+    fn run(src: &str) -> Result<RegFile, EvalError> {
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let program = crate::parse(&mut ctx, &AssembleOptions::default());
+        assert!(!ctx.had_errs(), "{:?}", ctx.take_diags());
+        let initial = RegFile::with_consts(program.consts());
+        interpret(program.instructions(), initial, &mut Memory::new(0))
+    }
+
+    #[test]
+    fn interpret_arithmetic() {
+        let regs = run(".const c1, 1, 2, 3, 4\n\
+             mov r0.xyzw, c1.xyzw\n\
+             add.w r0, r0, c1\n\
+             lsl.w r0, 2\n\
+             sub.w r0, r0, c1\n\
+             swizzle r0.wzyx\n")
+        .unwrap();
+        assert_eq!(regs.get(gpr(0)), [28, 21, 14, 7]);
+        assert_eq!(regs.get(konst(1)), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn interpret_bitops() {
+        let regs = run(".const c0, 0x00FF, 0x0F0F, 0xFFFF, 0\n\
+             zero r1\n\
+             not r1\n\
+             xor r1, c0\n\
+             mov r2.xy, c0.xy\n\
+             or r2, r1\n")
+        .unwrap();
+        assert_eq!(regs.get(gpr(1)), [0xFF00, 0xF0F0, 0x0000, 0xFFFF]);
+        assert_eq!(regs.get(gpr(2)), [0xFFFF, 0xFFFF, 0x0000, 0xFFFF]);
+    }
+
+    #[test]
+    fn interpret_rejects_writes_to_ri() {
+        let err = run(".const c1, 1, 1, 1, 1\nadd.w r0, r0, c1\nadd.w ri, ri, c1\n").unwrap_err();
+        assert!(matches!(err, EvalError::Jump(InstructionKind::Add { .. })));
+        // reading ri does not jump
+        let regs = run("add.w r0, r0, ri\n").unwrap();
+        assert_eq!(regs.get(gpr(0)), [0, 0, 0, 0]);
+    }
+
+    /// evaluates a single instruction written in assembly with `mem` as the memory
+    fn eval_mem(src: &str, regs: &RegFile, mem: &mut Memory) -> Result<RegFile, EvalError> {
+        let inst = crate::parse_instruction(src, &AssembleOptions::default())
+            .expect("instruction should parse");
+        eval(inst.kind(), regs, mem)
+    }
+
+    #[test]
+    fn simple_loads_and_stores() {
+        let mut mem = Memory::from_words(vec![10, 11, 12, 13, 14, 15]);
+        let mut regs = RegFile::default();
+        regs.set(gpr(1), [2, 0, 0, 0]);
+        regs.set(gpr(0), [1, 2, 3, 4]);
+
+        let loaded = eval_mem("mov r0.xy, [r1]", &regs, &mut mem).unwrap();
+        assert_eq!(loaded.get(gpr(0)), [12, 13, 3, 4]);
+        assert_eq!(loaded.get(gpr(1)), [2, 0, 0, 0]);
+
+        let stored = eval_mem("mov [r1], r0.xyz", &regs, &mut mem).unwrap();
+        assert_eq!(stored, regs);
+        assert_eq!(mem.words(), [10, 11, 1, 2, 3, 15]);
+    }
+
+    #[test]
+    fn scattered_loads_and_stores() {
+        let mut mem = Memory::from_words(vec![10, 11, 12, 13, 14, 15]);
+        let mut regs = RegFile::default();
+        regs.set(gpr(1), [5, 0, 3, 1]);
+
+        let loaded = eval_mem("mov r0.xyzw, [r1.*]", &regs, &mut mem).unwrap();
+        assert_eq!(loaded.get(gpr(0)), [15, 10, 13, 11]);
+
+        regs.set(gpr(0), [1, 2, 3, 4]);
+        eval_mem("mov [r1.*], r0.xyzw", &regs, &mut mem).unwrap();
+        assert_eq!(mem.words(), [2, 4, 12, 3, 14, 1]);
+    }
+
+    #[test]
+    fn incremented_addresses() {
+        let mut mem = Memory::new(8);
+        let mut regs = RegFile::default();
+        regs.set(gpr(1), [2, 7, 7, 7]);
+        regs.set(gpr(0), [1, 2, 3, 4]);
+
+        // the address is incremented by the number of elements moved
+        let stored = eval_mem("mov [r1]+, r0.xyz", &regs, &mut mem).unwrap();
+        assert_eq!(stored.get(gpr(1)), [5, 7, 7, 7]);
+        assert_eq!(mem.words(), [0, 0, 1, 2, 3, 0, 0, 0]);
+
+        // scattered addresses are each incremented by 1
+        let loaded = eval_mem("mov r2.xyzw, [r1.*]+", &regs, &mut mem).unwrap();
+        assert_eq!(loaded.get(gpr(1)), [3, 8, 8, 8]);
+        assert_eq!(loaded.get(gpr(2)), [1, 0, 0, 0]);
+
+        // incrementing ri writes to it, which jumps
+        let err = run("mov r0.x, [ri]+\n").unwrap_err();
+        assert!(matches!(err, EvalError::Jump(InstructionKind::Load { .. })));
+    }
+
+    #[test]
+    fn out_of_bounds_accesses() {
+        let mut mem = Memory::new(4);
+        let mut regs = RegFile::default();
+        regs.set(gpr(1), [3, 0, 0, 0]);
+        let err = eval_mem("mov r0.xy, [r1]", &regs, &mut mem).unwrap_err();
+        assert!(matches!(err, EvalError::OutOfBounds(4)));
+        let err = eval_mem("mov [r1.*], r0.xyzw", &regs, &mut Memory::new(0)).unwrap_err();
+        assert!(matches!(err, EvalError::OutOfBounds(3)));
+    }
+
+    #[test]
+    fn const_registers_start_with_their_values() {
+        let mut consts = ConstRegs::default();
+        consts.set(2, [1, 2, 3, 4]);
+        let regs = RegFile::with_consts(&consts);
+        assert_eq!(regs.get(konst(2)), [1, 2, 3, 4]);
+        // unset const registers and every general purpose register are 0
+        assert_eq!(regs.get(konst(0)), [0; 4]);
+        assert!((0..=MAX_REG_IDX).all(|idx| regs.get(gpr(idx)) == [0; 4]));
+    }
+
+    #[test]
+    fn object_consts_drive_the_same_computation() {
+        let src = ".const c3, 5, 6, 7, 0xFFFF\nadd.w r0, r0, c3\nadd.b r0, r0, c3\n";
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let opts = AssembleOptions::default();
+        let assembled = crate::assemble(&mut ctx, &opts).unwrap();
+        let object = crate::object::Object::new(&ctx, &assembled, opts.endianness);
+        let read = crate::object::Object::from_bytes(&object.to_bytes().unwrap()).unwrap();
+
+        let program = crate::parse(&mut ctx, &opts);
+        let from_source = interpret(
+            program.instructions(),
+            RegFile::with_consts(program.consts()),
+            &mut Memory::new(0),
+        )
+        .unwrap();
+        let from_object = interpret(
+            program.instructions(),
+            RegFile::with_consts(&read.consts),
+            &mut Memory::new(0),
+        )
+        .unwrap();
+        assert_eq!(from_source, from_object);
+        assert_eq!(from_source.get(gpr(0)), [10, 12, 14, 0xFEFE]);
+    }
+}
@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 use crate::lexer::Span;
 
@@ -20,6 +20,32 @@ impl Instruction {
     pub fn span(&self) -> Span {
         self.span
     }
+
+    /// gets every register operand of the instruction and how it is used
+    pub fn operands(&self) -> Vec<(RegSelector, OperandRole)> {
+        self.kind.operands()
+    }
+}
+
+/// how an instruction uses one of its register operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandRole {
+    /// the register is only read
+    Src,
+    /// the register is only written
+    Dst,
+    /// the register is read and then written
+    SrcDst,
+}
+
+impl OperandRole {
+    pub fn reads(&self) -> bool {
+        matches!(self, OperandRole::Src | OperandRole::SrcDst)
+    }
+
+    pub fn writes(&self) -> bool {
+        matches!(self, OperandRole::Dst | OperandRole::SrcDst)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -40,31 +66,31 @@ pub enum InstructionKind {
         mem: MemoryOperand,
     },
 
-    /// dst = src + dst
+    /// dst = src + dst, wrapping if the result does not fit in the size
     Add {
         size: OpSize,
         src: RegSelector,
         dst: RegSelector,
     },
-    /// dst = src - dst
+    /// dst = src - dst, wrapping if the result does not fit in the size
     Sub {
         size: OpSize,
         src: RegSelector,
         dst: RegSelector,
     },
-    /// dst = dst - src
+    /// dst = dst - src, wrapping if the result does not fit in the size
     SubRev {
         size: OpSize,
         src: RegSelector,
         dst: RegSelector,
     },
-    /// dst = src == dst
+    /// dst = src == dst, where true is all 1 bits and false is all 0 bits
     CmpEq {
         size: OpSize,
         src: RegSelector,
         dst: RegSelector,
     },
-    /// dst = src != dst
+    /// dst = src != dst, where true is all 1 bits and false is all 0 bits
     CmpNeq {
         size: OpSize,
         src: RegSelector,
@@ -150,15 +176,222 @@ pub enum InstructionKind {
     UnaryBitNot {
         dst: RegSelector,
     },
+    /// dst = !src
+    BitNot {
+        src: RegSelector,
+        dst: RegSelector,
+    },
     // TODO: System, SpecOp
 }
 
+/// the mnemonics that instructions are written with, shared by
+/// [`InstructionKind::mnemonic`] and the parser's instruction set so that they cannot
+/// disagree
+pub mod mnemonic {
+    pub const MOV: &str = "mov";
+    pub const SWIZZLE: &str = "swizzle";
+    pub const ADD: &str = "add";
+    pub const ADD_SAT: &str = "add_sat";
+    pub const SUB: &str = "sub";
+    pub const SUB_SAT: &str = "sub_sat";
+    pub const CMPEQ: &str = "cmpeq";
+    pub const CMPNEQ: &str = "cmpneq";
+    pub const LSL: &str = "lsl";
+    pub const LSR: &str = "lsr";
+    pub const ASR: &str = "asr";
+    pub const ROL: &str = "rol";
+    pub const ROR: &str = "ror";
+    pub const AND: &str = "and";
+    pub const OR: &str = "or";
+    pub const XOR: &str = "xor";
+    pub const NAND: &str = "nand";
+    pub const NOR: &str = "nor";
+    pub const XNOR: &str = "xnor";
+    pub const NOT: &str = "not";
+    /// `zero` is lowered to `xor`, so no [`InstructionKind`](super::InstructionKind)
+    /// has this mnemonic
+    pub const ZERO: &str = "zero";
+}
+
+impl InstructionKind {
+    /// the mnemonic that the instruction is written with in assembly. loads and stores
+    /// are written as `mov`, and reversed subtracts are written as `sub`.
+    pub fn mnemonic(&self) -> &'static str {
+        use mnemonic::*;
+        use InstructionKind::*;
+        match self {
+            Move { .. } | Load { .. } | Store { .. } => MOV,
+            Swizzle { .. } => SWIZZLE,
+            Add { .. } => ADD,
+            Sub { .. } | SubRev { .. } => SUB,
+            CmpEq { .. } => CMPEQ,
+            CmpNeq { .. } => CMPNEQ,
+            AddSaturate { .. } => ADD_SAT,
+            SubSaturate { .. } | SubRevSaturate { .. } => SUB_SAT,
+            ShiftLeft { .. } => LSL,
+            ShiftRightLogical { .. } => LSR,
+            ShiftRightArithmetic { .. } => ASR,
+            RotateLeft { .. } => ROL,
+            RotateRight { .. } => ROR,
+            BitAnd { .. } => AND,
+            BitOr { .. } => OR,
+            BitXor { .. } => XOR,
+            BitNand { .. } => NAND,
+            BitNor { .. } => NOR,
+            BitXnor { .. } => XNOR,
+            UnaryBitNot { .. } | BitNot { .. } => NOT,
+        }
+    }
+
+    /// whether the instruction writes to `ri`, which jumps
+    pub fn writes_ri(&self) -> bool {
+        self.operands()
+            .iter()
+            .any(|(reg, role)| role.writes() && reg.is_ri())
+    }
+
+    /// gets every register operand of the instruction and how it is used.
+    /// destinations that only have some of their elements written are still `Dst`.
+    /// the address of a memory operand that is incremented is `SrcDst`.
+    pub fn operands(&self) -> Vec<(RegSelector, OperandRole)> {
+        use InstructionKind::*;
+        use OperandRole::*;
+        let mem_role = |mem: MemoryOperand| if mem.increment() { SrcDst } else { Src };
+
+        match *self {
+            Move { src, dst } => vec![(src.reg(), Src), (dst.reg(), Dst)],
+            Swizzle { reg } => vec![(reg.reg(), SrcDst)],
+            Load { mem, dst } => vec![(mem.reg(), mem_role(mem)), (dst.reg(), Dst)],
+            Store { src, mem } => vec![(src.reg(), Src), (mem.reg(), mem_role(mem))],
+
+            Add { src, dst, .. }
+            | Sub { src, dst, .. }
+            | SubRev { src, dst, .. }
+            | CmpEq { src, dst, .. }
+            | CmpNeq { src, dst, .. }
+            | AddSaturate { src, dst, .. }
+            | SubSaturate { src, dst, .. }
+            | SubRevSaturate { src, dst, .. }
+            | BitAnd { src, dst }
+            | BitOr { src, dst }
+            | BitXor { src, dst }
+            | BitNand { src, dst }
+            | BitNor { src, dst }
+            | BitXnor { src, dst } => vec![(src, Src), (dst, SrcDst)],
+
+            ShiftLeft { dst, amount, .. }
+            | ShiftRightLogical { dst, amount, .. }
+            | ShiftRightArithmetic { dst, amount, .. }
+            | RotateLeft { dst, amount, .. }
+            | RotateRight { dst, amount, .. } => match amount {
+                ShiftAmount::Register(reg) => vec![(reg, Src), (dst, SrcDst)],
+                ShiftAmount::Const(..) => vec![(dst, SrcDst)],
+            },
+
+            UnaryBitNot { dst } => vec![(dst, SrcDst)],
+            BitNot { src, dst } => vec![(src, Src), (dst, Dst)],
+        }
+    }
+}
+
+/// displays the instruction as assembly that parses back to the same instruction
+impl fmt::Display for InstructionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use InstructionKind::*;
+        let name = self.mnemonic();
+        match self {
+            Move { src, dst } => write!(f, "{} {}, {}", name, dst, src),
+            Swizzle { reg } => write!(f, "{} {}", name, reg),
+            Load { mem, dst } => write!(f, "{} {}, {}", name, dst, mem),
+            Store { src, mem } => write!(f, "{} {}, {}", name, mem, src),
+
+            Add { size, src, dst }
+            | SubRev { size, src, dst }
+            | CmpEq { size, src, dst }
+            | CmpNeq { size, src, dst }
+            | AddSaturate { size, src, dst }
+            | SubRevSaturate { size, src, dst } => {
+                write!(f, "{}.{} {}, {}, {}", name, size, dst, dst, src)
+            }
+            // dst = src - dst
+            Sub { size, src, dst } | SubSaturate { size, src, dst } => {
+                write!(f, "{}.{} {}, {}, {}", name, size, dst, src, dst)
+            }
+
+            ShiftLeft { size, dst, amount }
+            | ShiftRightLogical { size, dst, amount }
+            | ShiftRightArithmetic { size, dst, amount }
+            | RotateLeft { size, dst, amount }
+            | RotateRight { size, dst, amount } => {
+                write!(f, "{}.{} {}, {}", name, size, dst, amount)
+            }
+
+            BitAnd { src, dst }
+            | BitOr { src, dst }
+            | BitXor { src, dst }
+            | BitNand { src, dst }
+            | BitNor { src, dst }
+            | BitXnor { src, dst }
+            | BitNot { src, dst } => write!(f, "{} {}, {}", name, dst, src),
+            UnaryBitNot { dst } => write!(f, "{} {}", name, dst),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpSize {
     Byte,
     Word,
 }
 
+impl OpSize {
+    /// the width of each element in bits
+    pub fn bits(&self) -> u8 {
+        self.bytes() * 8
+    }
+
+    /// the width of each element in bytes
+    pub fn bytes(&self) -> u8 {
+        match self {
+            OpSize::Byte => 1,
+            OpSize::Word => 2,
+        }
+    }
+}
+
+impl TryFrom<&str> for OpSize {
+    type Error = InvalidOpSize;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "b" | "byte" => Ok(OpSize::Byte),
+            "w" | "word" => Ok(OpSize::Word),
+            _ => Err(InvalidOpSize(value.to_string())),
+        }
+    }
+}
+
+impl FromStr for OpSize {
+    type Err = InvalidOpSize;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s)
+    }
+}
+
+impl fmt::Display for OpSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpSize::Byte => write!(f, "b"),
+            OpSize::Word => write!(f, "w"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid operation size `{0}`, expected `b` or `w`")]
+pub struct InvalidOpSize(pub String);
+
 #[derive(Debug, Clone, Copy)]
 pub enum ShiftAmount {
     Register(RegSelector),
@@ -175,6 +408,15 @@ impl ShiftAmount {
     }
 }
 
+impl fmt::Display for ShiftAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShiftAmount::Register(reg) => write!(f, "{}", reg),
+            ShiftAmount::Const(val, _) => write!(f, "{}", val),
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct RegSelector {
     idx: u8,
@@ -184,6 +426,8 @@ pub struct RegSelector {
 /// the maximum index per type of register
 pub const MAX_REG_IDX: u8 = 7;
 const DATA_IDX_OFFSET: u8 = 8;
+/// the index of `ri`, which is an alias for `r7`
+const RI_IDX: u8 = DATA_IDX_OFFSET + MAX_REG_IDX;
 impl RegSelector {
     pub fn new_const(idx: u8, span: Span) -> Self {
         assert!(idx <= MAX_REG_IDX);
@@ -216,7 +460,12 @@ impl RegSelector {
     /// this method considers `ri` (`r15`) to be a general purpose register
     /// because it is writable.
     pub fn is_gpr(&self) -> bool {
-        MAX_REG_IDX < self.idx && self.idx <= DATA_IDX_OFFSET + MAX_REG_IDX
+        MAX_REG_IDX < self.idx && self.idx <= RI_IDX
+    }
+
+    /// returns `true` if the register is `ri`, which jumps when it is written
+    pub fn is_ri(&self) -> bool {
+        self.idx == RI_IDX
     }
 }
 
@@ -228,7 +477,7 @@ impl fmt::Debug for RegSelector {
                 &match self.idx {
                     n @ 0..=7 => format!("c{}", n),
                     n @ 8..=14 => format!("r{}", n - DATA_IDX_OFFSET),
-                    15 => format!("ri"),
+                    RI_IDX => String::from("ri"),
                     _ => unreachable!(),
                 },
             )
@@ -242,7 +491,7 @@ impl fmt::Display for RegSelector {
         match self.idx {
             n @ 0..=7 => write!(f, "c{}", n),
             n @ 8..=14 => write!(f, "r{}", n - DATA_IDX_OFFSET),
-            15 => write!(f, "ri"),
+            RI_IDX => write!(f, "ri"),
             _ => unreachable!(),
         }
     }
@@ -279,6 +528,12 @@ impl SetSelector {
         set
     }
 
+    /// whether the specified element is in the selector
+    pub fn contains(&self, idx: u8) -> bool {
+        assert!(idx < 4);
+        self.0 & (1 << idx) != 0
+    }
+
     /// gets the bits set in the selector
     pub fn bits(&self) -> u8 {
         self.0
@@ -301,12 +556,10 @@ impl SetSelector {
         self.0 & 0b1000 != 0
     }
 
-    /*
     /// gets the number of selected elements in the selector
-    fn count(&self) -> u8 {
+    pub fn count(&self) -> u8 {
         self.0.count_ones() as u8
     }
-    */
 }
 
 impl PartialEq for SetSelector {
@@ -324,25 +577,39 @@ impl fmt::Debug for SetSelector {
                 if self.0 == 0 {
                     return write!(f, "<none>");
                 }
-                if self.0 & 0b0001 != 0 {
-                    write!(f, "x")?;
-                }
-                if self.0 & 0b0010 != 0 {
-                    write!(f, "y")?;
-                }
-                if self.0 & 0b0100 != 0 {
-                    write!(f, "z")?;
-                }
-                if self.0 & 0b1000 != 0 {
-                    write!(f, "w")?;
-                }
-                Ok(())
+                write!(f, "{}", self)
             })
             .field("span", &self.span())
             .finish()
     }
 }
 
+/// displays the selected elements in order, as in `xyw`
+impl fmt::Display for SetSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for idx in (0..4).filter(|&idx| self.contains(idx)) {
+            write!(f, "{}", elem_name(idx))?;
+        }
+        Ok(())
+    }
+}
+
+/// the names of the elements of a vector, in order
+const ELEM_NAMES: [char; 4] = ['x', 'y', 'z', 'w'];
+
+/// gets the name of the element at `idx`, as in `x` for 0
+pub fn elem_name(idx: u8) -> char {
+    ELEM_NAMES[(idx & 0b11) as usize]
+}
+
+/// gets the index of the element named `name`, as in 0 for `x`
+pub fn elem_idx(name: char) -> Option<u8> {
+    ELEM_NAMES
+        .iter()
+        .position(|&c| c == name)
+        .map(|idx| idx as u8)
+}
+
 #[derive(Copy, Clone)]
 /// an ordered selector of elements from a vector
 /// the low 2 bits correspond to the first selected element index and so on
@@ -353,8 +620,53 @@ impl SwizzleSelector {
         Self(0, span)
     }
 
+    /// creates a selector from its encoded bits, where each pair of bits from the low
+    /// bits up is the element selected for that position
+    pub fn from_bits(bits: u8, span: Span) -> Self {
+        Self(bits, span)
+    }
+
+    /// creates the canonical swizzle that moves the elements selected by `set` to the
+    /// lowest positions in ascending order, followed by the elements it does not select
+    /// in ascending order. for example `yw` becomes `ywxz`, and both `xyzw` and the empty
+    /// set become `xyzw`. the result is always a permutation, so no element is lost.
+    pub fn from_set(set: &SetSelector) -> Self {
+        let mut swizzle = Self::empty(set.span());
+        let selected = (0..4).filter(|&idx| set.contains(idx));
+        let unselected = (0..4).filter(|&idx| !set.contains(idx));
+        for (offset, idx) in selected.chain(unselected).enumerate() {
+            swizzle.set(offset as u8, idx);
+        }
+        swizzle
+    }
+
+    /// gets the element selected for the position at `offset`
+    pub fn get(&self, offset: u8) -> u8 {
+        assert!(offset < 4, "swizzle position {} is out of range", offset);
+        (self.0 >> (offset * 2)) & 0b11
+    }
+
+    /// whether the element `idx` is selected for any position, so its value is used
+    pub fn reads(&self, idx: u8) -> bool {
+        (0..4).any(|offset| self.get(offset) == idx)
+    }
+
+    /// creates the selector that is the same as swizzling by `other` and then by `self`.
+    /// composing with `xyzw` does not change the other selector.
+    pub fn compose(&self, other: &SwizzleSelector) -> SwizzleSelector {
+        let mut composed = SwizzleSelector::empty(Span::between(other.span(), self.span()));
+        for offset in 0..4 {
+            // the element at `offset` is moved from the element of the first swizzle's
+            // output that `self` selects, which `other` moved from its own selection
+            composed.set(offset, other.get(self.get(offset)));
+        }
+        composed
+    }
+
+    /// sets the element selected for the position at `offset`
     pub fn set(&mut self, offset: u8, selected: u8) {
-        let shift = (offset & 0b11) * 2;
+        assert!(offset < 4, "swizzle position {} is out of range", offset);
+        let shift = offset * 2;
         // set the bits at the position to 0, then set them to the correct value
         self.0 &= !(0b11 << shift);
         self.0 |= (selected & 0b11) << shift;
@@ -380,27 +692,21 @@ impl Eq for SwizzleSelector {}
 impl fmt::Debug for SwizzleSelector {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SwizzleSelector")
-            .field("selector", {
-                let elem_name = |idx: u8| match idx & 0b11 {
-                    0b00 => 'x',
-                    0b01 => 'y',
-                    0b10 => 'z',
-                    0b11 => 'w',
-                    _ => unreachable!(),
-                };
-                &format_args!(
-                    "{}{}{}{}",
-                    elem_name(self.0 & 0b00000011),
-                    elem_name((self.0 & 0b00001100) >> 2),
-                    elem_name((self.0 & 0b00110000) >> 4),
-                    elem_name((self.0 & 0b11000000) >> 6)
-                )
-            })
+            .field("selector", &format_args!("{}", self))
             .field("span", &self.span())
             .finish()
     }
 }
 
+impl fmt::Display for SwizzleSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for offset in 0..4 {
+            write!(f, "{}", elem_name(self.get(offset)))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct MemoryOperand {
     reg: RegSelector,
@@ -428,6 +734,8 @@ impl MemoryOperand {
         self.scatter
     }
 
+    /// whether the address is incremented after the access. incrementing `ri` is a write
+    /// to `ri`, so it jumps, and it is incremented only once.
     pub fn increment(&self) -> bool {
         self.increment
     }
@@ -437,6 +745,27 @@ impl MemoryOperand {
     }
 }
 
+impl PartialEq for MemoryOperand {
+    fn eq(&self, other: &Self) -> bool {
+        self.reg == other.reg && self.scatter == other.scatter && self.increment == other.increment
+    }
+}
+
+impl Eq for MemoryOperand {}
+
+/// displays the operand as `[reg.x]` for a single address or `[reg.xyzw]` to scatter,
+/// followed by `+` if the address is incremented
+impl fmt::Display for MemoryOperand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let selector = if self.scatter { "xyzw" } else { "x" };
+        write!(f, "[{}.{}]", self.reg, selector)?;
+        if self.increment {
+            write!(f, "+")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct SetRegSelector {
     reg: RegSelector,
@@ -466,6 +795,20 @@ impl SetRegSelector {
     }
 }
 
+impl PartialEq for SetRegSelector {
+    fn eq(&self, other: &Self) -> bool {
+        self.reg == other.reg && self.selector == other.selector
+    }
+}
+
+impl Eq for SetRegSelector {}
+
+impl fmt::Display for SetRegSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.reg, self.selector)
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct SwizzleRegSelector {
     reg: RegSelector,
@@ -494,3 +837,382 @@ impl SwizzleRegSelector {
         self.span
     }
 }
+
+impl PartialEq for SwizzleRegSelector {
+    fn eq(&self, other: &Self) -> bool {
+        self.reg == other.reg && self.selector == other.selector
+    }
+}
+
+impl Eq for SwizzleRegSelector {}
+
+impl fmt::Display for SwizzleRegSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.reg, self.selector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::AssembleOptions;
+
+    #[test]
+    fn memory_operand_eq_ignores_span() {
+        let a = MemoryOperand::new(
+            RegSelector::new_gpr(1, Span::new(0, 2)),
+            false,
+            true,
+            Span::new(0, 6),
+        );
+        let b = MemoryOperand::new(
+            RegSelector::new_gpr(1, Span::new(10, 12)),
+            false,
+            true,
+            Span::new(9, 16),
+        );
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn memory_operand_eq_compares_fields() {
+        let reg = RegSelector::new_gpr(1, Span::DUMMY);
+        let base = MemoryOperand::new(reg, false, false, Span::DUMMY);
+        assert_ne!(
+            base,
+            MemoryOperand::new(
+                RegSelector::new_gpr(2, Span::DUMMY),
+                false,
+                false,
+                Span::DUMMY
+            )
+        );
+        assert_ne!(base, MemoryOperand::new(reg, true, false, Span::DUMMY));
+        assert_ne!(base, MemoryOperand::new(reg, false, true, Span::DUMMY));
+    }
+
+    #[test]
+    fn set_reg_selector_eq_ignores_span() {
+        let a = SetRegSelector::new(
+            RegSelector::new_gpr(1, Span::new(0, 2)),
+            SetSelector::from_bits(0b0011, Span::new(3, 5)),
+            Span::new(0, 5),
+        );
+        let b = SetRegSelector::new(
+            RegSelector::new_gpr(1, Span::new(10, 12)),
+            SetSelector::from_bits(0b0011, Span::new(13, 15)),
+            Span::new(10, 15),
+        );
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn set_reg_selector_eq_compares_fields() {
+        let reg = RegSelector::new_gpr(1, Span::DUMMY);
+        let selector = SetSelector::from_bits(0b0011, Span::DUMMY);
+        let base = SetRegSelector::new(reg, selector, Span::DUMMY);
+        assert_ne!(
+            base,
+            SetRegSelector::new(RegSelector::new_gpr(2, Span::DUMMY), selector, Span::DUMMY)
+        );
+        assert_ne!(
+            base,
+            SetRegSelector::new(
+                reg,
+                SetSelector::from_bits(0b0111, Span::DUMMY),
+                Span::DUMMY
+            )
+        );
+    }
+
+    #[test]
+    fn swizzle_reg_selector_eq_ignores_span() {
+        let a = SwizzleRegSelector::new(
+            RegSelector::new_gpr(1, Span::new(0, 2)),
+            SwizzleSelector::from_bits(0b00_01_10_11, Span::new(3, 7)),
+            Span::new(0, 7),
+        );
+        let b = SwizzleRegSelector::new(
+            RegSelector::new_gpr(1, Span::new(10, 12)),
+            SwizzleSelector::from_bits(0b00_01_10_11, Span::new(13, 17)),
+            Span::new(10, 17),
+        );
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn swizzle_reg_selector_eq_compares_fields() {
+        let reg = RegSelector::new_gpr(1, Span::DUMMY);
+        let selector = SwizzleSelector::from_bits(0b00_01_10_11, Span::DUMMY);
+        let base = SwizzleRegSelector::new(reg, selector, Span::DUMMY);
+        assert_ne!(
+            base,
+            SwizzleRegSelector::new(RegSelector::new_gpr(2, Span::DUMMY), selector, Span::DUMMY)
+        );
+        assert_ne!(
+            base,
+            SwizzleRegSelector::new(
+                reg,
+                SwizzleSelector::from_bits(0b11_10_01_00, Span::DUMMY),
+                Span::DUMMY
+            )
+        );
+    }
+
+    #[test]
+    fn op_size_spellings() {
+        for (name, size) in [
+            ("b", OpSize::Byte),
+            ("byte", OpSize::Byte),
+            ("w", OpSize::Word),
+            ("word", OpSize::Word),
+        ] {
+            assert_eq!(OpSize::try_from(name).unwrap(), size, "{}", name);
+        }
+    }
+
+    #[test]
+    fn op_size_rejects_unknown() {
+        let err = OpSize::try_from("q").unwrap_err();
+        assert_eq!(err.0, "q");
+        assert_eq!(
+            err.to_string(),
+            "invalid operation size `q`, expected `b` or `w`"
+        );
+        assert!(OpSize::try_from("B").is_err());
+        assert!(OpSize::try_from("").is_err());
+    }
+
+    #[test]
+    fn set_selector_from_every_bit_pattern() {
+        for bits in 0..16 {
+            let selector = SetSelector::from_bits(bits, Span::DUMMY);
+            assert_eq!(selector.bits(), bits);
+            assert_eq!(selector.count(), bits.count_ones() as u8);
+            let flags = [selector.x(), selector.y(), selector.z(), selector.w()];
+            for (idx, flag) in flags.into_iter().enumerate() {
+                assert_eq!(flag, bits & (1 << idx) != 0);
+                assert_eq!(selector.contains(idx as u8), flag);
+            }
+            let expected = (0..4)
+                .filter(|idx| bits & (1 << idx) != 0)
+                .map(elem_name)
+                .collect::<String>();
+            assert_eq!(selector.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn swizzle_selector_from_bytes() {
+        for (bits, expected) in [
+            (0b11_10_01_00, "xyzw"),
+            (0b00_01_10_11, "wzyx"),
+            (0b00_00_00_00, "xxxx"),
+            (0b11_11_11_11, "wwww"),
+            (0b00_11_10_01, "yzwx"),
+        ] {
+            let selector = SwizzleSelector::from_bits(bits, Span::DUMMY);
+            assert_eq!(selector.bits(), bits);
+            assert_eq!(selector.to_string(), expected);
+            for (offset, name) in expected.chars().enumerate() {
+                assert_eq!(Some(selector.get(offset as u8)), elem_idx(name));
+            }
+        }
+    }
+
+    #[test]
+    fn op_size_widths() {
+        assert_eq!(OpSize::Byte.bits(), 8);
+        assert_eq!(OpSize::Byte.bytes(), 1);
+        assert_eq!(OpSize::Word.bits(), 16);
+        assert_eq!(OpSize::Word.bytes(), 2);
+    }
+
+    /// the operands of an instruction written in assembly, with registers as their names
+    fn operands(src: &str) -> Vec<(String, OperandRole)> {
+        crate::parse_instruction(src, &AssembleOptions::default())
+            .expect("instruction should parse")
+            .operands()
+            .into_iter()
+            .map(|(reg, role)| (reg.to_string(), role))
+            .collect()
+    }
+
+    #[test]
+    fn operands_of_each_form() {
+        use OperandRole::*;
+        let ops = |list: &[(&str, OperandRole)]| {
+            list.iter()
+                .map(|(name, role)| (name.to_string(), *role))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            operands("mov r0.xy, r1.xy"),
+            ops(&[("r1", Src), ("r0", Dst)])
+        );
+        assert_eq!(operands("swizzle r2.wzyx"), ops(&[("r2", SrcDst)]));
+        assert_eq!(operands("mov r0.x, [r1]"), ops(&[("r1", Src), ("r0", Dst)]));
+        assert_eq!(
+            operands("mov r0.x, [r1]+"),
+            ops(&[("r1", SrcDst), ("r0", Dst)])
+        );
+        assert_eq!(operands("mov [ri], r3.x"), ops(&[("r3", Src), ("ri", Src)]));
+        assert_eq!(
+            operands("add.w r0, r0, c1"),
+            ops(&[("c1", Src), ("r0", SrcDst)])
+        );
+        assert_eq!(operands("lsl.w r0, 3"), ops(&[("r0", SrcDst)]));
+        assert_eq!(
+            operands("lsl.w r0, r4"),
+            ops(&[("r4", Src), ("r0", SrcDst)])
+        );
+        assert_eq!(operands("not r0"), ops(&[("r0", SrcDst)]));
+        assert_eq!(operands("not r0, r1"), ops(&[("r1", Src), ("r0", Dst)]));
+    }
+
+    #[test]
+    fn operand_roles() {
+        assert!(OperandRole::Src.reads() && !OperandRole::Src.writes());
+        assert!(!OperandRole::Dst.reads() && OperandRole::Dst.writes());
+        assert!(OperandRole::SrcDst.reads() && OperandRole::SrcDst.writes());
+    }
+
+    #[test]
+    fn ri_is_r7() {
+        let ri = RegSelector::new_gpr(7, Span::DUMMY);
+        assert!(ri.is_ri() && ri.is_gpr());
+        assert_eq!(ri.to_string(), "ri");
+        assert!(!RegSelector::new_gpr(6, Span::DUMMY).is_ri());
+        assert!(!RegSelector::new_const(7, Span::DUMMY).is_ri());
+    }
+
+    #[test]
+    fn op_size_round_trip() {
+        for size in [OpSize::Byte, OpSize::Word] {
+            let name = size.to_string();
+            assert_eq!(name.parse::<OpSize>().unwrap(), size, "{}", name);
+        }
+        assert_eq!(OpSize::Byte.to_string(), "b");
+        assert_eq!(OpSize::Word.to_string(), "w");
+        assert!("q".parse::<OpSize>().is_err());
+    }
+
+    /// swizzles `lanes` by hand, moving the element that `selector` selects for each
+    /// position into that position
+    fn apply(selector: SwizzleSelector, lanes: [char; 4]) -> [char; 4] {
+        std::array::from_fn(|offset| lanes[selector.get(offset as u8) as usize])
+    }
+
+    #[test]
+    fn compose_matches_manual_lanes() {
+        let swizzle = |bits| SwizzleSelector::from_bits(bits, Span::DUMMY);
+        for first in 0..=u8::MAX {
+            for second in 0..=u8::MAX {
+                let (first, second) = (swizzle(first), swizzle(second));
+                assert_eq!(
+                    apply(second.compose(&first), ELEM_NAMES),
+                    apply(second, apply(first, ELEM_NAMES)),
+                    "{} then {}",
+                    first,
+                    second
+                );
+            }
+        }
+        // `yzwx` twice rotates by 2
+        assert_eq!(
+            swizzle(0b00_11_10_01)
+                .compose(&swizzle(0b00_11_10_01))
+                .to_string(),
+            "zwxy"
+        );
+    }
+
+    #[test]
+    fn compose_with_identity() {
+        let identity = SwizzleSelector::from_bits(0b11_10_01_00, Span::DUMMY);
+        for bits in 0..=u8::MAX {
+            let swizzle = SwizzleSelector::from_bits(bits, Span::DUMMY);
+            assert_eq!(swizzle.compose(&identity), swizzle);
+            assert_eq!(identity.compose(&swizzle), swizzle);
+        }
+    }
+
+    #[test]
+    fn set_selector_membership() {
+        let mut selector = SetSelector::empty(Span::DUMMY);
+        assert!((0..4).all(|idx| !selector.contains(idx)));
+        assert!(!selector.set(2));
+        assert!(selector.set(2));
+        assert!(!selector.set(0));
+        assert_eq!(
+            (0..4).map(|idx| selector.contains(idx)).collect::<Vec<_>>(),
+            [true, false, true, false]
+        );
+    }
+
+    #[test]
+    fn swizzle_selector_reads() {
+        // `xxyy` only uses the values of `x` and `y`
+        let selector = SwizzleSelector::from_bits(0b01_01_00_00, Span::DUMMY);
+        assert_eq!(selector.to_string(), "xxyy");
+        assert_eq!(
+            (0..4).map(|idx| selector.reads(idx)).collect::<Vec<_>>(),
+            [true, true, false, false]
+        );
+        let broadcast = SwizzleSelector::from_bits(0b11_11_11_11, Span::DUMMY);
+        assert_eq!(
+            (0..4).map(|idx| broadcast.reads(idx)).collect::<Vec<_>>(),
+            [false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn swizzle_from_set() {
+        for (set, name, bits) in [
+            (0b0000, "xyzw", 0b11_10_01_00),
+            (0b1111, "xyzw", 0b11_10_01_00),
+            (0b0001, "xyzw", 0b11_10_01_00),
+            (0b1010, "ywxz", 0b10_00_11_01),
+            (0b1000, "wxyz", 0b10_01_00_11),
+            (0b0101, "xzyw", 0b11_01_10_00),
+            (0b1100, "zwxy", 0b01_00_11_10),
+        ] {
+            let swizzle = SwizzleSelector::from_set(&SetSelector::from_bits(set, Span::DUMMY));
+            assert_eq!(swizzle.to_string(), name, "{:04b}", set);
+            assert_eq!(swizzle.bits(), bits, "{:04b}", set);
+        }
+    }
+
+    #[test]
+    fn swizzle_set_in_range() {
+        let mut selector = SwizzleSelector::empty(Span::DUMMY);
+        selector.set(3, 1);
+        selector.set(0, 2);
+        assert_eq!(selector.to_string(), "zxxy");
+    }
+
+    #[test]
+    #[should_panic(expected = "swizzle position 4 is out of range")]
+    fn swizzle_set_out_of_range() {
+        SwizzleSelector::empty(Span::DUMMY).set(4, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "swizzle position 4 is out of range")]
+    fn swizzle_get_out_of_range() {
+        SwizzleSelector::empty(Span::DUMMY).get(4);
+    }
+
+    #[test]
+    fn swizzle_display_matches_debug() {
+        for bits in 0..=u8::MAX {
+            let swizzle = SwizzleSelector::from_bits(bits, Span::DUMMY);
+            let debug = format!("{:?}", swizzle);
+            let lanes = debug
+                .strip_prefix("SwizzleSelector { selector: ")
+                .and_then(|rest| rest.split(',').next())
+                .unwrap();
+            assert_eq!(lanes, swizzle.to_string(), "{:08b}", bits);
+        }
+    }
+}
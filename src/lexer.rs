@@ -1,6 +1,9 @@
 use std::fmt::Display;
 
-use crate::reader::{self, Reader};
+use crate::{
+    diag::Diagnostic,
+    reader::{self, Reader},
+};
 
 /// this is a different thing from reader to be able to easily separate
 /// the splitting and the getting source mechanisms
@@ -8,62 +11,199 @@ use crate::reader::{self, Reader};
 pub struct Lexer<'a> {
     src: &'a str,
     pos: usize,
+    /// the id of the file that `src` is from, used for spans
+    file: u32,
     reader: Reader<'a>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(src: &'a str) -> Self {
+        Self::new_in_file(src, 0)
+    }
+
+    /// creates a lexer for the source of the file with id `file`
+    pub fn new_in_file(src: &'a str, file: u32) -> Self {
         Self {
             src,
             pos: 0,
+            file,
             reader: Reader::new(src),
         }
     }
 
+    /// creates a lexer that starts at byte `offset` of `src`, so that editors can re-lex
+    /// from an edit instead of from the start of the file. spans are still relative to
+    /// the start of `src`.
+    ///
+    /// tokens never depend on anything before them and never contain a newline, except
+    /// for the newline token itself, so lexing from the start of any line produces the
+    /// same tokens as lexing the whole source. resuming in the middle of a token, such
+    /// as inside a comment, does not.
+    ///
+    /// # Panics
+    /// panics if `offset` is not on a `char` boundary of `src`.
+    pub fn resume_at(src: &'a str, offset: usize, file: u32) -> Self {
+        assert!(src.is_char_boundary(offset));
+        Self {
+            src,
+            pos: offset,
+            file,
+            reader: Reader::new(&src[offset..]),
+        }
+    }
+
     pub fn next_token(&mut self) -> Token {
         use TokenKind::*;
 
         // loops to skip whitespace
         loop {
-            let next = self.reader.next();
+            let next = self.reader.next_token();
 
             let start_pos = self.pos;
-            self.pos += next.len();
+            // the reader only has the lengths of tokens, so the offset is kept in sync by
+            // adding every length, including unknown tokens and skipped whitespace
+            self.pos = self.pos.saturating_add(next.len());
+            debug_assert_eq!(self.src.len() - self.pos, self.reader.remaining().len());
+            let span = Span::from_offsets(start_pos, self.pos, self.file);
+            let text = span.span_text(self.src);
 
             let kind = match next.kind() {
                 reader::TokenKind::Newline => Newline,
                 reader::TokenKind::Whitespace | reader::TokenKind::Comment => continue,
-                reader::TokenKind::Ident => Ident(self.src[start_pos..self.pos].to_string()),
-                reader::TokenKind::Number => Number(
-                    self.src[start_pos..self.pos]
-                        .parse()
-                        .expect("lexed number literals should be valid"),
-                ),
+                reader::TokenKind::Ident if text.is_ascii() => Ident(text.to_string()),
+                reader::TokenKind::Ident => NonAsciiIdent(text.to_string()),
+                reader::TokenKind::Number => parse_number(text),
                 reader::TokenKind::EoF => EoF,
                 reader::TokenKind::Comma => Comma,
                 reader::TokenKind::Dot => Dot,
                 reader::TokenKind::LeftBracket => LeftBracket,
                 reader::TokenKind::RightBracket => RightBracket,
+                reader::TokenKind::LeftBrace => LeftBrace,
+                reader::TokenKind::RightBrace => RightBrace,
                 reader::TokenKind::Plus => Plus,
+                reader::TokenKind::Minus => Minus,
+                reader::TokenKind::Star => Star,
+                reader::TokenKind::Sigil => Sigil(text.chars().next().unwrap_or('%')),
+                reader::TokenKind::Str => match text.strip_suffix('"') {
+                    // a lone `"` is unterminated, even though it ends with `"`
+                    Some(s) if text.len() >= 2 => Str(s[1..].to_string()),
+                    _ => Unknown(text.to_string()),
+                },
+                reader::TokenKind::Unknown => Unknown(text.to_string()),
             };
-            let span = Span::new(start_pos as u32, self.pos as u32);
             return Token::new(kind, span);
         }
         // UNREACHABLE
     }
 }
 
+/// parses a number literal, which may have a `0x`, `0b`, or `0o` prefix for hex, binary,
+/// or octal
+fn parse_number(text: &str) -> TokenKind {
+    let (digits, radix) = match text.get(..2) {
+        Some("0x" | "0X") => (&text[2..], 16),
+        Some("0b" | "0B") => (&text[2..], 2),
+        Some("0o" | "0O") => (&text[2..], 8),
+        _ => (text, 10),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return TokenKind::MalformedNumber(text.to_string());
+    }
+
+    match number_to_u16(text, radix, Span::DUMMY) {
+        Ok(val) => TokenKind::Number(val),
+        // the digits are all valid, so this can only be an overflow
+        Err(_) => TokenKind::InvalidNumber(text.to_string()),
+    }
+}
+
+/// parses a number literal in `radix` into a `u16`. the literal may have the prefix for
+/// its radix, and errors name it as it was written. the error is reported at `span`.
+pub fn number_to_u16(text: &str, radix: u32, span: Span) -> Result<u16, Diagnostic> {
+    number_to_bits(text, radix, 16, span).map(|val| val as u16)
+}
+
+/// parses a number literal in `radix` into a `u8`. the literal may have the prefix for
+/// its radix, and errors name it as it was written. the error is reported at `span`.
+pub fn number_to_u8(text: &str, radix: u32, span: Span) -> Result<u8, Diagnostic> {
+    number_to_bits(text, radix, 8, span).map(|val| val as u8)
+}
+
+fn number_to_bits(text: &str, radix: u32, bits: u32, span: Span) -> Result<u32, Diagnostic> {
+    let prefix = match radix {
+        16 => "0x",
+        2 => "0b",
+        8 => "0o",
+        _ => "",
+    };
+    let digits = match text.get(..prefix.len()) {
+        Some(start) if !prefix.is_empty() && start.eq_ignore_ascii_case(prefix) => {
+            &text[prefix.len()..]
+        }
+        _ => text,
+    };
+    if digits.is_empty() {
+        return Err(Diagnostic::new(
+            format!("expected digits in number `{}`", text),
+            span,
+        ));
+    }
+    let mut val = 0_u32;
+    for c in digits.chars() {
+        let Some(digit) = c.to_digit(radix) else {
+            return Err(Diagnostic::new(
+                format!("invalid digit `{}` in base {} number", c, radix),
+                span,
+            ));
+        };
+        // saturate so that any number of digits can be checked without overflowing
+        val = val.saturating_mul(radix).saturating_add(digit);
+    }
+    if val >> bits != 0 {
+        return Err(Diagnostic::new(
+            format!("value {} does not fit in {} bits", text, bits),
+            span,
+        ));
+    }
+    Ok(val)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Span {
     low: u32,
     high: u32,
+    /// the id of the file this span is in
+    file: u32,
 }
 
 impl Span {
-    pub const DUMMY: Self = Self { low: 0, high: 0 };
+    /// a placeholder span for instructions and operands that do not come from the source,
+    /// such as the results of error recovery or programmatically built instructions.
+    /// diagnostics with this span are rendered without a source location.
+    ///
+    /// it starts after it ends, which no real span does, so that it is never confused with
+    /// an empty span, such as the EoF of an empty file.
+    pub const DUMMY: Self = Self {
+        low: u32::MAX,
+        high: 0,
+        file: 0,
+    };
 
+    /// creates a span in the main file
     pub fn new(low: u32, high: u32) -> Self {
-        Self { low, high }
+        Self::new_in_file(low, high, 0)
+    }
+
+    pub fn new_in_file(low: u32, high: u32, file: u32) -> Self {
+        Self { low, high, file }
+    }
+
+    /// creates a span from byte offsets. spans only hold 32 bit offsets, so offsets past
+    /// `u32::MAX` in sources larger than 4 GiB saturate instead of wrapping around to the
+    /// start of the source.
+    pub fn from_offsets(low: usize, high: usize, file: u32) -> Self {
+        let offset = |pos: usize| u32::try_from(pos).unwrap_or(u32::MAX);
+        Self::new_in_file(offset(low), offset(high), file)
     }
 
     pub fn low(&self) -> u32 {
@@ -74,16 +214,43 @@ impl Span {
         self.high
     }
 
+    pub fn file(&self) -> u32 {
+        self.file
+    }
+
+    /// gets the text of the source that this span covers. returns an empty string if the
+    /// span is not within `src` or does not start and end on `char` boundaries.
+    pub fn span_text<'a>(&self, src: &'a str) -> &'a str {
+        src.get(self.low as usize..self.high as usize).unwrap_or("")
+    }
+
+    /// whether this is the dummy span used for recovery and generated code
+    pub fn is_dummy(&self) -> bool {
+        self.low == Self::DUMMY.low
+            && self.high == Self::DUMMY.high
+            && self.file == Self::DUMMY.file
+    }
+
+    /// creates a span covering both spans. if either span is a dummy span, the
+    /// other span is returned. spans in different files cannot be joined, so `low`
+    /// is returned for them.
     pub fn between(low: Span, high: Span) -> Self {
-        assert!(low.low() < high.high());
+        if low.is_dummy() {
+            return high;
+        }
+        if high.is_dummy() || low.file() != high.file() {
+            return low;
+        }
+
         Self {
-            low: low.low(),
-            high: high.high(),
+            low: low.low().min(high.low()),
+            high: low.high().max(high.high()),
+            file: low.file(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Token {
     kind: TokenKind,
     span: Span,
@@ -98,12 +265,12 @@ impl Token {
         self.span
     }
 
-    fn new(kind: TokenKind, span: Span) -> Self {
+    pub fn new(kind: TokenKind, span: Span) -> Self {
         Self { kind, span }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TokenKind {
     EoF,
     Newline,
@@ -111,9 +278,24 @@ pub enum TokenKind {
     Dot,
     LeftBracket,
     RightBracket,
+    LeftBrace,
+    RightBrace,
     Plus,
+    Minus,
+    Star,
+    Sigil(char),
     Ident(String),
+    /// an identifier containing non-ASCII characters, which are not allowed
+    NonAsciiIdent(String),
     Number(u16),
+    /// the contents of a string, without the quotes
+    Str(String),
+    /// a number literal that does not fit in 16 bits
+    InvalidNumber(String),
+    /// a number literal with digits that are not valid for its base, or no digits
+    MalformedNumber(String),
+    /// text that does not form any valid token
+    Unknown(String),
 }
 
 impl Display for TokenKind {
@@ -125,9 +307,287 @@ impl Display for TokenKind {
             TokenKind::Dot => write!(f, "."),
             TokenKind::LeftBracket => write!(f, "["),
             TokenKind::RightBracket => write!(f, "]"),
+            TokenKind::LeftBrace => write!(f, "{{"),
+            TokenKind::RightBrace => write!(f, "}}"),
             TokenKind::Plus => write!(f, "+"),
+            TokenKind::Minus => write!(f, "-"),
+            TokenKind::Star => write!(f, "*"),
+            TokenKind::Sigil(c) => write!(f, "{}", c),
             TokenKind::Ident(s) => write!(f, "{}", s),
+            TokenKind::NonAsciiIdent(s) => write!(f, "{}", s),
             TokenKind::Number(val) => write!(f, "{}", val),
+            TokenKind::Str(s) => write!(f, "\"{}\"", s),
+            TokenKind::InvalidNumber(s) => write!(f, "{}", s),
+            TokenKind::MalformedNumber(s) => write!(f, "{}", s),
+            TokenKind::Unknown(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn span_text_slices_source() {
+        let src = "add.w r0, r0, c1\nnot r0\n";
+        assert_eq!(Span::new(6, 8).span_text(src), "r0");
+        assert_eq!(Span::new(17, 23).span_text(src), "not r0");
+        assert_eq!(Span::new(3, 3).span_text(src), "");
+    }
+
+    #[test]
+    fn span_text_outside_source_is_empty() {
+        let src = "é r0";
+        assert_eq!(Span::new(0, 2).span_text(src), "é");
+        // not on a char boundary
+        assert_eq!(Span::new(1, 2).span_text(src), "");
+        assert_eq!(Span::new(3, 100).span_text(src), "");
+    }
+
+    /// lexes every token of `lexer` up to and including the EoF, as the kind and the
+    /// bounds of its span
+    fn lex_all(mut lexer: Lexer<'_>) -> Vec<(TokenKind, u32, u32)> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            let eof = token.kind() == &TokenKind::EoF;
+            tokens.push((
+                token.kind().clone(),
+                token.span().low(),
+                token.span().high(),
+            ));
+            if eof {
+                return tokens;
+            }
+        }
+    }
+
+    #[test]
+    fn resume_matches_full_lex() {
+        let src = "add.w r0, r0, c1 # add\n\n  mov [r1.x]+, r2.xy\n.const c0, 0x10, -2\nnot ri";
+        let full = lex_all(Lexer::new(src));
+        for (idx, _) in src.match_indices('\n') {
+            let offset = idx + 1;
+            let resumed = lex_all(Lexer::resume_at(src, offset, 0));
+            let expected = full
+                .iter()
+                .filter(|(_, low, _)| *low as usize >= offset)
+                .cloned()
+                .collect::<Vec<_>>();
+            assert_eq!(resumed, expected, "resumed at {}", offset);
+        }
+    }
+
+    #[test]
+    fn resume_after_edit() {
+        let old = "not r0\nnot r1\nnot r2\n";
+        let new = "not r0\nadd.w r1, r1, c1\nnot r2\n";
+        // re-lexing from the edited line gives the same tokens as lexing the new source
+        let line_start = old.find('\n').unwrap() + 1;
+        let mut relexed = lex_all(Lexer::new(new))
+            .into_iter()
+            .take_while(|(_, low, _)| (*low as usize) < line_start)
+            .collect::<Vec<_>>();
+        relexed.extend(lex_all(Lexer::resume_at(new, line_start, 0)));
+        assert_eq!(relexed, lex_all(Lexer::new(new)));
+    }
+
+    #[test]
+    fn empty_span_at_start_is_not_dummy() {
+        assert!(Span::DUMMY.is_dummy());
+        let empty = Span::new(0, 0);
+        assert!(!empty.is_dummy());
+
+        // an empty span is joined like any other span
+        let joined = Span::between(empty, Span::new(4, 6));
+        assert_eq!((joined.low(), joined.high()), (0, 6));
+        let joined = Span::between(Span::DUMMY, Span::new(4, 6));
+        assert_eq!((joined.low(), joined.high()), (4, 6));
+    }
+
+    #[test]
+    fn eof_of_empty_source_is_not_dummy() {
+        let mut lexer = Lexer::new("");
+        let token = lexer.next_token();
+        assert_eq!(token.kind(), &TokenKind::EoF);
+        assert!(!token.span().is_dummy());
+    }
+
+    fn lex_one(src: &str) -> TokenKind {
+        Lexer::new(src).next_token().kind().clone()
+    }
+
+    #[test]
+    fn octal_numbers() {
+        assert_eq!(lex_one("0o17"), TokenKind::Number(0o17));
+        assert_eq!(lex_one("0O777"), TokenKind::Number(0o777));
+        assert_eq!(lex_one("0o177777"), TokenKind::Number(0xFFFF));
+        assert_eq!(
+            lex_one("0o200000"),
+            TokenKind::InvalidNumber(String::from("0o200000"))
+        );
+    }
+
+    #[test]
+    fn octal_invalid_digit() {
+        assert_eq!(
+            lex_one("0o8"),
+            TokenKind::MalformedNumber(String::from("0o8"))
+        );
+        assert_eq!(
+            lex_one("0o17a"),
+            TokenKind::MalformedNumber(String::from("0o17a"))
+        );
+        assert_eq!(
+            lex_one("0o"),
+            TokenKind::MalformedNumber(String::from("0o"))
+        );
+    }
+
+    #[test]
+    fn non_ascii_identifier_is_one_token() {
+        let mut lexer = Lexer::new("ré0, x");
+        let token = lexer.next_token();
+        assert_eq!(token.kind(), &TokenKind::NonAsciiIdent(String::from("ré0")));
+        assert_eq!((token.span().low(), token.span().high()), (0, 4));
+        assert_eq!(lexer.next_token().kind(), &TokenKind::Comma);
+    }
+
+    #[test]
+    fn single_char_at_eof() {
+        use TokenKind::*;
+        for (src, kind) in [
+            ("r", Ident(String::from("r"))),
+            ("5", Number(5)),
+            (".", Dot),
+            // a lone `"` is an unterminated string
+            ("\"", Unknown(String::from("\""))),
+            ("\u{e9}", NonAsciiIdent(String::from("\u{e9}"))),
+        ] {
+            let len = src.len() as u32;
+            assert_eq!(
+                lex_all(Lexer::new(src)),
+                [(kind, 0, len), (EoF, len, len)],
+                "{:?}",
+                src
+            );
         }
+        // comments are skipped, so a lone `#` is only the end of the file
+        assert_eq!(lex_all(Lexer::new("#")), [(EoF, 1, 1)]);
+    }
+
+    /// lexes the kinds of every token in `src` before the EoF
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        lex_all(Lexer::new(src))
+            .into_iter()
+            .map(|(kind, _, _)| kind)
+            .filter(|kind| kind != &TokenKind::EoF)
+            .collect()
+    }
+
+    #[test]
+    fn star_token() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("[r1.*]"),
+            [
+                LeftBracket,
+                Ident(String::from("r1")),
+                Dot,
+                Star,
+                RightBracket
+            ]
+        );
+        assert_eq!(lex_all(Lexer::new("*")), [(Star, 0, 1), (EoF, 1, 1)]);
+    }
+
+    #[test]
+    fn brace_tokens() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("{1, 2}"),
+            [LeftBrace, Number(1), Comma, Number(2), RightBrace]
+        );
+    }
+
+    fn number_error(text: &str, radix: u32, bits: u32) -> String {
+        number_to_bits(text, radix, bits, Span::DUMMY)
+            .unwrap_err()
+            .msg()
+            .to_string()
+    }
+
+    #[test]
+    fn numbers_in_range() {
+        assert_eq!(number_to_u16("0xffff", 16, Span::DUMMY).unwrap(), 0xFFFF);
+        assert_eq!(number_to_u16("0XfF", 16, Span::DUMMY).unwrap(), 0xFF);
+        assert_eq!(number_to_u16("ff", 16, Span::DUMMY).unwrap(), 0xFF);
+        assert_eq!(number_to_u16("65535", 10, Span::DUMMY).unwrap(), 65535);
+        assert_eq!(number_to_u16("0b101", 2, Span::DUMMY).unwrap(), 0b101);
+        assert_eq!(number_to_u8("0o377", 8, Span::DUMMY).unwrap(), 0o377);
+        assert_eq!(number_to_u8("0", 10, Span::DUMMY).unwrap(), 0);
+    }
+
+    #[test]
+    fn number_overflow_names_the_literal() {
+        assert_eq!(
+            number_error("0x1ffff", 16, 16),
+            "value 0x1ffff does not fit in 16 bits"
+        );
+        assert_eq!(
+            number_error("70000", 10, 16),
+            "value 70000 does not fit in 16 bits"
+        );
+        assert_eq!(
+            number_error("0b111111111", 2, 8),
+            "value 0b111111111 does not fit in 8 bits"
+        );
+        // many digits do not overflow while checking
+        assert_eq!(
+            number_error("99999999999999999999", 10, 16),
+            "value 99999999999999999999 does not fit in 16 bits"
+        );
+    }
+
+    #[test]
+    fn number_without_digits() {
+        assert_eq!(number_error("0x", 16, 16), "expected digits in number `0x`");
+        assert_eq!(
+            number_error("0x1g", 16, 16),
+            "invalid digit `g` in base 16 number"
+        );
+        assert_eq!(
+            lex_one("0x"),
+            TokenKind::MalformedNumber(String::from("0x"))
+        );
+    }
+
+    #[test]
+    fn offsets_match_byte_positions() {
+        // `@` and `~` are unknown tokens, and `é` is 2 bytes
+        let unit = "not r0 @ ~ \u{e9}\n";
+        let starts = [0, 4, 7, 9, 11, 13];
+        let repeats = 10_000;
+        let src = unit.repeat(repeats);
+
+        let tokens = lex_all(Lexer::new(&src));
+        let (eof, tokens) = tokens.split_last().unwrap();
+        assert_eq!(tokens.len(), starts.len() * repeats);
+        for (idx, (kind, low, high)) in tokens.iter().enumerate() {
+            let expected = idx / starts.len() * unit.len() + starts[idx % starts.len()];
+            assert_eq!(*low as usize, expected, "{:?}", kind);
+            assert!(src.is_char_boundary(*high as usize));
+        }
+        assert_eq!(eof, &(TokenKind::EoF, src.len() as u32, src.len() as u32));
+    }
+
+    #[test]
+    fn offsets_past_u32_saturate() {
+        let far = u32::MAX as usize + 10;
+        let span = Span::from_offsets(far, far + 1, 0);
+        assert_eq!((span.low(), span.high()), (u32::MAX, u32::MAX));
+        let span = Span::from_offsets(5, far, 0);
+        assert_eq!((span.low(), span.high()), (5, u32::MAX));
     }
 }
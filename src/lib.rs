@@ -0,0 +1,1049 @@
+#![feature(debug_closure_helpers)]
+
+use std::io::{self, BufRead, Write};
+
+use log::*;
+
+use consts::ConstRegs;
+use diag::{Context, Diagnostic};
+use instruction::{Instruction, OpSize};
+use lexer::{Span, Token};
+use object::{Object, ObjectError};
+use options::{AssembleOptions, OutputMode};
+use parser::{InstructionSpec, Parser};
+use program::Program;
+use source::Source;
+
+pub mod analysis;
+pub mod codegen;
+pub mod consts;
+pub mod diag;
+pub mod disasm;
+pub mod docs;
+pub mod eval;
+pub mod instruction;
+pub mod lexer;
+pub mod lint;
+pub mod object;
+pub mod opt;
+pub mod options;
+pub mod parser;
+pub mod program;
+pub mod reader;
+pub mod source;
+pub mod util;
+pub mod validate;
+
+/// the output of assembling a program
+#[derive(Debug, Clone)]
+pub struct Assembled {
+    pub code: Vec<u16>,
+    pub consts: ConstRegs,
+    /// the span of the instruction that generated each word of `code`, or the dummy span
+    /// for padding
+    pub spans: Vec<Span>,
+    /// the estimated number of cycles to run every instruction once
+    pub cost: u32,
+}
+
+/// parses every instruction in the source of `ctx`, reporting any errors to `ctx`.
+pub fn parse(ctx: &mut Context, opts: &AssembleOptions) -> Program {
+    parse_continuing(ctx, opts, ConstRegs::default(), None).0
+}
+
+/// parses like [`parse`], continuing from the const registers and `.size` left by an
+/// earlier piece of the same program. returns the program and the size to continue with.
+fn parse_continuing(
+    ctx: &mut Context,
+    opts: &AssembleOptions,
+    consts: ConstRegs,
+    default_size: Option<OpSize>,
+) -> (Program, Option<OpSize>) {
+    // TODO: i dont like having to do this, but otherwise it requires self references
+    // maybe the source shouldn't be in ctx?
+    let src_str = ctx.source().src().to_owned();
+    let mut parser = configure(Parser::with_consts(src_str.as_str(), consts), opts)
+        .with_default_size(default_size);
+
+    let mut insts = Vec::new();
+    loop {
+        let inst = parser.parse_inst(ctx);
+        match inst {
+            Ok(Some(inst)) => insts.push(inst),
+            Ok(None) => {
+                break;
+            }
+            Err(_) => {
+                break;
+            }
+        }
+    }
+
+    let default_size = parser.default_size();
+    let program = Program::with_symbols(
+        insts,
+        parser.consts().clone(),
+        parser.all_macro_references(),
+        src_str,
+    );
+    (program, default_size)
+}
+
+/// applies the parsing options from `opts` to `parser`
+fn configure<'a>(parser: Parser<'a>, opts: &AssembleOptions) -> Parser<'a> {
+    parser
+        .with_register_sigils(opts.register_sigils)
+        .with_strict_selectors(opts.strict_selectors)
+        .with_ignore_newlines(opts.ignore_newlines)
+        .with_include_paths(opts.include_paths.clone())
+}
+
+/// parses exactly one instruction, such as a line typed into a REPL. anything other than
+/// whitespace and comments after the instruction is an error. returns every diagnostic
+/// if there were any errors.
+pub fn parse_instruction(
+    src: &str,
+    opts: &AssembleOptions,
+) -> Result<Instruction, Vec<Diagnostic>> {
+    let mut ctx = Context::new(Source::new(String::from("<input>"), src.to_string()));
+    let mut parser = configure(Parser::new(src), opts);
+    let inst = match parser.parse_inst(&mut ctx) {
+        Ok(Some(inst)) => Some(inst),
+        Ok(None) => {
+            ctx.add_diag(Diagnostic::new(
+                String::from("expected an instruction"),
+                Span::new(0, src.len() as u32),
+            ));
+            None
+        }
+        Err(()) => None,
+    };
+
+    if let Some(inst) = inst {
+        if let Some(span) = parser.skip_to_content() {
+            ctx.add_diag(Diagnostic::new(
+                format!("unexpected `{}` after the instruction", span.span_text(src)),
+                span,
+            ));
+        }
+        if !ctx.had_errs() {
+            return Ok(inst);
+        }
+    }
+    Err(ctx.take_diags())
+}
+
+/// parses the program in `tokens`, which were lexed from `src` by another tool.
+/// returns every diagnostic if there were any errors.
+pub fn parse_tokens(
+    src: &str,
+    tokens: &[Token],
+    opts: &AssembleOptions,
+) -> Result<Program, Vec<Diagnostic>> {
+    let mut ctx = Context::new(Source::new(String::from("<input>"), src.to_string()));
+    let mut parser = configure(Parser::from_tokens(src, tokens.to_vec()), opts);
+    let mut insts = Vec::new();
+    while let Ok(Some(inst)) = parser.parse_inst(&mut ctx) {
+        insts.push(inst);
+    }
+
+    if ctx.had_errs() {
+        Err(ctx.take_diags())
+    } else {
+        Ok(Program::with_symbols(
+            insts,
+            parser.consts().clone(),
+            parser.all_macro_references(),
+            src.to_string(),
+        ))
+    }
+}
+
+/// finds the span of every definition and use of `symbol` in `src`, for tools that rename
+/// symbols. the only symbols are macro names, so this is every `.macro` that defines
+/// `symbol` and every invocation of it. errors in `src` are ignored, but nothing after
+/// an error that stops parsing is searched. `src` is parsed with the options from `opts`
+/// so that the references are the same as when it is assembled.
+pub fn references(src: &str, symbol: &str, opts: &AssembleOptions) -> Vec<Span> {
+    let mut ctx = Context::new(Source::new(String::from("<input>"), src.to_string()));
+    parse(&mut ctx, opts).references(symbol).to_vec()
+}
+
+/// lists every instruction the parser accepts, with its operands and sizes
+pub fn instruction_set() -> &'static [InstructionSpec] {
+    parser::INSTRUCTION_SET
+}
+
+/// assembles the source of `ctx` into code, returning `None` if there were any errors.
+/// the diagnostics are left in `ctx` to be emitted by the caller.
+pub fn assemble(ctx: &mut Context, opts: &AssembleOptions) -> Option<Assembled> {
+    ctx.set_warnings_as_errors(opts.warnings_as_errors);
+    ctx.set_tab_width(opts.tab_width);
+    let mut program = parse(ctx, opts);
+    // included files are only known after parsing
+    lint::lint_source(ctx, &opts.lints);
+    if opts.optimize {
+        opt::optimize(program.instructions_mut());
+    }
+    let insts = program.instructions();
+    debug!("{:#?}", insts);
+
+    check_options(ctx, opts);
+
+    let (mut code, mut spans) = gen_sized(ctx, insts, 0, opts);
+    if ctx.had_errs() {
+        return None;
+    }
+    if let Some(pad_to) = opts.pad_to {
+        code.resize(pad_to / 2, u16::from_ne_bytes([opts.pad_byte; 2]));
+        spans.resize(code.len(), Span::DUMMY);
+    }
+    let cost = opts.cost_model.program_cost(insts);
+    Some(Assembled {
+        code,
+        consts: program.consts().clone(),
+        spans,
+        cost,
+    })
+}
+
+/// assembles the source of `ctx` into a hex dump of the code in the byte order from
+/// `opts`, returning `None` if there were any errors.
+pub fn assemble_hex(ctx: &mut Context, opts: &AssembleOptions) -> Option<String> {
+    let assembled = assemble(ctx, opts)?;
+    let bytes = codegen::to_bytes(assembled.code.as_slice(), opts.endianness);
+    Some(util::HexDump(bytes.as_slice()).to_string())
+}
+
+/// assembles the source of `ctx` into the Intel HEX format, with the code in the byte
+/// order from `opts` starting at address 0. returns `None` if there were any errors.
+pub fn assemble_ihex(ctx: &mut Context, opts: &AssembleOptions) -> Option<String> {
+    let assembled = assemble(ctx, opts)?;
+    let bytes = codegen::to_bytes(assembled.code.as_slice(), opts.endianness);
+    Some(util::IntelHex(bytes.as_slice()).to_string())
+}
+
+/// assembles the source of `ctx` into bytes in the output mode from `opts`, returning
+/// `None` if there were any errors
+pub fn assemble_binary(ctx: &mut Context, opts: &AssembleOptions) -> Option<Vec<u8>> {
+    let assembled = assemble(ctx, opts)?;
+    output_or_report(ctx, &assembled, opts)
+}
+
+/// formats assembled code as bytes in the output mode and byte order from `opts`.
+/// fails if the code is too large to be written as an object.
+pub fn output(
+    ctx: &Context,
+    assembled: &Assembled,
+    opts: &AssembleOptions,
+) -> Result<Vec<u8>, ObjectError> {
+    let bytes = || codegen::to_bytes(assembled.code.as_slice(), opts.endianness);
+    Ok(match opts.output_mode {
+        OutputMode::Words => {
+            format!("{:X}", util::ArrayPrinter(assembled.code.as_slice())).into_bytes()
+        }
+        OutputMode::HexDump => util::HexDump(bytes().as_slice()).to_string().into_bytes(),
+        OutputMode::IntelHex => util::IntelHex(bytes().as_slice()).to_string().into_bytes(),
+        OutputMode::Flat => bytes(),
+        OutputMode::Object => {
+            let object = Object::new(ctx, assembled, opts.endianness);
+            if opts.object_comments {
+                object.with_comments(ctx).to_bytes()?
+            } else {
+                object.to_bytes()?
+            }
+        }
+    })
+}
+
+/// formats assembled code like [`output`], reporting an error to `ctx` if it fails
+fn output_or_report(
+    ctx: &mut Context,
+    assembled: &Assembled,
+    opts: &AssembleOptions,
+) -> Option<Vec<u8>> {
+    output(ctx, assembled, opts)
+        .map_err(|e| {
+            ctx.add_diag(Diagnostic::new(
+                format!("cannot write the object: {}", e),
+                Span::DUMMY,
+            ));
+        })
+        .ok()
+}
+
+/// assembles the source of `ctx` into a map of the layout of the code, returning `None`
+/// if there were any errors. see [`layout_map`] for the format.
+pub fn assemble_map(ctx: &mut Context, opts: &AssembleOptions) -> Option<String> {
+    let assembled = assemble(ctx, opts)?;
+    Some(layout_map(ctx, &assembled))
+}
+
+/// lists the byte offset and size of each instruction in `assembled`, along with its
+/// location in the source, followed by the total size. padding is listed as a single
+/// entry.
+pub fn layout_map(ctx: &Context, assembled: &Assembled) -> String {
+    let mut map = String::from("offset   size  source\n");
+    let mut start = 0;
+    while start < assembled.spans.len() {
+        let span = assembled.spans[start];
+        let same = |other: &Span| {
+            (other.file(), other.low(), other.high()) == (span.file(), span.low(), span.high())
+        };
+        let len = assembled.spans[start..]
+            .iter()
+            .take_while(|other| same(other))
+            .count();
+        let source = if span.is_dummy() {
+            String::from("  <padding>\n")
+        } else {
+            diag::location(ctx, span)
+        };
+        map.push_str(&format!("{:#06X} {:>5}{}", start * 2, len * 2, source));
+        start += len;
+    }
+    map.push_str(&format!("total size: {} bytes\n", assembled.code.len() * 2));
+    map
+}
+
+/// assembles the source of `ctx` and writes the code to `out` in the output mode and byte
+/// order from `opts`, as [`output`] formats it. nothing is written if there are any errors. the diagnostics are left in `ctx` to be
+/// emitted by the caller.
+///
+/// returns the values of the const registers set by the program, or `None` if there
+/// were any errors.
+pub fn assemble_to(
+    ctx: &mut Context,
+    opts: &AssembleOptions,
+    out: &mut impl Write,
+) -> io::Result<Option<ConstRegs>> {
+    let Some(assembled) = assemble(ctx, opts) else {
+        return Ok(None);
+    };
+    let Some(bytes) = output_or_report(ctx, &assembled, opts) else {
+        return Ok(None);
+    };
+    out.write_all(&bytes)?;
+    Ok(Some(assembled.consts))
+}
+
+/// assembles `input` one line at a time, passing the bytes of each line to `emit` as soon
+/// as it is assembled, so that the whole program never needs to be in memory.
+/// diagnostics are emitted as each line finishes. once an error is encountered, no more
+/// code is emitted, but the rest of the input is still checked for errors.
+///
+/// this works because every instruction is contained in a single line and there are
+/// no labels, so there are never any forward references to resolve. macros cannot be
+/// used, since their definitions span multiple lines. for the same reason,
+/// `ignore_newlines` is an error, since an instruction cannot be split over lines.
+///
+/// returns the values of the const registers set by the program, or `None` if there
+/// were any errors.
+pub fn assemble_stream(
+    filename: &str,
+    mut input: impl BufRead,
+    opts: &AssembleOptions,
+    mut emit: impl FnMut(&[u8]),
+) -> io::Result<Option<ConstRegs>> {
+    let mut ctx = Context::new(Source::new(filename.to_owned(), String::new()));
+    check_options(&mut ctx, opts);
+    if opts.ignore_newlines {
+        ctx.add_diag(
+            Diagnostic::new(
+                String::from("newlines cannot be ignored when assembling a stream"),
+                Span::DUMMY,
+            )
+            .with_note(String::from(
+                "each line of a stream is assembled on its own",
+            )),
+        );
+    }
+    ctx.emit_diags();
+    let mut had_errs = ctx.had_errs();
+    let mut consts = ConstRegs::default();
+    let mut default_size = None;
+    // the number of bytes of code generated so far
+    let mut offset = 0;
+    let mut line_idx = 0;
+    loop {
+        // each line keeps its newline, so only a last line without one is linted as
+        // missing the final newline
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        let source = Source::new_partial(filename.to_owned(), line, line_idx);
+        line_idx += 1;
+        let mut ctx = Context::new(source);
+        ctx.set_warnings_as_errors(opts.warnings_as_errors);
+        ctx.set_tab_width(opts.tab_width);
+
+        let (mut program, size) = parse_continuing(&mut ctx, opts, consts, default_size);
+        default_size = size;
+        lint::lint_source(&mut ctx, &opts.lints);
+        if opts.optimize {
+            opt::optimize(program.instructions_mut());
+        }
+        consts = program.consts().clone();
+        let (code, _) = gen_sized(&mut ctx, program.instructions(), offset, opts);
+        offset += code.len() * 2;
+
+        ctx.emit_diags();
+        had_errs |= ctx.had_errs();
+        if !had_errs {
+            emit(codegen::to_bytes(code.as_slice(), opts.endianness).as_slice());
+        }
+    }
+
+    if let Some(pad_to) = opts.pad_to {
+        if !had_errs {
+            emit(vec![opts.pad_byte; pad_to.saturating_sub(offset)].as_slice());
+        }
+    }
+
+    Ok((!had_errs).then_some(consts))
+}
+
+/// reports options that cannot be used to assemble any program, so that every way of
+/// assembling rejects the same options
+fn check_options(ctx: &mut Context, opts: &AssembleOptions) {
+    if let Some(pad_to) = opts.pad_to.filter(|pad_to| pad_to % 2 != 0) {
+        ctx.add_diag(Diagnostic::new(
+            format!(
+                "cannot pad the program to {} bytes, the size must be a multiple of 2",
+                pad_to
+            ),
+            Span::DUMMY,
+        ));
+    }
+}
+
+/// generates code for `insts`, which start `offset` bytes into the program, reporting
+/// an error on the instruction that makes the program exceed the maximum size or the
+/// size it is padded to. returns the code and the span of the instruction that
+/// generated each word.
+fn gen_sized(
+    ctx: &mut Context,
+    insts: &[Instruction],
+    offset: usize,
+    opts: &AssembleOptions,
+) -> (Vec<u16>, Vec<Span>) {
+    let mut code = Vec::new();
+    let mut spans = Vec::new();
+    for inst in insts {
+        let start = offset + code.len() * 2;
+        code.extend(codegen::gen_inst(*inst));
+        spans.resize(code.len(), inst.span());
+        let end = offset + code.len() * 2;
+
+        if let Some(max_size) = opts.max_size {
+            // only report the instruction that crosses the limit
+            if start <= max_size && end > max_size {
+                ctx.add_diag(
+                    Diagnostic::new(
+                        format!("program exceeds the maximum size of {} bytes", max_size),
+                        inst.span(),
+                    )
+                    .with_note(format!("this instruction ends at byte {}", end)),
+                );
+            }
+        }
+
+        if let Some(pad_to) = opts.pad_to {
+            if start <= pad_to && end > pad_to {
+                ctx.add_diag(
+                    Diagnostic::new(
+                        format!("program exceeds the padded size of {} bytes", pad_to),
+                        inst.span(),
+                    )
+                    .with_note(format!("this instruction ends at byte {}", end)),
+                );
+            }
+        }
+    }
+    (code, spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+    use crate::diag::{DiagnosticSink, Severity};
+
+    /// assembles `src` in one go, returning the code as bytes and every diagnostic
+    fn assemble_src(src: &str, opts: &AssembleOptions) -> (Option<Vec<u8>>, Vec<Diagnostic>) {
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let bytes = assemble(&mut ctx, opts)
+            .map(|assembled| codegen::to_bytes(assembled.code.as_slice(), opts.endianness));
+        (bytes, ctx.take_diags())
+    }
+
+    /// assembles `src` through [`assemble_stream`], returning the code as bytes if there
+    /// were no errors
+    fn stream_src(src: &str, opts: &AssembleOptions) -> Option<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let consts = assemble_stream("test.s", src.as_bytes(), opts, |code| {
+            bytes.extend_from_slice(code)
+        })
+        .unwrap();
+        consts.map(|_| bytes)
+    }
+
+    #[test]
+    fn stream_large_program() {
+        let mut src = String::from(".const c1, 1, 2, 3, 4\n");
+        for idx in 0..10_000 {
+            src.push_str(match idx % 3 {
+                0 => "add.w r0, r0, c1\n",
+                1 => "lsl.b r1, 3\n",
+                _ => "mov [r2.x]+, r0.xy\n",
+            });
+        }
+        let opts = AssembleOptions::default();
+        let streamed = stream_src(&src, &opts).expect("program should assemble");
+        assert_eq!(streamed.len(), 10_000 * 2);
+        let (batch, diags) = assemble_src(&src, &opts);
+        assert!(diags.is_empty());
+        assert_eq!(batch.unwrap(), streamed);
+    }
+
+    #[test]
+    fn stream_matches_batch() {
+        let src = "add.w r0, r0, c1\nswizzle r1.wzyx\nmov r2.xy, [r3.x]\n";
+        for opts in [
+            AssembleOptions::default(),
+            AssembleOptions {
+                endianness: options::Endianness::Big,
+                pad_to: Some(16),
+                pad_byte: 0xAA,
+                ..AssembleOptions::default()
+            },
+        ] {
+            let (batch, diags) = assemble_src(src, &opts);
+            assert!(diags.is_empty());
+            assert_eq!(batch, stream_src(src, &opts));
+        }
+    }
+
+    #[test]
+    fn stream_lints_final_newline_once() {
+        let opts = AssembleOptions {
+            warnings_as_errors: true,
+            lints: options::Lints {
+                trailing_whitespace: true,
+                missing_final_newline: true,
+                ..options::Lints::default()
+            },
+            ..AssembleOptions::default()
+        };
+        // every line but the last ends in a newline, which is not a missing final newline
+        for src in [
+            "not r0\nnot r1\n",
+            "not r0\r\nnot r1\r\n",
+            "not r0\n\nnot r1\n",
+            "",
+        ] {
+            let (batch, diags) = assemble_src(src, &opts);
+            assert!(diags.is_empty(), "{:?}", src);
+            assert_eq!(stream_src(src, &opts), batch, "{:?}", src);
+        }
+
+        let src = "not r0\nnot r1";
+        let (batch, diags) = assemble_src(src, &opts);
+        assert_eq!(batch, None);
+        assert_eq!(
+            diags.iter().map(|d| d.msg()).collect::<Vec<_>>(),
+            ["missing newline at end of file"]
+        );
+        assert_eq!(stream_src(src, &opts), None);
+    }
+
+    #[test]
+    fn stream_rejects_ignore_newlines() {
+        let src = "add.w r0,\n r0,\n c1\n";
+        let opts = AssembleOptions {
+            ignore_newlines: true,
+            ..AssembleOptions::default()
+        };
+        assert!(assemble_src(src, &opts).0.is_some());
+        assert_eq!(stream_src(src, &opts), None);
+        // even a program that does not need it is rejected
+        assert_eq!(stream_src("not r0\n", &opts), None);
+    }
+
+    #[test]
+    fn stream_rejects_same_options_as_batch() {
+        let src = "add.w r0, r0, c1\n";
+        let opts = AssembleOptions {
+            pad_to: Some(7),
+            ..AssembleOptions::default()
+        };
+        let (batch, diags) = assemble_src(src, &opts);
+        assert_eq!(batch, None);
+        assert_eq!(
+            diags.iter().map(|d| d.msg()).collect::<Vec<_>>(),
+            ["cannot pad the program to 7 bytes, the size must be a multiple of 2"]
+        );
+        assert_eq!(stream_src(src, &opts), None);
+
+        // an error in the program stops both paths
+        let src = "add.w r0, r0, c1\nadd.q r0, r0, c1\n";
+        let opts = AssembleOptions::default();
+        assert_eq!(assemble_src(src, &opts).0, None);
+        assert_eq!(stream_src(src, &opts), None);
+    }
+
+    #[test]
+    fn warnings_as_errors_option() {
+        let src = "lsl.b r0, 8\n";
+        let (bytes, diags) = assemble_src(src, &AssembleOptions::default());
+        assert!(bytes.is_some());
+        assert_eq!(diags[0].severity(), diag::Severity::Warning);
+
+        let opts = AssembleOptions {
+            warnings_as_errors: true,
+            ..AssembleOptions::default()
+        };
+        let (bytes, diags) = assemble_src(src, &opts);
+        assert_eq!(bytes, None);
+        assert_eq!(diags[0].severity(), diag::Severity::Error);
+    }
+
+    #[test]
+    fn endianness_and_max_size_options() {
+        let src = "add.w r0, r0, c1\nadd.w r0, r0, c1\n";
+        let (little, _) = assemble_src(src, &AssembleOptions::default());
+        let opts = AssembleOptions {
+            endianness: options::Endianness::Big,
+            ..AssembleOptions::default()
+        };
+        let (big, _) = assemble_src(src, &opts);
+        assert_eq!(little.unwrap(), [0x09, 0x81, 0x09, 0x81]);
+        assert_eq!(big.unwrap(), [0x81, 0x09, 0x81, 0x09]);
+
+        let opts = AssembleOptions {
+            max_size: Some(2),
+            ..AssembleOptions::default()
+        };
+        let (bytes, diags) = assemble_src(src, &opts);
+        assert_eq!(bytes, None);
+        assert_eq!(
+            diags.iter().map(|d| d.msg()).collect::<Vec<_>>(),
+            ["program exceeds the maximum size of 2 bytes"]
+        );
+    }
+
+    /// a small xorshift generator, so that the random inputs are the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// assembles `src` with several combinations of options, which must never panic
+    fn assemble_all_modes(src: &str) {
+        for (ignore_newlines, register_sigils, strict_selectors, optimize) in
+            [(false, false, false, false), (true, true, true, true)]
+        {
+            let opts = AssembleOptions {
+                ignore_newlines,
+                register_sigils,
+                strict_selectors,
+                optimize,
+                ..AssembleOptions::default()
+            };
+            let mut ctx = Context::new(Source::new(String::from("fuzz.s"), src.to_string()));
+            assemble(&mut ctx, &opts);
+            // formatting the diagnostics must not panic either
+            let diags = ctx.take_diags();
+            for d in &diags {
+                diag::location(&ctx, d.span());
+                for span in d.notes().iter().filter_map(|note| note.span()) {
+                    diag::location(&ctx, span);
+                }
+            }
+            ctx.format_json(diags.as_slice());
+            parse_instruction(src, &opts).ok();
+        }
+    }
+
+    #[test]
+    fn fuzz_arbitrary_bytes() {
+        let mut rng = Rng(0x5EED_1234_ABCD_0001);
+        for _ in 0..5000 {
+            let len = rng.below(64);
+            let bytes = (0..len).map(|_| rng.next() as u8).collect::<Vec<_>>();
+            assemble_all_modes(&String::from_utf8_lossy(&bytes));
+        }
+    }
+
+    #[test]
+    fn fuzz_token_soup() {
+        // pieces of valid syntax, so that the random programs get past the lexer and
+        // into every part of the parser
+        const PIECES: &[&str] = &[
+            "mov", "swizzle", "add", "sub_sat", "cmpeq", "lsl", "asr", "rol", "not", "zero", "and",
+            "r0", "r7", "ri", "c3", "%r1", "$2", ".b", ".w", ".x", ".xyzw", ".wzyx", ".xx", ".",
+            ",", "[", "]", "]+", "{", "}", "*", "+", "-", "0", "15", "16", "0x1ffff", "0b", "0o9",
+            "65535", "\"", "\"a\"", "#c", "\n", "\t", " ", "é", ".const", ".macro", ".endm",
+            ".size", ".include", "m", "\\a", "\r\n",
+        ];
+        let mut rng = Rng(0xF00D_F00D_0042_0042);
+        for _ in 0..5000 {
+            let len = rng.below(24);
+            let src = (0..len)
+                .map(|_| PIECES[rng.below(PIECES.len())])
+                .collect::<Vec<_>>()
+                .join(if rng.below(2) == 0 { " " } else { "" });
+            assemble_all_modes(&src);
+        }
+    }
+
+    #[test]
+    fn pad_to_size() {
+        let opts = AssembleOptions {
+            pad_to: Some(8),
+            pad_byte: 0xCC,
+            ..AssembleOptions::default()
+        };
+        let (bytes, diags) = assemble_src("add.w r0, r0, c1\n", &opts);
+        assert!(diags.is_empty());
+        assert_eq!(
+            bytes.unwrap(),
+            [0x09, 0x81, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]
+        );
+
+        // a program that is exactly the padded size is not padded
+        let opts = AssembleOptions {
+            pad_to: Some(2),
+            ..opts
+        };
+        let (bytes, _) = assemble_src("add.w r0, r0, c1\n", &opts);
+        assert_eq!(bytes.unwrap(), [0x09, 0x81]);
+    }
+
+    #[test]
+    fn pad_to_overflow() {
+        let opts = AssembleOptions {
+            pad_to: Some(4),
+            ..AssembleOptions::default()
+        };
+        let src = "not r0\nnot r1\nnot r2\nnot r3\n";
+        let (bytes, diags) = assemble_src(src, &opts);
+        assert_eq!(bytes, None);
+        assert_eq!(
+            diags.iter().map(|d| d.msg()).collect::<Vec<_>>(),
+            ["program exceeds the padded size of 4 bytes"]
+        );
+        // only the instruction that crosses the size is reported
+        assert_eq!(diags[0].span().span_text(src), "not r2");
+        assert_eq!(diags[0].notes()[0].msg(), "this instruction ends at byte 6");
+    }
+
+    #[test]
+    fn assemble_to_writer() {
+        let src = ".const c1, 1, 2\nadd.w r0, r0, c1\nnot r0\n";
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let mut out = Vec::new();
+        let consts = assemble_to(&mut ctx, &AssembleOptions::default(), &mut out).unwrap();
+        assert_eq!(out, [0x09, 0x81, 0x3C, 0x80]);
+        assert_eq!(consts.unwrap().get(1), Some([1, 2, 0, 0]));
+        assert_eq!(Some(out), assemble_src(src, &AssembleOptions::default()).0);
+
+        // nothing is written if there are errors
+        let mut ctx = Context::new(Source::new(
+            String::from("test.s"),
+            String::from("not c0\n"),
+        ));
+        let mut out = Vec::new();
+        let consts = assemble_to(&mut ctx, &AssembleOptions::default(), &mut out).unwrap();
+        assert_eq!(consts, None);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn assemble_to_uses_output_mode() {
+        let src = "not r0\n";
+        for output_mode in [
+            OutputMode::Words,
+            OutputMode::HexDump,
+            OutputMode::IntelHex,
+            OutputMode::Flat,
+            OutputMode::Object,
+        ] {
+            let opts = AssembleOptions {
+                output_mode,
+                ..AssembleOptions::default()
+            };
+            let ctx = || Context::new(Source::new(String::from("test.s"), src.to_string()));
+            let mut out = Vec::new();
+            assemble_to(&mut ctx(), &opts, &mut out).unwrap().unwrap();
+            let expected = assemble_binary(&mut ctx(), &opts).unwrap();
+            assert_eq!(out, expected, "{:?}", output_mode);
+            if output_mode == OutputMode::Words {
+                assert_eq!(out, b"[803C]");
+            }
+        }
+    }
+
+    /// counts the errors reported to it, sharing the counts with the test
+    struct CountingSink(Rc<Cell<(usize, usize)>>);
+
+    impl DiagnosticSink for CountingSink {
+        fn emit(&mut self, diag: Diagnostic) {
+            let (errors, total) = self.0.get();
+            let is_err = diag.severity() == Severity::Error;
+            self.0.set((errors + usize::from(is_err), total + 1));
+        }
+    }
+
+    #[test]
+    fn custom_sink_receives_every_diagnostic() {
+        let counts = Rc::new(Cell::new((0, 0)));
+        let src = "not r0\nbogus r0\nlsl.b r0, 8\n";
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()))
+            .with_sink(CountingSink(Rc::clone(&counts)));
+        let opts = AssembleOptions::default();
+        assert!(assemble(&mut ctx, &opts).is_none());
+        assert!(ctx.take_diags().is_empty());
+        let (_, expected) = assemble_src(src, &opts);
+        let expected_errors = expected
+            .iter()
+            .filter(|d| d.severity() == Severity::Error)
+            .count();
+        assert!(expected_errors > 0);
+        assert_eq!(counts.get(), (expected_errors, expected.len()));
+        assert!(ctx.had_errs());
+    }
+
+    #[test]
+    fn hex_dump_of_a_program() {
+        let src = "not r0\nadd.w r0, r0, c1\n";
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let dump = assemble_hex(&mut ctx, &AssembleOptions::default()).unwrap();
+        // `not r0` is 0x803C and `add.w r0, r0, c1` is 0x8109, in little endian
+        assert_eq!(
+            dump,
+            "00000000  3c 80 09 81                                       |<...|\n00000004\n"
+        );
+    }
+
+    #[test]
+    fn output_modes() {
+        let src = "not r0\n";
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let assembled = assemble(&mut ctx, &AssembleOptions::default()).unwrap();
+        let output = |output_mode| {
+            let opts = AssembleOptions {
+                output_mode,
+                ..AssembleOptions::default()
+            };
+            output(&ctx, &assembled, &opts).unwrap()
+        };
+        assert_eq!(output(OutputMode::Words), b"[803C]");
+        assert_eq!(output(OutputMode::Flat), [0x3C, 0x80]);
+        assert_eq!(
+            output(OutputMode::HexDump),
+            util::HexDump(&[0x3C, 0x80]).to_string().into_bytes()
+        );
+        assert_eq!(
+            output(OutputMode::IntelHex),
+            util::IntelHex(&[0x3C, 0x80]).to_string().into_bytes()
+        );
+        let object = Object::from_bytes(&output(OutputMode::Object)).unwrap();
+        assert_eq!(object.code, assembled.code);
+        assert!(object.comments.is_none());
+    }
+
+    #[test]
+    fn object_output_errors_are_reported() {
+        let opts = AssembleOptions {
+            output_mode: OutputMode::Object,
+            ..AssembleOptions::default()
+        };
+        let mut ctx = Context::new(Source::new("a".repeat(70_000), String::from("not r0\n")));
+        assert_eq!(assemble_binary(&mut ctx, &opts), None);
+        assert_eq!(
+            ctx.take_diags()
+                .iter()
+                .map(|d| d.msg().to_string())
+                .collect::<Vec<_>>(),
+            ["cannot write the object: file name is 70000 bytes long, but the maximum is 65535"]
+        );
+    }
+
+    fn parse_errors(src: &str) -> Vec<String> {
+        parse_instruction(src, &AssembleOptions::default())
+            .unwrap_err()
+            .iter()
+            .map(|d| d.msg().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn parse_single_instruction() {
+        let inst = parse_instruction("add.w r0, r0, c1", &AssembleOptions::default()).unwrap();
+        assert_eq!(inst.kind().to_string(), "add.w r0, r0, c1");
+        // trailing whitespace, comments, and newlines are allowed
+        let inst =
+            parse_instruction("  not r0  # invert\n\n", &AssembleOptions::default()).unwrap();
+        assert_eq!(inst.kind().to_string(), "not r0");
+    }
+
+    #[test]
+    fn parse_instruction_uses_options() {
+        assert!(parse_instruction("not %r0", &AssembleOptions::default()).is_err());
+        let sigils = AssembleOptions {
+            register_sigils: true,
+            ..AssembleOptions::default()
+        };
+        let inst = parse_instruction("not %r0", &sigils).unwrap();
+        assert_eq!(inst.kind().to_string(), "not r0");
+
+        let strict = AssembleOptions {
+            strict_selectors: true,
+            ..AssembleOptions::default()
+        };
+        assert!(parse_instruction("mov r0.x, [r1]", &AssembleOptions::default()).is_ok());
+        assert!(parse_instruction("mov r0.x, [r1]", &strict).is_err());
+
+        let ignore = AssembleOptions {
+            ignore_newlines: true,
+            ..AssembleOptions::default()
+        };
+        assert!(parse_instruction("add.w r0,\nr0,\nc1", &AssembleOptions::default()).is_err());
+        let inst = parse_instruction("add.w r0,\nr0,\nc1", &ignore).unwrap();
+        assert_eq!(inst.kind().to_string(), "add.w r0, r0, c1");
+    }
+
+    #[test]
+    fn parse_instruction_rejects_extra_content() {
+        assert_eq!(
+            parse_errors("not r0 r1"),
+            ["unexpected `r1` after the instruction"]
+        );
+        assert_eq!(
+            parse_errors("not r0\nnot r1"),
+            ["unexpected `not` after the instruction"]
+        );
+        assert_eq!(
+            parse_errors("# only a comment"),
+            ["expected an instruction"]
+        );
+        assert_eq!(parse_errors(""), ["expected an instruction"]);
+    }
+
+    #[test]
+    fn parse_hand_built_tokens() {
+        use lexer::TokenKind::*;
+        let src = "not r0\nnot r1\n";
+        let token = |kind, low, high| Token::new(kind, Span::new(low, high));
+        let ident = |name: &str, low, high| token(Ident(name.to_string()), low, high);
+        let tokens = [
+            ident("not", 0, 3),
+            ident("r0", 4, 6),
+            token(Newline, 6, 7),
+            ident("not", 7, 10),
+            ident("r1", 11, 13),
+            token(Newline, 13, 14),
+        ];
+        let program = parse_tokens(src, &tokens, &AssembleOptions::default()).unwrap();
+        assert_eq!(program.src(), src);
+        let insts = program.instructions();
+        let texts = insts
+            .iter()
+            .map(|i| i.kind().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, ["not r0", "not r1"]);
+        let spans = insts
+            .iter()
+            .map(|i| i.span().span_text(src))
+            .collect::<Vec<_>>();
+        assert_eq!(spans, ["not r0", "not r1"]);
+
+        let diags = parse_tokens(
+            src,
+            &[ident("not", 0, 3), token(Number(5), 4, 6)],
+            &AssembleOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(diags[0].span().span_text(src), "r0");
+    }
+
+    #[test]
+    fn macro_references() {
+        let src = ".macro bump reg\n    add.w reg, reg, c1\n.endm\n\
+                   .macro twice reg\n    bump reg\n    bump reg\n.endm\n\
+                   bump r0\ntwice r1\n";
+        let texts = |symbol| {
+            references(src, symbol, &AssembleOptions::default())
+                .iter()
+                .map(|span| (span.low(), span.span_text(src)))
+                .collect::<Vec<_>>()
+        };
+        let bump = texts("bump");
+        assert_eq!(bump.len(), 4);
+        assert!(bump.iter().all(|(_, text)| *text == "bump"));
+        // the definition comes first, followed by every use in source order
+        let offsets = bump
+            .iter()
+            .map(|(low, _)| *low as usize)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            offsets[0],
+            src.find(".macro bump").unwrap() + ".macro ".len()
+        );
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(offsets[3], src.find("bump r0").unwrap());
+
+        assert_eq!(texts("twice").len(), 2);
+        assert!(texts("reg").is_empty());
+        assert!(texts("missing").is_empty());
+    }
+
+    #[test]
+    fn macro_references_use_options() {
+        let src = ".macro inv reg\n    not reg\n.endm\ninv %r0\n";
+        let sigils = AssembleOptions {
+            register_sigils: true,
+            ..AssembleOptions::default()
+        };
+        assert_eq!(references(src, "inv", &sigils).len(), 2);
+        // macros cannot be defined when newlines are ignored, so there are no references
+        let ignore = AssembleOptions {
+            ignore_newlines: true,
+            ..AssembleOptions::default()
+        };
+        assert!(references(src, "inv", &ignore).is_empty());
+    }
+
+    #[test]
+    fn layout_map_with_padding() {
+        let src = "not r0\nadd.w r0, r0, c1\n";
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let opts = AssembleOptions {
+            pad_to: Some(16),
+            ..AssembleOptions::default()
+        };
+        let map = assemble_map(&mut ctx, &opts).unwrap();
+        // there are no labels or `.align`, so padding is the only layout that is not code
+        assert_eq!(
+            map,
+            "offset   size  source\n\
+             0x0000     2  test.s:1:0  not r0\n\
+             0x0002     2  test.s:2:0  add.w r0, r0, c1\n\
+             0x0004    12  <padding>\n\
+             total size: 16 bytes\n"
+        );
+    }
+}
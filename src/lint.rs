@@ -0,0 +1,202 @@
+use crate::{
+    diag::{Context, Diagnostic},
+    lexer::Span,
+    options::Lints,
+    reader::{Reader, TokenKind},
+};
+
+/// runs the enabled style lints over the raw source of every file in `ctx`, including
+/// files added by `.include`, reporting warnings.
+/// these need to see whitespace, so they work on the tokens from the reader rather
+/// than the lexer.
+pub fn lint_source(ctx: &mut Context, lints: &Lints) {
+    for file in 0..ctx.files().len() as u32 {
+        lint_file(ctx, file, lints);
+    }
+}
+
+/// runs the enabled style lints over the raw source of `file`. a source that is only
+/// part of a file must keep the newline at the end of each of its lines, otherwise it
+/// is reported as missing a final newline.
+pub fn lint_file(ctx: &mut Context, file: u32, lints: &Lints) {
+    if !(lints.mixed_indentation || lints.trailing_whitespace || lints.missing_final_newline) {
+        return;
+    }
+
+    let src = ctx.file(file).src().to_owned();
+    let mut reader = Reader::new(src.as_str());
+    let mut pos = 0;
+    // the kind and span of the previous token
+    let mut prev: Option<(TokenKind, Span)> = None;
+    loop {
+        let token = reader.next_token();
+        let span = Span::from_offsets(pos, pos + token.len(), file);
+        let text = span.span_text(src.as_str());
+        pos += token.len();
+        let line_start = prev.is_none_or(|(kind, _)| kind == TokenKind::Newline);
+
+        match token.kind() {
+            TokenKind::Whitespace
+                if lints.mixed_indentation
+                    && line_start
+                    && text.contains(' ')
+                    && text.contains('\t') =>
+            {
+                ctx.add_diag(Diagnostic::new_warning(
+                    String::from("indentation mixes tabs and spaces"),
+                    span,
+                ));
+            }
+            TokenKind::Newline | TokenKind::EoF if lints.trailing_whitespace => {
+                if let Some(trailing) = prev.and_then(|prev| trailing_whitespace(&src, prev)) {
+                    ctx.add_diag(Diagnostic::new_warning(
+                        String::from("trailing whitespace"),
+                        trailing,
+                    ));
+                }
+            }
+            _ => {}
+        }
+
+        if token.kind() == TokenKind::EoF {
+            if lints.missing_final_newline
+                && prev.is_some_and(|(kind, _)| kind != TokenKind::Newline)
+            {
+                ctx.add_diag(Diagnostic::new_warning(
+                    String::from("missing newline at end of file"),
+                    span,
+                ));
+            }
+            break;
+        }
+
+        prev = Some((token.kind(), span));
+    }
+}
+
+/// gets the span of the whitespace at the end of a token that ends a line, if there is
+/// any. a single `\r` before the newline is not considered trailing whitespace.
+fn trailing_whitespace(src: &str, (kind, span): (TokenKind, Span)) -> Option<Span> {
+    if !matches!(kind, TokenKind::Whitespace | TokenKind::Comment) {
+        return None;
+    }
+
+    let text = span.span_text(src);
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    let trimmed = text.trim_end();
+    (trimmed.len() < text.len()).then(|| {
+        Span::new_in_file(
+            span.low() + trimmed.len() as u32,
+            span.low() + text.len() as u32,
+            span.file(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::Source;
+
+    /// runs `lints` over `src`, returning every warning
+    fn lint(src: &str, lints: &Lints) -> Vec<Diagnostic> {
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        lint_source(&mut ctx, lints);
+        ctx.take_diags()
+    }
+
+    fn indentation() -> Lints {
+        Lints {
+            mixed_indentation: true,
+            ..Lints::default()
+        }
+    }
+
+    #[test]
+    fn consistent_indentation() {
+        assert!(lint("\tadd.w r0, r0, c1\n\t\tnot r0\n", &indentation()).is_empty());
+        assert!(lint("    add.w r0, r0, c1\n  not r0\n", &indentation()).is_empty());
+        // tabs and spaces may be used on different lines, or after the indentation
+        assert!(lint("\tnot r0\n  not r1\nnot r0 \t# comment\n", &indentation()).is_empty());
+    }
+
+    #[test]
+    fn mixed_indentation() {
+        let src = "not r0\n \tnot r1\n\t not r2\n";
+        let diags = lint(src, &indentation());
+        assert_eq!(diags.len(), 2);
+        assert!(diags
+            .iter()
+            .all(|d| d.msg() == "indentation mixes tabs and spaces"));
+        assert_eq!(diags[0].span().span_text(src), " \t");
+        assert_eq!(diags[1].span().span_text(src), "\t ");
+
+        // the lint only runs when it is enabled
+        assert!(lint(src, &Lints::default()).is_empty());
+    }
+
+    fn endings() -> Lints {
+        Lints {
+            trailing_whitespace: true,
+            missing_final_newline: true,
+            ..Lints::default()
+        }
+    }
+
+    #[test]
+    fn clean_file() {
+        let src = "not r0\r\n# comment\n\tnot r1\n\n";
+        assert!(lint(src, &endings()).is_empty());
+    }
+
+    #[test]
+    fn trailing_whitespace() {
+        let src = "not r0  \nnot r1 # comment \t\r\nnot r2\t";
+        let diags = lint(src, &endings());
+        let found = diags
+            .iter()
+            .map(|d| (d.msg(), d.span().span_text(src)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("trailing whitespace", "  "),
+                ("trailing whitespace", " \t"),
+                ("trailing whitespace", "\t"),
+                ("missing newline at end of file", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        let lints = Lints {
+            missing_final_newline: true,
+            ..Lints::default()
+        };
+        let diags = lint("not r0\nnot r1", &lints);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].msg(), "missing newline at end of file");
+        assert_eq!(diags[0].span().low(), 13);
+        // an empty file does not need a newline
+        assert!(lint("", &lints).is_empty());
+    }
+
+    #[test]
+    fn lints_included_files() {
+        let mut ctx = Context::new(Source::new(
+            String::from("main.s"),
+            String::from("not r0\n"),
+        ));
+        let file = ctx.add_file(Source::new(
+            String::from("inc.s"),
+            String::from("not r1 \n"),
+        ));
+        lint_source(&mut ctx, &endings());
+        let diags = ctx.take_diags();
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].msg(), "trailing whitespace");
+        assert_eq!(diags[0].span().file(), file);
+        assert_eq!(diags[0].span().span_text(ctx.file(file).src()), " ");
+    }
+}
@@ -1,24 +1,15 @@
-#![feature(debug_closure_helpers)]
-
 use clap::Parser as _;
 use eyre::Context as _;
 use log::*;
 
-use diag::Context;
-use parser::Parser;
-use source::Source;
 use std::fs;
 use std::path::PathBuf;
-use util::ArrayPrinter;
-
-mod codegen;
-mod diag;
-mod instruction;
-mod lexer;
-mod parser;
-mod reader;
-mod source;
-mod util;
+use wavevm_assembler::{
+    diag::Context,
+    options::{AssembleOptions, Lints, OutputMode},
+    source::Source,
+    util::ArrayPrinter,
+};
 
 #[derive(clap::Parser)]
 #[command(about = "WaveVM Assembly Compiler", long_about = None)]
@@ -29,9 +20,64 @@ struct Cli {
     /// Output file path, only logs to stdout if not set
     #[arg(short, long)]
     output: Option<PathBuf>,
+    /// Print diagnostics as a JSON array instead of as text
+    #[arg(long)]
+    json_diagnostics: bool,
+    /// Include the comment before each instruction in object output
+    #[arg(long)]
+    object_comments: bool,
+    /// Write a map of the offset and size of each instruction to this path
+    #[arg(long)]
+    map: Option<PathBuf>,
+    /// Format of the output file
+    #[arg(short, long, value_enum, default_value_t = OutputMode::Words)]
+    format: OutputMode,
     /// Log level, valid values are: OFF, ERROR, WARN, INFO, DEBUG, TRACE
     #[arg(short, long, default_value_t = LevelFilter::Info)]
     log_level: LevelFilter,
+    /// Maximum size of the compiled program in bytes
+    #[arg(long)]
+    max_size: Option<usize>,
+    /// Pad the output to this many bytes
+    #[arg(long)]
+    pad_to: Option<usize>,
+    /// The byte to pad the output with
+    #[arg(long, default_value_t = 0)]
+    pad_byte: u8,
+    /// Number of columns between tab stops when reporting columns
+    #[arg(long, default_value_t = 1)]
+    tab_width: u32,
+    /// Report all warnings as errors
+    #[arg(short = 'W', long)]
+    warnings_as_errors: bool,
+    /// Run optimization passes
+    #[arg(short = 'O', long)]
+    optimize: bool,
+    /// Allow register names to be written with a `%` or `$` sigil, and `$0` for `r0`
+    #[arg(long)]
+    register_sigils: bool,
+    /// Require every selector to be written, so that `[r0]` must be `[r0.x]`
+    #[arg(long)]
+    strict_selectors: bool,
+    /// Treat newlines like whitespace, so instructions do not need their own lines
+    #[arg(long)]
+    ignore_newlines: bool,
+    /// Directory to search for included files, may be used multiple times
+    #[arg(short = 'I', long = "include-path")]
+    include_paths: Vec<PathBuf>,
+    /// Enable an optional style lint, may be used multiple times
+    #[arg(long = "lint", value_enum)]
+    lints: Vec<LintArg>,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LintArg {
+    /// Indentation that mixes tabs and spaces
+    MixedIndentation,
+    /// Whitespace at the end of a line
+    TrailingWhitespace,
+    /// A file that does not end with a newline
+    MissingFinalNewline,
 }
 
 fn main() -> eyre::Result<()> {
@@ -49,37 +95,58 @@ fn main() -> eyre::Result<()> {
     let source = Source::new_from_file(cli.input)?;
     let mut ctx = Context::new(source);
 
-    // TODO: i dont like having to do this, but otherwise it requires self references
-    // maybe the source shouldn't be in ctx?
-    let src_str = ctx.source().src().to_owned();
-    let mut parser = Parser::new(src_str.as_str());
-
-    let mut insts = Vec::new();
-    loop {
-        let inst = parser.parse_inst(&mut ctx);
-        match inst {
-            Ok(Some(inst)) => insts.push(inst),
-            Ok(None) => {
-                break;
-            }
-            Err(_) => {
-                break;
-            }
+    let mut lints = Lints::default();
+    for lint in cli.lints {
+        match lint {
+            LintArg::MixedIndentation => lints.mixed_indentation = true,
+            LintArg::TrailingWhitespace => lints.trailing_whitespace = true,
+            LintArg::MissingFinalNewline => lints.missing_final_newline = true,
         }
     }
 
-    if ctx.had_errs() {
-        ctx.emit_errs();
-        error!("failed due to previous errors");
+    let opts = AssembleOptions {
+        output_mode: cli.format,
+        object_comments: cli.object_comments,
+        max_size: cli.max_size,
+        pad_to: cli.pad_to,
+        pad_byte: cli.pad_byte,
+        warnings_as_errors: cli.warnings_as_errors,
+        tab_width: cli.tab_width,
+        optimize: cli.optimize,
+        register_sigils: cli.register_sigils,
+        strict_selectors: cli.strict_selectors,
+        ignore_newlines: cli.ignore_newlines,
+        include_paths: cli.include_paths,
+        lints,
+        ..AssembleOptions::default()
+    };
+
+    let assembled = wavevm_assembler::assemble(&mut ctx, &opts);
+
+    if cli.json_diagnostics {
+        ctx.emit_diags_json();
     } else {
-        debug!("{:#?}", insts);
-        let code = codegen::gen(insts.as_slice());
-        let printer = ArrayPrinter(code.as_slice());
-        info!("{:X}", printer);
+        ctx.emit_diags();
+    }
+    if let Some(assembled) = assembled {
+        for (idx, value) in assembled.consts.iter() {
+            info!("c{}: {:X}", idx, ArrayPrinter(&value));
+        }
+        info!("{:X}", ArrayPrinter(assembled.code.as_slice()));
+        info!("estimated cost: {} cycles", assembled.cost);
+        if let Some(map) = cli.map {
+            fs::write(&map, wavevm_assembler::layout_map(&ctx, &assembled))
+                .context("failed to write map file")?;
+            info!("Wrote map to \"{}\"", map.display())
+        }
         if let Some(output) = cli.output {
-            fs::write(&output, format!("{:X}", printer)).context("failed to write output file")?;
-            info!("Wrote compiled hex to \"{}\"", output.display())
+            let contents = wavevm_assembler::output(&ctx, &assembled, &opts)
+                .context("failed to format output")?;
+            fs::write(&output, contents).context("failed to write output file")?;
+            info!("Wrote compiled output to \"{}\"", output.display())
         }
+    } else {
+        error!("failed due to previous errors");
     }
 
     Ok(())
@@ -0,0 +1,393 @@
+//! the object format, which contains the code along with the const register values and
+//! the spans that each word of code was generated from, for loaders and debuggers.
+//!
+//! every integer in the header and tables is little endian. the layout is:
+//! - the magic `WVMO`
+//! - the version, a `u8`, currently 1
+//! - the flags, a `u8`. bit 0 is set if the code is big endian.
+//! - the const registers: a `u8` with bit `n` set if `cn` has a value, followed by the 4
+//!   `u16` elements of each register that has a value, in order
+//! - the files: a `u16` count, followed by a `u16` length and the UTF-8 name of each file
+//! - the code: a `u32` word count, followed by the words in the byte order from the flags
+//! - the spans: for each word of code, its `u16` file id, `u32` low, and `u32` high.
+//!   padding has the dummy span, with a low of `u32::MAX` and a high of 0.
+//! - the comments, only if bit 1 of the flags is set: for each word of code, a `u16`
+//!   length and the UTF-8 text of the comment before its instruction, which is empty if
+//!   there is no comment
+//!
+//! the program has no labels, so there is no symbol table.
+
+use crate::{
+    consts::ConstRegs, diag::Context, docs, instruction::MAX_REG_IDX, lexer::Span,
+    options::Endianness, Assembled,
+};
+
+const MAGIC: &[u8; 4] = b"WVMO";
+const VERSION: u8 = 1;
+const FLAG_BIG_ENDIAN: u8 = 0b01;
+const FLAG_COMMENTS: u8 = 0b10;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ObjectError {
+    #[error("missing object magic")]
+    BadMagic,
+    #[error("unsupported object version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unexpected end of object")]
+    Truncated,
+    #[error("file name is not valid UTF-8")]
+    InvalidFilename,
+    #[error("comment is not valid UTF-8")]
+    InvalidComment,
+    #[error("span refers to file {0}, which does not exist")]
+    InvalidFile(u16),
+    #[error("{0} unexpected bytes after the end of the object")]
+    TrailingBytes(usize),
+    #[error("too many files, found {0} but the maximum is {max}", max = u16::MAX)]
+    TooManyFiles(usize),
+    #[error("file name is {0} bytes long, but the maximum is {max}", max = u16::MAX)]
+    FilenameTooLong(usize),
+    #[error("comment is {0} bytes long, but the maximum is {max}", max = u16::MAX)]
+    CommentTooLong(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub endianness: Endianness,
+    pub consts: ConstRegs,
+    pub code: Vec<u16>,
+    /// the names of the files that spans refer to, indexed by file id
+    pub files: Vec<String>,
+    /// the span that each word of `code` was generated from
+    pub spans: Vec<Span>,
+    /// the comment before the instruction that generated each word of `code`, if
+    /// comments are included
+    pub comments: Option<Vec<Option<String>>>,
+}
+
+impl Object {
+    pub fn new(ctx: &Context, assembled: &Assembled, endianness: Endianness) -> Self {
+        Self {
+            endianness,
+            consts: assembled.consts.clone(),
+            code: assembled.code.clone(),
+            files: ctx
+                .files()
+                .iter()
+                .map(|f| f.filename().to_string())
+                .collect(),
+            spans: assembled.spans.clone(),
+            comments: None,
+        }
+    }
+
+    /// includes the comment before each instruction in its source file, as found by
+    /// [`doc_comments`](crate::docs::doc_comments). `ctx` must be the context the code
+    /// was assembled in.
+    pub fn with_comments(mut self, ctx: &Context) -> Self {
+        let blocks = ctx
+            .files()
+            .iter()
+            .map(|f| docs::comment_blocks(f.src()))
+            .collect::<Vec<_>>();
+        let comments = self
+            .spans
+            .iter()
+            .map(|span| {
+                if span.is_dummy() {
+                    return None;
+                }
+                blocks[span.file() as usize].get(&span.low()).cloned()
+            })
+            .collect();
+        self.comments = Some(comments);
+        self
+    }
+
+    /// encodes the object, failing if a length does not fit in its field
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ObjectError> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        let mut flags = match self.endianness {
+            Endianness::Little => 0,
+            Endianness::Big => FLAG_BIG_ENDIAN,
+        };
+        if self.comments.is_some() {
+            flags |= FLAG_COMMENTS;
+        }
+        out.push(flags);
+
+        let mask = self
+            .consts
+            .iter()
+            .fold(0u8, |mask, (idx, _)| mask | 1 << idx);
+        out.push(mask);
+        for (_, value) in self.consts.iter() {
+            for element in value {
+                out.extend_from_slice(&element.to_le_bytes());
+            }
+        }
+
+        let file_count = u16::try_from(self.files.len())
+            .map_err(|_| ObjectError::TooManyFiles(self.files.len()))?;
+        out.extend_from_slice(&file_count.to_le_bytes());
+        for name in &self.files {
+            let len =
+                u16::try_from(name.len()).map_err(|_| ObjectError::FilenameTooLong(name.len()))?;
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+        }
+
+        out.extend_from_slice(&(self.code.len() as u32).to_le_bytes());
+        out.extend(crate::codegen::to_bytes(
+            self.code.as_slice(),
+            self.endianness,
+        ));
+
+        for span in &self.spans {
+            out.extend_from_slice(&(span.file() as u16).to_le_bytes());
+            out.extend_from_slice(&span.low().to_le_bytes());
+            out.extend_from_slice(&span.high().to_le_bytes());
+        }
+
+        if let Some(comments) = &self.comments {
+            for comment in comments {
+                let comment = comment.as_deref().unwrap_or("");
+                let len = u16::try_from(comment.len())
+                    .map_err(|_| ObjectError::CommentTooLong(comment.len()))?;
+                out.extend_from_slice(&len.to_le_bytes());
+                out.extend_from_slice(comment.as_bytes());
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ObjectError> {
+        let mut r = ByteReader(bytes);
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(ObjectError::BadMagic);
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(ObjectError::UnsupportedVersion(version));
+        }
+        let flags = r.u8()?;
+        let endianness = if flags & FLAG_BIG_ENDIAN != 0 {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        };
+
+        let mut consts = ConstRegs::default();
+        let mask = r.u8()?;
+        for idx in (0..=MAX_REG_IDX).filter(|idx| mask & 1 << idx != 0) {
+            let mut value = [0; 4];
+            for element in &mut value {
+                *element = r.u16()?;
+            }
+            consts.set(idx, value);
+        }
+
+        let mut files = Vec::new();
+        for _ in 0..r.u16()? {
+            let len = r.u16()? as usize;
+            let name =
+                std::str::from_utf8(r.take(len)?).map_err(|_| ObjectError::InvalidFilename)?;
+            files.push(name.to_string());
+        }
+
+        let len = r.u32()? as usize;
+        let code = r
+            .take(len.checked_mul(2).ok_or(ObjectError::Truncated)?)?
+            .chunks_exact(2)
+            .map(|word| match endianness {
+                Endianness::Little => u16::from_le_bytes([word[0], word[1]]),
+                Endianness::Big => u16::from_be_bytes([word[0], word[1]]),
+            })
+            .collect();
+
+        let mut spans = Vec::new();
+        for _ in 0..len {
+            let file = r.u16()?;
+            if file as usize >= files.len() {
+                return Err(ObjectError::InvalidFile(file));
+            }
+            let low = r.u32()?;
+            let high = r.u32()?;
+            spans.push(Span::new_in_file(low, high, file as u32));
+        }
+
+        let comments = if flags & FLAG_COMMENTS != 0 {
+            let mut comments = Vec::new();
+            for _ in 0..len {
+                let len = r.u16()? as usize;
+                let comment =
+                    std::str::from_utf8(r.take(len)?).map_err(|_| ObjectError::InvalidComment)?;
+                comments.push((!comment.is_empty()).then(|| comment.to_string()));
+            }
+            Some(comments)
+        } else {
+            None
+        };
+
+        if !r.0.is_empty() {
+            return Err(ObjectError::TrailingBytes(r.0.len()));
+        }
+        Ok(Self {
+            endianness,
+            consts,
+            code,
+            files,
+            spans,
+            comments,
+        })
+    }
+}
+
+struct ByteReader<'a>(&'a [u8]);
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ObjectError> {
+        if self.0.len() < len {
+            return Err(ObjectError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjectError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ObjectError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::AssembleOptions, source::Source};
+
+    fn object(src: &str, opts: &AssembleOptions) -> (Context, Object) {
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let assembled = crate::assemble(&mut ctx, opts).expect("program should assemble");
+        let object = Object::new(&ctx, &assembled, opts.endianness);
+        (ctx, object)
+    }
+
+    fn assert_same(read: &Object, object: &Object) {
+        let span_parts = |spans: &[Span]| {
+            spans
+                .iter()
+                .map(|span| (span.file(), span.low(), span.high()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(read.endianness, object.endianness);
+        assert_eq!(read.consts, object.consts);
+        assert_eq!(read.code, object.code);
+        assert_eq!(read.files, object.files);
+        assert_eq!(span_parts(&read.spans), span_parts(&object.spans));
+        assert_eq!(read.comments, object.comments);
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = ".const c1, 1, 2, 3, 4\n# clears r0\nzero r0\nadd.w r0, r0, c1\n";
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let opts = AssembleOptions {
+                endianness,
+                pad_to: Some(8),
+                ..AssembleOptions::default()
+            };
+            let (ctx, object) = object(src, &opts);
+            assert!(object.spans.last().unwrap().is_dummy());
+
+            let read = Object::from_bytes(&object.to_bytes().unwrap()).unwrap();
+            assert_same(&read, &object);
+            assert!(read.spans.last().unwrap().is_dummy());
+
+            let object = object.with_comments(&ctx);
+            let read = Object::from_bytes(&object.to_bytes().unwrap()).unwrap();
+            assert_same(&read, &object);
+            assert_eq!(
+                read.comments.as_ref().unwrap()[0].as_deref(),
+                Some("clears r0")
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_objects() {
+        let (_, object) = object("not r0\n", &AssembleOptions::default());
+        let bytes = object.to_bytes().unwrap();
+        assert_eq!(
+            Object::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+            ObjectError::Truncated
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Object::from_bytes(&trailing).unwrap_err(),
+            ObjectError::TrailingBytes(1)
+        );
+        assert_eq!(
+            Object::from_bytes(b"WVMX").unwrap_err(),
+            ObjectError::BadMagic
+        );
+    }
+
+    /// an object with one word of code from a file named `name`, with `comment` before it
+    fn named(name: String, comment: String) -> Object {
+        Object {
+            endianness: Endianness::Little,
+            consts: ConstRegs::default(),
+            code: vec![0x803C],
+            files: vec![name],
+            spans: vec![Span::new(0, 6)],
+            comments: Some(vec![Some(comment)]),
+        }
+    }
+
+    #[test]
+    fn lengths_at_the_maximum() {
+        let max = usize::from(u16::MAX);
+        let object = named("n".repeat(max), "c".repeat(max));
+        let read = Object::from_bytes(&object.to_bytes().unwrap()).unwrap();
+        assert_same(&read, &object);
+    }
+
+    #[test]
+    fn lengths_past_the_maximum() {
+        let max = usize::from(u16::MAX);
+        let err = named("n".repeat(max + 1), String::new())
+            .to_bytes()
+            .unwrap_err();
+        assert_eq!(err, ObjectError::FilenameTooLong(max + 1));
+        assert_eq!(
+            err.to_string(),
+            "file name is 65536 bytes long, but the maximum is 65535"
+        );
+        assert_eq!(
+            named(String::from("test.s"), "c".repeat(max + 1))
+                .to_bytes()
+                .unwrap_err(),
+            ObjectError::CommentTooLong(max + 1)
+        );
+
+        let mut object = named(String::from("test.s"), String::new());
+        object.files = vec![String::new(); max + 1];
+        assert_eq!(
+            object.to_bytes().unwrap_err(),
+            ObjectError::TooManyFiles(max + 1)
+        );
+    }
+}
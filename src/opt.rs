@@ -0,0 +1,295 @@
+use log::*;
+
+use crate::instruction::{Instruction, InstructionKind, ShiftAmount, SwizzleRegSelector};
+
+/// runs every optimization pass over `insts`.
+/// passes that remove instructions move every instruction after them to a lower offset,
+/// which changes where a jump through `ri` lands, so they are only run on programs that
+/// never write to `ri`.
+pub fn optimize(insts: &mut Vec<Instruction>) {
+    for inst in insts.iter_mut() {
+        canonicalize_sub(inst);
+        reduce_rotate(inst);
+    }
+    let jumps = insts.iter().any(|inst| inst.kind().writes_ri());
+    if jumps {
+        debug!("not removing instructions from a program that writes to ri");
+    } else {
+        fuse_swizzles(insts);
+        remove_zero_shifts(insts);
+    }
+}
+
+/// removes shifts and rotates by a constant 0, which do not change their register.
+/// the VM has no flags that a shift could set, so these have no effect at all.
+/// the program must not write to `ri`.
+fn remove_zero_shifts(insts: &mut Vec<Instruction>) {
+    use InstructionKind::*;
+    insts.retain(|inst| match *inst.kind() {
+        ShiftLeft { amount, .. }
+        | ShiftRightLogical { amount, .. }
+        | ShiftRightArithmetic { amount, .. }
+        | RotateLeft { amount, .. }
+        | RotateRight { amount, .. } => {
+            let removed = matches!(amount, ShiftAmount::Const(0, _));
+            if removed {
+                debug!("removing shift by 0 {}", inst.kind());
+            }
+            !removed
+        }
+        _ => true,
+    });
+}
+
+/// replaces consecutive swizzles of the same register with a single swizzle that has
+/// the span of the first one. the program must not write to `ri`.
+fn fuse_swizzles(insts: &mut Vec<Instruction>) {
+    let mut fused: Vec<Instruction> = Vec::with_capacity(insts.len());
+    for inst in insts.drain(..) {
+        if let (Some(prev), InstructionKind::Swizzle { reg }) = (fused.last_mut(), *inst.kind()) {
+            if let InstructionKind::Swizzle { reg: prev_reg } = *prev.kind() {
+                if prev_reg.reg() == reg.reg() {
+                    debug!("fusing swizzles {} and {}", prev_reg, reg);
+                    let selector = reg.selector().compose(&prev_reg.selector());
+                    let reg = SwizzleRegSelector::new(reg.reg(), selector, prev_reg.span());
+                    *prev = Instruction::new(InstructionKind::Swizzle { reg }, prev.span());
+                    continue;
+                }
+            }
+        }
+        fused.push(inst);
+    }
+    *insts = fused;
+}
+
+/// reduces constant rotate amounts modulo the size of an element, since rotating by the
+/// size does not change the element. a byte rotate by 9 becomes a rotate by 1, and a
+/// rotate by 8 becomes a rotate by 0, which is then removed unless the program writes
+/// to `ri`.
+fn reduce_rotate(inst: &mut Instruction) {
+    let kind = match *inst.kind() {
+        InstructionKind::RotateLeft {
+            size,
+            dst,
+            amount: ShiftAmount::Const(val, span),
+        } => InstructionKind::RotateLeft {
+            size,
+            dst,
+            amount: ShiftAmount::Const(val % size.bits(), span),
+        },
+        InstructionKind::RotateRight {
+            size,
+            dst,
+            amount: ShiftAmount::Const(val, span),
+        } => InstructionKind::RotateRight {
+            size,
+            dst,
+            amount: ShiftAmount::Const(val % size.bits(), span),
+        },
+        _ => return,
+    };
+    *inst = Instruction::new(kind, inst.span());
+}
+
+/// `dst - dst` can be encoded as either a sub or a reversed sub, so this picks the
+/// reversed sub, which is what the parser produces for `sub dst, dst, dst`.
+/// this makes equivalent programs always generate the same code.
+fn canonicalize_sub(inst: &mut Instruction) {
+    let kind = match *inst.kind() {
+        InstructionKind::Sub { size, src, dst } if src == dst => {
+            InstructionKind::SubRev { size, src, dst }
+        }
+        InstructionKind::SubSaturate { size, src, dst } if src == dst => {
+            InstructionKind::SubRevSaturate { size, src, dst }
+        }
+        _ => return,
+    };
+    *inst = Instruction::new(kind, inst.span());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen,
+        diag::Context,
+        instruction::{OpSize, RegSelector},
+        lexer::Span,
+        options::AssembleOptions,
+        source::Source,
+    };
+
+    fn inst(kind: InstructionKind) -> Instruction {
+        Instruction::new(kind, Span::DUMMY)
+    }
+
+    fn optimized(mut insts: Vec<Instruction>) -> Vec<Instruction> {
+        optimize(&mut insts);
+        insts
+    }
+
+    #[test]
+    fn self_sub_is_canonical() {
+        let r0 = RegSelector::new_gpr(0, Span::DUMMY);
+        let size = OpSize::Word;
+        let sub = optimized(vec![inst(InstructionKind::Sub {
+            size,
+            src: r0,
+            dst: r0,
+        })]);
+        let sub_rev = optimized(vec![inst(InstructionKind::SubRev {
+            size,
+            src: r0,
+            dst: r0,
+        })]);
+        assert!(matches!(sub[0].kind(), InstructionKind::SubRev { .. }));
+        assert_eq!(codegen::gen(&sub), codegen::gen(&sub_rev));
+
+        let sat = optimized(vec![inst(InstructionKind::SubSaturate {
+            size,
+            src: r0,
+            dst: r0,
+        })]);
+        assert!(matches!(
+            sat[0].kind(),
+            InstructionKind::SubRevSaturate { .. }
+        ));
+    }
+
+    #[test]
+    fn other_subs_are_unchanged() {
+        let (r0, r1) = (
+            RegSelector::new_gpr(0, Span::DUMMY),
+            RegSelector::new_gpr(1, Span::DUMMY),
+        );
+        let sub = optimized(vec![inst(InstructionKind::Sub {
+            size: OpSize::Byte,
+            src: r1,
+            dst: r0,
+        })]);
+        assert!(matches!(sub[0].kind(), InstructionKind::Sub { .. }));
+    }
+
+    /// optimizes a program with one instruction written in assembly on each line,
+    /// returning the optimized instructions as assembly
+    fn optimized_src(lines: &[&str]) -> Vec<String> {
+        let insts = lines
+            .iter()
+            .map(|line| {
+                crate::parse_instruction(line, &AssembleOptions::default())
+                    .expect("instruction should parse")
+            })
+            .collect();
+        optimized(insts)
+            .iter()
+            .map(|inst| inst.kind().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn fusible_swizzles() {
+        assert_eq!(
+            optimized_src(&["swizzle r0.yzwx", "swizzle r0.yzwx"]),
+            ["swizzle r0.zwxy"]
+        );
+        // any number of swizzles fuse into one
+        assert_eq!(
+            optimized_src(&["swizzle r1.yzwx", "swizzle r1.yzwx", "swizzle r1.yzwx"]),
+            ["swizzle r1.wxyz"]
+        );
+
+        // the fused swizzle has the span of the first one
+        let src = "swizzle r0.wzyx\nswizzle r0.yzwx\n";
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let mut program = crate::parse(&mut ctx, &AssembleOptions::default());
+        optimize(program.instructions_mut());
+        let [fused] = program.instructions() else {
+            panic!("expected the swizzles to be fused");
+        };
+        assert_eq!(fused.span().span_text(src), "swizzle r0.wzyx");
+    }
+
+    #[test]
+    fn unfusible_swizzles() {
+        for lines in [
+            // the register is written between the swizzles
+            &["swizzle r0.yzwx", "add.w r0, r0, c1", "swizzle r0.yzwx"][..],
+            // different registers
+            &["swizzle r0.yzwx", "swizzle r1.yzwx"],
+            // each swizzle of ri jumps
+            &["swizzle ri.yzwx", "swizzle ri.yzwx"],
+            // removing an instruction would move the target of the jump
+            &["swizzle r0.yzwx", "swizzle r0.yzwx", "not ri"],
+        ] {
+            let expected = lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(optimized_src(lines), expected);
+        }
+    }
+
+    #[test]
+    fn zero_shifts_are_removed() {
+        assert_eq!(
+            optimized_src(&[
+                "lsl.w r0, 0",
+                "not r0",
+                "lsr.b r1, 0",
+                "asr.w r2, 0",
+                "rol.w r3, 0",
+                "ror.b r4, 0",
+            ]),
+            ["not r0"]
+        );
+        // shifts by a register or by a nonzero amount are kept
+        assert_eq!(
+            optimized_src(&["lsl.w r0, 1", "lsr.w r0, r1"]),
+            ["lsl.w r0, 1", "lsr.w r0, r1"]
+        );
+    }
+
+    #[test]
+    fn zero_shifts_of_ri_are_kept() {
+        // writing to ri jumps, even if the value does not change
+        assert_eq!(
+            optimized_src(&["lsl.w ri, 0", "ror.b ri, 0"]),
+            ["lsl.w ri, 0", "ror.b ri, 0"]
+        );
+    }
+
+    #[test]
+    fn zero_shifts_are_kept_in_programs_that_jump() {
+        // removing the first shift would move the target of the jump through ri
+        let lines = ["lsl.w r0, 0", "not r1", "add.w ri, ri, c1", "ror.b r2, 0"];
+        assert_eq!(optimized_src(&lines), lines);
+        let lines = ["lsr.b r0, 0", "mov r1.x, [ri.x]+"];
+        assert_eq!(optimized_src(&lines), lines);
+        // values are still simplified in place
+        assert_eq!(
+            optimized_src(&["rol.b r0, 9", "not ri"]),
+            ["rol.b r0, 1", "not ri"]
+        );
+    }
+
+    #[test]
+    fn byte_rotates_are_reduced() {
+        // rotating a byte by 8 does not change it, so it is removed
+        assert_eq!(
+            optimized_src(&["rol.b r0, 8", "ror.b r0, 8"]),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            optimized_src(&["rol.b r0, 9", "ror.b r0, 15", "rol.b r0, 7"]),
+            ["rol.b r0, 1", "ror.b r0, 7", "rol.b r0, 7"]
+        );
+    }
+
+    #[test]
+    fn word_and_register_rotates_are_unchanged() {
+        let lines = ["rol.w r0, 9", "ror.w r0, 15", "rol.b r0, r1"];
+        assert_eq!(optimized_src(&lines), lines);
+        // ri still jumps after its rotate is reduced to 0
+        assert_eq!(optimized_src(&["rol.b ri, 8"]), ["rol.b ri, 0"]);
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::PathBuf;
+
+use crate::analysis::CostModel;
+
+/// options that control how a program is assembled
+#[derive(Debug, Clone)]
+pub struct AssembleOptions {
+    /// the byte order used when code is output as bytes
+    pub endianness: Endianness,
+    /// the format that code is output in by [`crate::output`]
+    pub output_mode: OutputMode,
+    /// include the comment before each instruction in object output
+    pub object_comments: bool,
+    /// the maximum size of the output in bytes, if any
+    pub max_size: Option<usize>,
+    /// the size in bytes to pad the output to with `pad_byte`, if any.
+    /// it is an error for the program to be larger than this.
+    pub pad_to: Option<usize>,
+    /// the byte used to pad the output up to `pad_to`
+    pub pad_byte: u8,
+    /// report all warnings as errors
+    pub warnings_as_errors: bool,
+    /// run optimization passes over the program before generating code.
+    /// this rewrites instructions into equivalent canonical forms, reduces constant
+    /// rotates modulo the element size, fuses consecutive swizzles of the same register,
+    /// and removes shifts by 0.
+    ///
+    /// instruction offsets are not preserved: removing an instruction moves every
+    /// instruction after it, and the offsets in [`crate::layout_map`] describe the
+    /// optimized code. instructions are never removed from a program that writes to `ri`,
+    /// since that would change where its jumps land.
+    pub optimize: bool,
+    /// allow register names to be written with a `%` or `$` sigil, as in `%r0`.
+    /// a sigil followed by a number names a general purpose register, so `$0` is `r0`.
+    pub register_sigils: bool,
+    /// require every selector to be written instead of using a default, so that `[r0]`
+    /// must be written as `[r0.x]`
+    pub strict_selectors: bool,
+    /// treat newlines like whitespace, so that instructions do not have to be on their
+    /// own lines. macros cannot be used with this, and neither can
+    /// [`crate::assemble_stream`].
+    pub ignore_newlines: bool,
+    /// directories to search for files included with `.include`, after the
+    /// directory of the including file
+    pub include_paths: Vec<PathBuf>,
+    /// the number of columns between tab stops when reporting columns in diagnostics
+    pub tab_width: u32,
+    /// the instruction costs used when estimating how long a program takes to run
+    pub cost_model: CostModel,
+    /// optional style lints, which are all disabled by default
+    pub lints: Lints,
+}
+
+impl Default for AssembleOptions {
+    fn default() -> Self {
+        Self {
+            endianness: Endianness::Little,
+            output_mode: OutputMode::Flat,
+            object_comments: false,
+            max_size: None,
+            pad_to: None,
+            pad_byte: 0,
+            warnings_as_errors: false,
+            optimize: false,
+            register_sigils: false,
+            strict_selectors: false,
+            ignore_newlines: false,
+            include_paths: Vec::new(),
+            tab_width: 1,
+            cost_model: CostModel::default(),
+            lints: Lints::default(),
+        }
+    }
+}
+
+/// which style lints to run. each lint reports a warning.
+#[derive(Debug, Clone, Default)]
+pub struct Lints {
+    /// lines whose indentation contains both tabs and spaces
+    pub mixed_indentation: bool,
+    /// whitespace at the end of a line
+    pub trailing_whitespace: bool,
+    /// a file that does not end with a newline
+    pub missing_final_newline: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputMode {
+    /// each word of code in hex, as text
+    Words,
+    /// a hex dump of the bytes of the code with offsets and an ASCII column
+    HexDump,
+    /// the bytes of the code as Intel HEX records
+    IntelHex,
+    /// only the code, ready to be run
+    Flat,
+    /// the code along with the const register values and debug info, in the format
+    /// described in [`crate::object`]
+    Object,
+}
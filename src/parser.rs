@@ -1,94 +1,256 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::PathBuf,
+};
+
 use log::*;
 
 use crate::instruction::Instruction;
 use crate::lexer::Span;
 use crate::{
+    consts::ConstRegs,
     diag::{Context, Diagnostic},
     instruction::{
-        InstructionKind, MemoryOperand, OpSize, RegSelector, SetRegSelector, SetSelector,
-        ShiftAmount, SwizzleRegSelector, SwizzleSelector, MAX_REG_IDX,
+        self, mnemonic, InstructionKind, MemoryOperand, OpSize, RegSelector, SetRegSelector,
+        SetSelector, ShiftAmount, SwizzleRegSelector, SwizzleSelector, MAX_REG_IDX,
     },
     lexer::{Lexer, Token, TokenKind},
+    source::Source,
+    validate,
 };
 
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     current: Token,
+    consts: ConstRegs,
+    /// whether registers may be written with a sigil
+    register_sigils: bool,
+    /// whether selectors must always be written instead of using a default
+    strict_selectors: bool,
+    /// whether newlines are skipped like whitespace, so that instructions and directives
+    /// end where their grammar does instead of at the end of the line
+    ignore_newlines: bool,
+    /// the size of math and shift instructions that are written without a size, set by
+    /// `.size`
+    default_size: Option<OpSize>,
+    /// the id of the file being parsed
+    file: u32,
+    /// the directories to search for included files
+    include_paths: Vec<PathBuf>,
+    /// the canonical paths of the files that included the file being parsed, used
+    /// to detect include cycles
+    includers: Vec<PathBuf>,
+    /// instructions from included files that have not been returned yet
+    pending: VecDeque<Instruction>,
+    /// tokens from macro expansions, which are parsed before any more tokens from the lexer
+    queued: VecDeque<Token>,
+    macros: HashMap<String, Macro>,
+    /// the span of every definition and invocation of each macro name
+    macro_refs: HashMap<String, Vec<Span>>,
+}
+
+/// the maximum depth of macros invoking other macros, which stops recursive macros
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// a macro defined with `.macro`
+#[derive(Debug, Clone)]
+struct Macro {
+    /// the span of the macro name in its definition
+    span: Span,
+    params: Vec<String>,
+    /// the tokens in the body, where every line ends with a newline
+    body: Vec<Token>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(src: &'a str) -> Self {
-        let mut lexer = Lexer::new(src);
+        Self::with_consts(src, ConstRegs::default())
+    }
+
+    /// creates a parser that starts with some const registers already set, to
+    /// continue parsing a program that was split up
+    pub fn with_consts(src: &'a str, consts: ConstRegs) -> Self {
+        Self::new_in_file(src, 0, consts)
+    }
+
+    /// creates a parser that parses `tokens` instead of lexing `src`, for tools that
+    /// already have tokens, such as editors. the spans of the tokens should be in `src`.
+    pub fn from_tokens(src: &'a str, tokens: Vec<Token>) -> Self {
+        // the lexer is only used for the EoF after the last token
+        let mut parser =
+            Self::with_lexer(Lexer::resume_at(src, src.len(), 0), 0, ConstRegs::default());
+        parser.queued = tokens.into();
+        parser.bump();
+        parser
+    }
+
+    fn new_in_file(src: &'a str, file: u32, consts: ConstRegs) -> Self {
+        Self::with_lexer(Lexer::new_in_file(src, file), file, consts)
+    }
+
+    fn with_lexer(mut lexer: Lexer<'a>, file: u32, consts: ConstRegs) -> Self {
         let current = lexer.next_token();
-        Self { lexer, current }
+        Self {
+            lexer,
+            current,
+            consts,
+            register_sigils: false,
+            strict_selectors: false,
+            ignore_newlines: false,
+            default_size: None,
+            file,
+            include_paths: Vec::new(),
+            includers: Vec::new(),
+            pending: VecDeque::new(),
+            queued: VecDeque::new(),
+            macros: HashMap::new(),
+            macro_refs: HashMap::new(),
+        }
+    }
+
+    /// sets the directories that are searched for included files, after the directory
+    /// of the including file
+    pub fn with_include_paths(mut self, include_paths: Vec<PathBuf>) -> Self {
+        self.include_paths = include_paths;
+        self
+    }
+
+    /// allows register names to be written with a `%` or `$` sigil, as in `%r0`, and
+    /// general purpose registers to be written as a sigil and their number, as in `$0`
+    pub fn with_register_sigils(mut self, register_sigils: bool) -> Self {
+        self.register_sigils = register_sigils;
+        self
+    }
+
+    /// requires every selector to be written, so that `[r0]` is an error instead of
+    /// shorthand for `[r0.x]`
+    pub fn with_strict_selectors(mut self, strict_selectors: bool) -> Self {
+        self.strict_selectors = strict_selectors;
+        self
+    }
+
+    /// skips newlines like whitespace, for generated code that does not put each
+    /// instruction on its own line. macros cannot be used, since they are made of lines,
+    /// and the rest of the source is skipped after an error, since there is no next line
+    /// to recover at.
+    pub fn with_ignore_newlines(mut self, ignore_newlines: bool) -> Self {
+        self.ignore_newlines = ignore_newlines;
+        self
     }
 
-    pub fn parse_inst(&mut self, ctx: &mut Context) -> Result<Option<Instruction>, ()> {
-        // eat all newlines before an instruction to ignore empty lines
-        // whitespace is ignored entirely, so it does not need to be considered
+    /// starts with a size set as if by `.size`, to continue parsing a program that was
+    /// split up
+    pub fn with_default_size(mut self, default_size: Option<OpSize>) -> Self {
+        self.default_size = default_size;
+        self
+    }
+
+    /// the const register values set by directives that have been parsed so far
+    pub fn consts(&self) -> &ConstRegs {
+        &self.consts
+    }
+
+    /// the size set by the last `.size` that has been parsed, if any
+    pub fn default_size(&self) -> Option<OpSize> {
+        self.default_size
+    }
+
+    /// the spans of every definition and invocation of the macro `name` that has been
+    /// parsed so far, in source order. invocations inside the body of another macro are
+    /// only listed once, no matter how many times that macro was expanded.
+    pub fn macro_references(&self, name: &str) -> Vec<Span> {
+        sorted_refs(self.macro_refs.get(name).cloned().unwrap_or_default())
+    }
+
+    /// the references to every macro that has been parsed so far, in the same form as
+    /// [`Parser::macro_references`]
+    pub(crate) fn all_macro_references(&self) -> HashMap<String, Vec<Span>> {
+        self.macro_refs
+            .iter()
+            .map(|(name, spans)| (name.clone(), sorted_refs(spans.clone())))
+            .collect()
+    }
+
+    /// skips empty lines, returning the span of the next token if there is anything left
+    /// to parse
+    pub(crate) fn skip_to_content(&mut self) -> Option<Span> {
         while matches!(self.current.kind(), TokenKind::Newline) {
             self.bump();
         }
+        (self.pending.is_empty() && self.current.kind() != &TokenKind::EoF)
+            .then(|| self.current.span())
+    }
 
-        if self.current.kind() == &TokenKind::EoF {
-            return Ok(None);
+    pub(crate) fn parse_inst(&mut self, ctx: &mut Context) -> Result<Option<Instruction>, ()> {
+        // directives are handled entirely by the parser, so they are skipped
+        // until an instruction is found
+        loop {
+            if let Some(inst) = self.pending.pop_front() {
+                return Ok(Some(inst));
+            }
+
+            // eat all newlines before an instruction to ignore empty lines
+            // whitespace is ignored entirely, so it does not need to be considered
+            while matches!(self.current.kind(), TokenKind::Newline) {
+                self.bump();
+            }
+
+            if self.current.kind() == &TokenKind::EoF {
+                return Ok(None);
+            }
+
+            // macros are expanded into the token stream and then parsed like any other
+            // source, so they are handled here along with directives
+            if matches!(self.current.kind(), TokenKind::Ident(name) if self.macros.contains_key(name))
+            {
+                self.parse_macro_invocation(ctx);
+                continue;
+            }
+
+            if self.current.kind() != &TokenKind::Dot {
+                break;
+            }
+            self.parse_directive(ctx);
+            // eat until newline to prevent cascading errors
+            if ctx.had_errs() {
+                self.eat_line();
+            }
         }
 
         let span_start = self.current.span();
         let mut inner = || {
             let inst = self.expect_ident().map_err(|d| ctx.add_diag(d))?;
 
-            let inst = match inst.to_lowercase().as_str() {
-                "mov" => self.parse_move(ctx)?,
-                "swizzle" => self.parse_swizzle(ctx)?,
-
-                // =========
-                // math ops
-                // =========
-                "add" => self.parse_add(ctx, AddMode::Normal)?,
-                "add_sat" => self.parse_add(ctx, AddMode::Saturate)?,
-                "sub" => self.parse_sub(ctx, SubMode::Normal)?,
-                "sub_sat" => self.parse_sub(ctx, SubMode::Saturate)?,
-                "cmpeq" => self.parse_cmp(ctx, CmpMode::Eq)?,
-                "cmpneq" => self.parse_cmp(ctx, CmpMode::Neq)?,
-
-                // ==========
-                // shift ops
-                // ==========
-                "lsl" | "asl" => self.parse_lsl(ctx)?,
-                "rol" => self.parse_rol(ctx)?,
-                "asr" => self.parse_asr(ctx)?,
-                "lsr" => self.parse_lsr(ctx)?,
-                "ror" => self.parse_ror(ctx)?,
-
-                // ============
-                // bitwise ops
-                // ============
-                "and" => self.parse_and(ctx),
-                "or" => self.parse_or(ctx),
-                "xor" => self.parse_xor(ctx),
-                "nand" => self.parse_nand(ctx),
-                "nor" => self.parse_nor(ctx),
-                "xnor" => self.parse_xnor(ctx),
-                "not" => self.parse_unary_not(ctx),
-
-                _ => {
-                    ctx.add_diag(Diagnostic::new(
-                        format!("invalid instruction `{}`", inst),
-                        self.current.span(),
-                    ));
-                    return Err(());
-                }
+            let name = inst.to_lowercase();
+            let Some(spec) = INSTRUCTION_SET
+                .iter()
+                .find(|spec| spec.mnemonic == name || spec.aliases.contains(&name.as_str()))
+            else {
+                ctx.add_diag(Diagnostic::new(
+                    format!("invalid instruction `{}`", inst),
+                    self.current.span(),
+                ));
+                return Err(());
             };
+            if let Some(d) = self.check_operand_count(&inst, spec) {
+                ctx.add_diag(d);
+                // use a dummy instruction to allow parsing to continue on the next line
+                return Ok(Some(Instruction::new(
+                    InstructionKind::UnaryBitNot {
+                        dst: RegSelector::new_gpr(0, Span::DUMMY),
+                    },
+                    span_start,
+                )));
+            }
+            let inst = (spec.parse)(self, ctx)?;
             Ok(Some(inst))
         };
 
         let ret = inner();
         // eat until newline to prevent cascading errors
         if ctx.had_errs() {
-            while !matches!(self.current.kind(), TokenKind::Newline | TokenKind::EoF) {
-                self.bump();
-            }
+            self.eat_line();
         }
 
         if ret.is_err() {
@@ -98,6 +260,611 @@ impl<'a> Parser<'a> {
         ret
     }
 
+    /// checks that the instruction on the current line has as many operands as one of the
+    /// forms of `spec`, without consuming any tokens. the error points at the first extra
+    /// operand, or at the end of the line if there are too few.
+    fn check_operand_count(&mut self, name: &str, spec: &InstructionSpec) -> Option<Diagnostic> {
+        // without newlines, operands can only be found by parsing them
+        if self.ignore_newlines {
+            return None;
+        }
+
+        let mnemonic = self.current.clone();
+        self.bump();
+        let mut line = Vec::new();
+        while !matches!(self.current.kind(), TokenKind::Newline | TokenKind::EoF) {
+            line.push(self.current.clone());
+            self.bump();
+        }
+        let end = self.current.span();
+
+        // put the line back, so that it is parsed as if it was never read
+        let mut queued = VecDeque::from(line.clone());
+        queued.push_back(self.current.clone());
+        queued.append(&mut self.queued);
+        self.queued = queued;
+        self.current = mnemonic;
+
+        let mut operands = line.as_slice();
+        if !spec.sizes.is_empty() {
+            if let [first, _, rest @ ..] = operands {
+                if first.kind() == &TokenKind::Dot {
+                    operands = rest;
+                }
+            }
+        }
+        let commas = operands
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.kind() == &TokenKind::Comma)
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        let found = if operands.is_empty() {
+            0
+        } else {
+            commas.len() + 1
+        };
+
+        let mut counts = spec.forms.iter().map(|form| form.len()).collect::<Vec<_>>();
+        counts.sort_unstable();
+        counts.dedup();
+        if counts.contains(&found) {
+            return None;
+        }
+
+        let max = counts.last().copied().unwrap_or(0);
+        let span = if found > max {
+            // the first extra operand starts after the comma that ends the last operand
+            let comma = commas[max - 1];
+            let start = operands.get(comma + 1).unwrap_or(&operands[comma]);
+            Span::between(start.span(), operands[operands.len() - 1].span())
+        } else {
+            end
+        };
+        let expected = counts
+            .iter()
+            .map(|count| count.to_string())
+            .collect::<Vec<_>>()
+            .join(" or ");
+        let diag = Diagnostic::new(
+            format!(
+                "`{}` takes {} operand{}, found {}",
+                name,
+                expected,
+                if counts == [1] { "" } else { "s" },
+                found
+            ),
+            span,
+        );
+        // the user probably assumed this was a two argument math op of the form dst, src
+        // but it actually is three, dst, lhs, rhs
+        Some(if spec.forms == MATH_FORMS && found == 2 {
+            diag.with_note(String::from(
+                "math operands are of the form `op dst, lhs, rhs`",
+            ))
+        } else {
+            diag
+        })
+    }
+
+    fn parse_directive(&mut self, ctx: &mut Context) {
+        let span_start = self.current.span();
+        self.bump();
+
+        let name = match self.expect_ident() {
+            Ok(name) => name,
+            Err(d) => {
+                ctx.add_diag(d.with_note(String::from("expected a directive name")));
+                return;
+            }
+        };
+
+        match name.to_lowercase().as_str() {
+            "const" => self.parse_const(ctx),
+            "include" => self.parse_include(ctx),
+            "macro" => self.parse_macro(ctx, span_start),
+            "size" => self.parse_size_directive(ctx),
+            "endm" => ctx.add_diag(Diagnostic::new(
+                String::from("`.endm` without a matching `.macro`"),
+                Span::between(span_start, self.current.span()),
+            )),
+            _ => ctx.add_diag(Diagnostic::new(
+                format!("unknown directive `.{}`", name),
+                Span::between(span_start, self.current.span()),
+            )),
+        }
+    }
+
+    /// parses `.const cN, x, y, z, w` or `.const cN, {x, y, z, w}`, setting the elements
+    /// of a const register. fewer than four values may be given, in which case the
+    /// remaining elements are 0.
+    fn parse_const(&mut self, ctx: &mut Context) {
+        self.bump();
+
+        let reg = match self.parse_reg() {
+            Ok(reg) => reg,
+            Err(d) => {
+                ctx.add_diag(d);
+                return;
+            }
+        };
+        if !reg.is_const() {
+            ctx.add_diag(Diagnostic::new(
+                format!("expected a const register, got {}", reg),
+                reg.span(),
+            ));
+            return;
+        }
+
+        let mut value = [0_u16; 4];
+        let mut count = 0;
+        let mut braced = false;
+        while self.eat(&TokenKind::Comma) {
+            // the elements may be grouped in braces, as in `.const c0, {1, 2, 3, 4}`
+            if count == 0 {
+                braced = self.eat(&TokenKind::LeftBrace);
+            }
+            let span = self.current.span();
+            let Some(elem) = self.parse_const_elem(ctx) else {
+                return;
+            };
+            if count == value.len() {
+                ctx.add_diag(Diagnostic::new(
+                    String::from("const registers only have 4 elements"),
+                    span,
+                ));
+                return;
+            }
+            value[count] = elem;
+            count += 1;
+        }
+        if braced && !self.eat(&TokenKind::RightBrace) {
+            ctx.add_diag(Diagnostic::new(
+                format!("expected `,` or `}}`, found `{}`", self.current.kind()),
+                self.current.span(),
+            ));
+            return;
+        }
+
+        if !self.at_line_end() {
+            ctx.add_diag(Diagnostic::new(
+                format!(
+                    "expected {}end of line, found `{}`",
+                    if braced { "" } else { "`,` or " },
+                    self.current.kind()
+                ),
+                self.current.span(),
+            ));
+            return;
+        }
+        if count == 0 {
+            ctx.add_diag(Diagnostic::new(
+                String::from("expected at least one value for the const register"),
+                self.current.span(),
+            ));
+            return;
+        }
+
+        if self.consts.set(reg.idx(), value) {
+            ctx.add_diag(Diagnostic::new(
+                format!("{} was already set", reg),
+                reg.span(),
+            ));
+        }
+    }
+
+    /// parses `.size b` or `.size w`, setting the size of the following math and shift
+    /// instructions that are written without a size. a size written on an instruction is
+    /// used instead. a `.size` in an included file also applies after the `.include`.
+    fn parse_size_directive(&mut self, ctx: &mut Context) {
+        self.bump();
+
+        let span = self.current.span();
+        let size = match self.current.kind() {
+            TokenKind::Ident(size) => OpSize::try_from(size.as_str()).ok(),
+            _ => None,
+        };
+        let Some(size) = size else {
+            ctx.add_diag(
+                Diagnostic::new(
+                    format!("expected a size, found `{}`", self.current.kind()),
+                    span,
+                )
+                .with_note(String::from("the size must be `b` or `w`")),
+            );
+            return;
+        };
+        self.bump();
+
+        if !self.at_line_end() {
+            ctx.add_diag(Diagnostic::new(
+                format!("expected end of line, found `{}`", self.current.kind()),
+                self.current.span(),
+            ));
+            return;
+        }
+        self.default_size = Some(size);
+    }
+
+    /// parses `.include "path"`, parsing the included file and queueing its instructions
+    /// to be returned before any of the following instructions.
+    /// the path is resolved relative to the directory of the including file first, then
+    /// relative to each include path in order.
+    fn parse_include(&mut self, ctx: &mut Context) {
+        self.bump();
+
+        let span = self.current.span();
+        let path = match self.current.kind() {
+            TokenKind::Str(path) => PathBuf::from(path),
+            other => {
+                ctx.add_diag(Diagnostic::new(
+                    format!("expected a path in quotes, found `{}`", other),
+                    span,
+                ));
+                return;
+            }
+        };
+        self.bump();
+
+        let including = PathBuf::from(ctx.file(self.file).filename());
+        let Some(resolved) = including
+            .parent()
+            .map(|dir| dir.join(&path))
+            .into_iter()
+            .chain(self.include_paths.iter().map(|dir| dir.join(&path)))
+            .find(|candidate| candidate.is_file())
+        else {
+            ctx.add_diag(Diagnostic::new(
+                format!("could not find included file `{}`", path.display()),
+                span,
+            ));
+            return;
+        };
+
+        let mut includers = self.includers.clone();
+        if let Ok(including) = fs::canonicalize(&including) {
+            includers.push(including);
+        }
+        let canonical = fs::canonicalize(&resolved).unwrap_or_else(|_| resolved.clone());
+        if includers.contains(&canonical) {
+            let chain = includers
+                .iter()
+                .chain([&canonical])
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            ctx.add_diag(
+                Diagnostic::new(
+                    format!("`{}` is included recursively", path.display()),
+                    span,
+                )
+                .with_note(format!("include chain: {}", chain)),
+            );
+            return;
+        }
+
+        let source = match Source::new_from_file(&resolved) {
+            Ok(source) => source,
+            Err(e) => {
+                ctx.add_diag(Diagnostic::new(
+                    format!("failed to read `{}`: {}", resolved.display(), e),
+                    span,
+                ));
+                return;
+            }
+        };
+        let src = source.src().to_owned();
+        let file = ctx.add_file(source);
+
+        let mut parser = Parser::new_in_file(src.as_str(), file, self.consts.clone());
+        parser.register_sigils = self.register_sigils;
+        parser.strict_selectors = self.strict_selectors;
+        parser.ignore_newlines = self.ignore_newlines;
+        parser.default_size = self.default_size;
+        parser.include_paths = self.include_paths.clone();
+        parser.includers = includers;
+        parser.macros = self.macros.clone();
+        parser.macro_refs = std::mem::take(&mut self.macro_refs);
+        while let Ok(Some(inst)) = parser.parse_inst(ctx) {
+            self.pending.push_back(inst);
+        }
+        // like consts and macros, a `.size` in an included file applies to the rest of
+        // the including file
+        self.consts = parser.consts;
+        self.default_size = parser.default_size;
+        self.macros = parser.macros;
+        self.macro_refs = parser.macro_refs;
+    }
+
+    /// parses the definition of a macro:
+    /// ```text
+    /// .macro name param1, param2
+    ///     body
+    /// .endm
+    /// ```
+    /// when the macro is invoked as `name arg1, arg2`, every identifier in the body that
+    /// is the name of a parameter is replaced with the tokens of its argument.
+    /// a macro may have the same name as an instruction, which replaces the instruction.
+    fn parse_macro(&mut self, ctx: &mut Context, span_start: Span) {
+        if self.ignore_newlines {
+            ctx.add_diag(
+                Diagnostic::new(
+                    String::from("macros cannot be defined when newlines are ignored"),
+                    Span::between(span_start, self.current.span()),
+                )
+                .with_note(String::from("the body of a macro is made of lines")),
+            );
+            return;
+        }
+        self.bump();
+
+        let name_span = self.current.span();
+        let name = match self.expect_ident() {
+            Ok(name) => name,
+            Err(d) => {
+                ctx.add_diag(d.with_note(String::from("expected a macro name")));
+                return;
+            }
+        };
+        self.bump();
+
+        let mut params = Vec::new();
+        while !matches!(self.current.kind(), TokenKind::Newline | TokenKind::EoF) {
+            let param = match self.expect_ident() {
+                Ok(param) => param,
+                Err(d) => {
+                    ctx.add_diag(d.with_note(String::from("expected a macro parameter")));
+                    return;
+                }
+            };
+            if params.contains(&param) {
+                ctx.add_diag(Diagnostic::new(
+                    format!("parameter `{}` is already defined", param),
+                    self.current.span(),
+                ));
+            }
+            params.push(param);
+            self.bump();
+
+            if !self.eat(&TokenKind::Comma)
+                && !matches!(self.current.kind(), TokenKind::Newline | TokenKind::EoF)
+            {
+                ctx.add_diag(Diagnostic::new(
+                    String::from("expected a comma or the end of the line"),
+                    self.current.span(),
+                ));
+                return;
+            }
+        }
+
+        // collect the body up to the `.endm`
+        let mut body = Vec::new();
+        let mut line_start = self.eat(&TokenKind::Newline);
+        loop {
+            match self.current.kind() {
+                TokenKind::EoF => {
+                    ctx.add_diag(
+                        Diagnostic::new(
+                            format!("macro `{}` is missing an `.endm`", name),
+                            Span::between(span_start, name_span),
+                        )
+                        .with_note(String::from("the macro ends at the end of the file")),
+                    );
+                    return;
+                }
+                TokenKind::Dot if line_start => {
+                    let dot = self.current.clone();
+                    self.bump();
+                    match self.current.kind() {
+                        TokenKind::Ident(directive) if directive.eq_ignore_ascii_case("endm") => {
+                            self.bump();
+                            break;
+                        }
+                        TokenKind::Ident(directive) if directive.eq_ignore_ascii_case("macro") => {
+                            ctx.add_diag(Diagnostic::new(
+                                String::from("macros cannot be defined inside another macro"),
+                                Span::between(dot.span(), self.current.span()),
+                            ));
+                            return;
+                        }
+                        _ => body.push(dot),
+                    }
+                }
+                _ => {}
+            }
+
+            line_start = self.current.kind() == &TokenKind::Newline;
+            body.push(self.current.clone());
+            self.bump();
+        }
+
+        if !matches!(self.current.kind(), TokenKind::Newline | TokenKind::EoF) {
+            ctx.add_diag(Diagnostic::new(
+                String::from("expected the end of the line after `.endm`"),
+                self.current.span(),
+            ));
+        }
+
+        self.macro_refs
+            .entry(name.clone())
+            .or_default()
+            .push(name_span);
+        if let Some(prev) = self.macros.get(&name) {
+            ctx.add_diag(
+                Diagnostic::new(format!("macro `{}` is already defined", name), name_span)
+                    .with_note_at(format!("`{}` was first defined here", name), prev.span),
+            );
+            return;
+        }
+        self.macros.insert(
+            name,
+            Macro {
+                span: name_span,
+                params,
+                body,
+            },
+        );
+    }
+
+    /// expands the macro invocation on the current line, so that the tokens of the
+    /// expansion are parsed next
+    fn parse_macro_invocation(&mut self, ctx: &mut Context) {
+        let mut line = Vec::new();
+        while !matches!(self.current.kind(), TokenKind::Newline | TokenKind::EoF) {
+            line.push(self.current.clone());
+            self.bump();
+        }
+
+        let mut expanded = Vec::new();
+        if self.expand_macro(ctx, &line, 0, &mut expanded).is_err() {
+            return;
+        }
+
+        // the expanded tokens are parsed before the end of the invocation's line
+        let mut queued = VecDeque::from(expanded);
+        queued.push_back(self.current.clone());
+        queued.append(&mut self.queued);
+        self.queued = queued;
+        self.bump();
+    }
+
+    /// expands a single line that invokes a macro into `out`, also expanding any macros
+    /// that the body invokes
+    fn expand_macro(
+        &mut self,
+        ctx: &mut Context,
+        line: &[Token],
+        depth: usize,
+        out: &mut Vec<Token>,
+    ) -> Result<(), ()> {
+        let Some((name_token, rest)) = line.split_first() else {
+            return Ok(());
+        };
+        let TokenKind::Ident(name) = name_token.kind() else {
+            unreachable!("macro invocations start with the macro name");
+        };
+        self.macro_refs
+            .entry(name.clone())
+            .or_default()
+            .push(name_token.span());
+        let invocation_span = Span::between(
+            name_token.span(),
+            rest.last().map_or(name_token.span(), |t| t.span()),
+        );
+
+        if depth >= MAX_MACRO_DEPTH {
+            ctx.add_diag(
+                Diagnostic::new(
+                    format!(
+                        "macro expansion exceeds the maximum depth of {}",
+                        MAX_MACRO_DEPTH
+                    ),
+                    invocation_span,
+                )
+                .with_note(format!("`{}` may be invoking itself", name)),
+            );
+            return Err(());
+        }
+
+        // cloned so that the body can be expanded while recording references
+        let mac = self.macros[name].clone();
+        let args = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(|t| t.kind() == &TokenKind::Comma).collect()
+        };
+        if args.iter().any(|arg| arg.is_empty()) {
+            ctx.add_diag(Diagnostic::new(
+                String::from("macro arguments must not be empty"),
+                invocation_span,
+            ));
+            return Err(());
+        }
+        if args.len() != mac.params.len() {
+            ctx.add_diag(Diagnostic::new(
+                format!(
+                    "macro `{}` takes {} argument{}, but {} {} given",
+                    name,
+                    mac.params.len(),
+                    if mac.params.len() == 1 { "" } else { "s" },
+                    args.len(),
+                    if args.len() == 1 { "was" } else { "were" },
+                ),
+                invocation_span,
+            ));
+            return Err(());
+        }
+
+        for body_line in mac
+            .body
+            .split_inclusive(|t| t.kind() == &TokenKind::Newline)
+        {
+            let mut substituted = Vec::new();
+            for token in body_line {
+                match token.kind() {
+                    TokenKind::Ident(ident) => match mac.params.iter().position(|p| p == ident) {
+                        Some(idx) => substituted.extend_from_slice(args[idx]),
+                        None => substituted.push(token.clone()),
+                    },
+                    _ => substituted.push(token.clone()),
+                }
+            }
+
+            match substituted.first().map(|t| t.kind()) {
+                Some(TokenKind::Ident(name)) if self.macros.contains_key(name) => {
+                    // the invocation does not include the newline
+                    let newline = substituted.pop_if(|t| t.kind() == &TokenKind::Newline);
+                    self.expand_macro(ctx, &substituted, depth + 1, out)?;
+                    out.extend(newline);
+                }
+                _ => out.extend(substituted),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// parses a signed or unsigned 16 bit number, wrapping negative numbers to 16 bits
+    fn parse_const_elem(&mut self, ctx: &mut Context) -> Option<u16> {
+        let span_start = self.current.span();
+        let negative = self.eat(&TokenKind::Minus);
+        let sign = if negative { "-" } else { "" };
+        let span = Span::between(span_start, self.current.span());
+
+        match self.current.kind() {
+            TokenKind::Number(num) if negative && *num > 0x8000 => {
+                ctx.add_diag(Diagnostic::new(
+                    format!("value -{} does not fit in 16 bits", num),
+                    span,
+                ));
+                None
+            }
+            TokenKind::Number(num) => {
+                let num = *num;
+                self.bump();
+                Some(if negative { num.wrapping_neg() } else { num })
+            }
+            TokenKind::InvalidNumber(text) => {
+                ctx.add_diag(Diagnostic::new(
+                    format!("value {}{} does not fit in 16 bits", sign, text),
+                    span,
+                ));
+                None
+            }
+            TokenKind::MalformedNumber(text) => {
+                ctx.add_diag(Diagnostic::new(format!("invalid number `{}`", text), span));
+                None
+            }
+            other => {
+                ctx.add_diag(Diagnostic::new(
+                    format!("expected a number, found `{}`", other),
+                    self.current.span(),
+                ));
+                None
+            }
+        }
+    }
+
     fn parse_move(&mut self, ctx: &mut Context) -> Result<Instruction, ()> {
         let span_start = self.current.span();
         self.bump();
@@ -121,12 +888,9 @@ impl<'a> Parser<'a> {
                 // while it could be reasonable to allow the source to only be a *subset* of the
                 // destination (as in `move r0.xyzw, r1.xyz`), exact equality expresses the
                 // same thing but with less room for error.
-                if dst.selector() != src.selector() {
-                    // this is not critical to fail on, it's mostly for clarity in writing
-                    ctx.add_diag(Diagnostic::new(
-                        String::from("lhs and rhs of move must select the same elements"),
-                        Span::between(dst.span(), src.span()),
-                    ));
+                // this is not critical to fail on, it's mostly for clarity in writing
+                if let Some(d) = validate::check_move_selectors(src, dst) {
+                    ctx.add_diag(d);
                 }
 
                 // the dst must be a writable register
@@ -150,19 +914,56 @@ impl<'a> Parser<'a> {
                         dst.span(),
                     ));
                 }
+                if let Some(d) = validate::check_mem_value(dst) {
+                    ctx.add_diag(d);
+                }
+                let kind = InstructionKind::Load { mem, dst };
+                if let Some(d) = validate::check_conflicting_writes(&kind) {
+                    ctx.add_diag(d);
+                } else if let Some(d) = validate::check_ri_increment(mem) {
+                    ctx.add_diag(d);
+                }
 
                 Ok(Instruction::new(
-                    InstructionKind::Load { mem, dst },
+                    kind,
                     Span::between(span_start, mem.span()),
                 ))
             }
-            (LoadStoreOp::RegOp(src), LoadStoreOp::MemOp(mem)) => Ok(Instruction::new(
-                InstructionKind::Store { src, mem },
-                Span::between(span_start, src.span()),
-            )),
+            (LoadStoreOp::RegOp(src), LoadStoreOp::MemOp(mem)) => {
+                if let Some(d) = validate::check_mem_value(src) {
+                    ctx.add_diag(d);
+                }
+                if let Some(d) = validate::check_scatter_store(src, mem) {
+                    ctx.add_diag(d);
+                }
+                if let Some(d) = validate::check_ri_increment(mem) {
+                    ctx.add_diag(d);
+                }
+
+                Ok(Instruction::new(
+                    InstructionKind::Store { src, mem },
+                    Span::between(span_start, src.span()),
+                ))
+            }
 
             // mem-to-mem moves do not exist
-            (LoadStoreOp::MemOp(_), LoadStoreOp::MemOp(_)) => todo!(),
+            (LoadStoreOp::MemOp(src), LoadStoreOp::MemOp(dst)) => {
+                ctx.add_diag(Diagnostic::new(
+                    String::from("cannot move directly from memory to memory"),
+                    Span::between(dst.span(), src.span()),
+                ));
+
+                // generate a dummy load to allow for further parsing
+                let dst = SetRegSelector::new(
+                    RegSelector::new_gpr(0, Span::DUMMY),
+                    SetSelector::from_bits(0b1111, Span::DUMMY),
+                    Span::DUMMY,
+                );
+                Ok(Instruction::new(
+                    InstructionKind::Load { mem: src, dst },
+                    Span::between(span_start, src.span()),
+                ))
+            }
         }
     }
 
@@ -178,6 +979,9 @@ impl<'a> Parser<'a> {
                 dst.span(),
             ));
         }
+        if let Some(d) = validate::check_swizzle_ri(dst) {
+            ctx.add_diag(d);
+        }
         Ok(Instruction::new(
             InstructionKind::Swizzle { reg: dst },
             Span::between(span_start, dst.span()),
@@ -325,6 +1129,7 @@ impl<'a> Parser<'a> {
     fn parse_lsl(&mut self, ctx: &mut Context) -> Result<Instruction, ()> {
         let span_start = self.current.span();
         let (size, dst, amount) = self.parse_shift_common(ctx)?;
+        lint_shift_out_all(ctx, size, amount, false);
         Ok(Instruction::new(
             InstructionKind::ShiftLeft { size, dst, amount },
             Span::between(span_start, amount.span()),
@@ -343,6 +1148,7 @@ impl<'a> Parser<'a> {
     fn parse_lsr(&mut self, ctx: &mut Context) -> Result<Instruction, ()> {
         let span_start = self.current.span();
         let (size, dst, amount) = self.parse_shift_common(ctx)?;
+        lint_shift_out_all(ctx, size, amount, false);
         Ok(Instruction::new(
             InstructionKind::ShiftRightLogical { size, dst, amount },
             Span::between(span_start, amount.span()),
@@ -352,6 +1158,7 @@ impl<'a> Parser<'a> {
     fn parse_asr(&mut self, ctx: &mut Context) -> Result<Instruction, ()> {
         let span_start = self.current.span();
         let (size, dst, amount) = self.parse_shift_common(ctx)?;
+        lint_shift_out_all(ctx, size, amount, true);
         Ok(Instruction::new(
             InstructionKind::ShiftRightArithmetic { size, dst, amount },
             Span::between(span_start, amount.span()),
@@ -421,7 +1228,45 @@ impl<'a> Parser<'a> {
         )
     }
 
-    fn parse_unary_not(&mut self, ctx: &mut Context) -> Instruction {
+    /// parses either `not dst` or `not dst, src`
+    fn parse_not(&mut self, ctx: &mut Context) -> Instruction {
+        let span_start = self.current.span();
+        self.bump();
+        let mut was_err = false;
+        let dst = self.parse_reg().unwrap_or_else(|d| {
+            ctx.add_diag(d);
+            was_err = true;
+            // use a dummy selector to allow recovery
+            RegSelector::new_gpr(0, Span::DUMMY)
+        });
+        // the dst must be a writable register
+        if !was_err && !dst.is_gpr() {
+            ctx.add_diag(Diagnostic::new(
+                format!("expected dst to be a writable register, got {}", dst),
+                dst.span(),
+            ));
+        }
+
+        if !self.eat(&TokenKind::Comma) {
+            return Instruction::new(
+                InstructionKind::UnaryBitNot { dst },
+                Span::between(span_start, dst.span()),
+            );
+        }
+
+        let src = self.parse_reg().unwrap_or_else(|d| {
+            ctx.add_diag(d);
+            // use a dummy selector to allow recovery
+            RegSelector::new_gpr(0, Span::DUMMY)
+        });
+        Instruction::new(
+            InstructionKind::BitNot { src, dst },
+            Span::between(span_start, src.span()),
+        )
+    }
+
+    /// parses `zero dst`, which sets every element of `dst` to 0 by xoring it with itself
+    fn parse_zero(&mut self, ctx: &mut Context) -> Instruction {
         let span_start = self.current.span();
         self.bump();
         let mut was_err = false;
@@ -440,7 +1285,7 @@ impl<'a> Parser<'a> {
         }
 
         Instruction::new(
-            InstructionKind::UnaryBitNot { dst },
+            InstructionKind::BitXor { src: dst, dst },
             Span::between(span_start, dst.span()),
         )
     }
@@ -455,34 +1300,9 @@ impl<'a> Parser<'a> {
     ) -> Result<(OpSize, RegSelector, RegSelector, RegSelector), ()> {
         let span_start = self.current.span();
         self.bump();
+        let size = self.parse_size(ctx)?;
 
-        if !self.eat(&TokenKind::Dot) {
-            ctx.add_diag(Diagnostic::new(
-                String::from("math operands need a `.b` or `.w` to specify size"),
-                self.current.span(),
-            ));
-            return Err(());
-        }
-        let size = self.expect_ident().map_err(|d| {
-            ctx.add_diag(d.with_note(String::from(
-                "math operands need a `.b` or `.w` to specify size",
-            )));
-        })?;
-
-        let size = match size.as_str() {
-            "b" => OpSize::Byte,
-            "w" => OpSize::Word,
-            _ => {
-                ctx.add_diag(Diagnostic::new(
-                    String::from("math operands need a `.b` or `.w` to specify size"),
-                    self.current.span(),
-                ));
-                return Err(());
-            }
-        };
-        self.bump();
-
-        let mut was_reg_err = true;
+        let mut was_reg_err = false;
         let dst = self.parse_reg().unwrap_or_else(|d| {
             ctx.add_diag(d);
             was_reg_err = true;
@@ -540,17 +1360,22 @@ impl<'a> Parser<'a> {
         Ok((size, dst, lhs, rhs))
     }
 
-    fn parse_shift_common(
-        &mut self,
-        ctx: &mut Context,
-    ) -> Result<(OpSize, RegSelector, ShiftAmount), ()> {
-        self.bump();
-
+    /// parses the `.b` or `.w` size of a math or shift instruction, using the size set by
+    /// `.size` if there is none
+    fn parse_size(&mut self, ctx: &mut Context) -> Result<OpSize, ()> {
         if !self.eat(&TokenKind::Dot) {
-            ctx.add_diag(Diagnostic::new(
-                String::from("math operands need a `.b` or `.w` to specify size"),
-                self.current.span(),
-            ));
+            if let Some(size) = self.default_size {
+                return Ok(size);
+            }
+            ctx.add_diag(
+                Diagnostic::new(
+                    String::from("math operands need a `.b` or `.w` to specify size"),
+                    self.current.span(),
+                )
+                .with_note(String::from(
+                    "use `.size b` or `.size w` to set a size for instructions without one",
+                )),
+            );
             return Err(());
         }
         let size = self.expect_ident().map_err(|d| {
@@ -559,10 +1384,9 @@ impl<'a> Parser<'a> {
             )));
         })?;
 
-        let size = match size.as_str() {
-            "b" => OpSize::Byte,
-            "w" => OpSize::Word,
-            _ => {
+        let size = match OpSize::try_from(size.as_str()) {
+            Ok(size) => size,
+            Err(_) => {
                 ctx.add_diag(Diagnostic::new(
                     String::from("math operands need a `.b` or `.w` to specify size"),
                     self.current.span(),
@@ -571,6 +1395,15 @@ impl<'a> Parser<'a> {
             }
         };
         self.bump();
+        Ok(size)
+    }
+
+    fn parse_shift_common(
+        &mut self,
+        ctx: &mut Context,
+    ) -> Result<(OpSize, RegSelector, ShiftAmount), ()> {
+        self.bump();
+        let size = self.parse_size(ctx)?;
 
         let mut was_reg_err = false;
         // if there was an error parsing the dst register, use a dummy selector
@@ -590,12 +1423,14 @@ impl<'a> Parser<'a> {
         }
 
         let amount = match self.current.kind() {
-            TokenKind::Ident(_) => ShiftAmount::Register(self.parse_reg().unwrap_or_else(|d| {
-                ctx.add_diag(d);
-                was_reg_err = true;
-                // use a dummy selector to allow recovery
-                RegSelector::new_gpr(0, Span::DUMMY)
-            })),
+            TokenKind::Ident(_) | TokenKind::NonAsciiIdent(_) => {
+                ShiftAmount::Register(self.parse_reg().unwrap_or_else(|d| {
+                    ctx.add_diag(d);
+                    was_reg_err = true;
+                    // use a dummy selector to allow recovery
+                    RegSelector::new_gpr(0, Span::DUMMY)
+                }))
+            }
             TokenKind::Number(num) => {
                 let span = self.current.span();
                 let num = *num;
@@ -612,9 +1447,46 @@ impl<'a> Parser<'a> {
                     ShiftAmount::Const(num as u8, span)
                 }
             }
+            TokenKind::InvalidNumber(_) => {
+                ctx.add_diag(Diagnostic::new(
+                    String::from("shift amount must not be greater than 15"),
+                    self.current.span(),
+                ));
+                self.bump();
+                // dummy value for recovery
+                ShiftAmount::Const(0, Span::DUMMY)
+            }
+            TokenKind::MalformedNumber(text) => {
+                ctx.add_diag(Diagnostic::new(
+                    format!("invalid number `{}`", text),
+                    self.current.span(),
+                ));
+                self.bump();
+                // dummy value for recovery
+                ShiftAmount::Const(0, Span::DUMMY)
+            }
+            TokenKind::Minus => {
+                let span_start = self.current.span();
+                self.bump();
+                let span = Span::between(span_start, self.current.span());
+                ctx.add_diag(Diagnostic::new(
+                    String::from("shift amount must not be negative"),
+                    span,
+                ));
+                if matches!(
+                    self.current.kind(),
+                    TokenKind::Number(_)
+                        | TokenKind::InvalidNumber(_)
+                        | TokenKind::MalformedNumber(_)
+                ) {
+                    self.bump();
+                }
+                // dummy value for recovery
+                ShiftAmount::Const(0, Span::DUMMY)
+            }
             _ => {
                 ctx.add_diag(Diagnostic::new(
-                    String::from("expected a register or constant number as a shift amout"),
+                    String::from("expected a register or constant number as a shift amount"),
                     self.current.span(),
                 ));
                 return Err(());
@@ -680,15 +1552,14 @@ impl<'a> Parser<'a> {
         // memory operands must contain their selector in order starting with X
         // since they must be precisely x or xyzw
         // but register operands can be any ordered sequence of selectors
-        let select_mode = if is_mem {
-            SelectorMode::SquentialXStart
-        } else {
-            SelectorMode::Ordered
-        };
-
+        //
         // we can exit early here and the higher levels will eat until
         // end of line to prevent cascading errors
-        let set = self.parse_set_reg(ctx, select_mode)?;
+        let set = if is_mem {
+            self.parse_mem_reg(ctx)?
+        } else {
+            self.parse_set_reg(ctx, SelectorMode::Ordered)?
+        };
 
         if is_mem {
             let selector = set.selector();
@@ -724,15 +1595,49 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_reg(&mut self) -> Result<RegSelector, Diagnostic> {
-        let span = self.current.span();
+        let mut span = self.current.span();
+        if let TokenKind::Sigil(sigil) = *self.current.kind() {
+            if !self.register_sigils {
+                return Err(Diagnostic::new(
+                    format!("unexpected `{}` before register name", sigil),
+                    span,
+                )
+                .with_note(String::from("register sigils are not enabled")));
+            }
+            self.bump();
+
+            // the sigil must be part of the register name
+            if self.current.span().low() != span.high() {
+                return Err(Diagnostic::new(
+                    format!("expected register name immediately after `{}`", sigil),
+                    Span::between(span, self.current.span()),
+                ));
+            }
+            span = Span::between(span, self.current.span());
+
+            // a number after the sigil is the index of a general purpose register
+            if let TokenKind::Number(idx) = *self.current.kind() {
+                if idx > u16::from(MAX_REG_IDX) {
+                    return Err(Diagnostic::new(
+                        format!("invalid register `{}{}`", sigil, idx),
+                        span,
+                    )
+                    .with_note(format!("maximum register index is {}", MAX_REG_IDX)));
+                }
+                self.bump();
+                return Ok(RegSelector::new_gpr(idx as u8, span));
+            }
+        }
         let name = self.expect_ident().map_err(|d| {
+            if let TokenKind::NonAsciiIdent(_) = self.current.kind() {
+                return d;
+            }
             Diagnostic::new(
                 format!("expected register name got {}", self.current.kind()),
                 d.span(),
             )
         })?;
-        let reg = if name.starts_with('r') {
-            let idx = &name[1..];
+        let reg = if let Some(idx) = name.strip_prefix('r') {
             // ri is an alias for r7
             if idx == "i" {
                 RegSelector::new_gpr(7, span)
@@ -749,8 +1654,7 @@ impl<'a> Parser<'a> {
                 };
                 RegSelector::new_gpr(idx, span)
             }
-        } else if name.starts_with('c') {
-            let idx = &name[1..];
+        } else if let Some(idx) = name.strip_prefix('c') {
             let idx = match idx.parse::<u8>() {
                 Ok(idx) if idx <= MAX_REG_IDX => idx,
                 _ => {
@@ -798,6 +1702,45 @@ impl<'a> Parser<'a> {
         ))
     }
 
+    /// parses the register of a memory operand. a register without a selector, as in
+    /// `[r0]`, uses only its `x` element as the address, and `[r0.*]` is shorthand for
+    /// scattering to every element, like `[r0.xyzw]`.
+    fn parse_mem_reg(&mut self, ctx: &mut Context) -> Result<SetRegSelector, ()> {
+        let reg = self.parse_reg().map_err(|d| {
+            ctx.add_diag(d);
+        })?;
+        let selector = if self.eat(&TokenKind::Dot) {
+            if self.current.kind() == &TokenKind::Star {
+                let span = self.current.span();
+                self.bump();
+                SetSelector::from_bits(0b1111, span)
+            } else {
+                self.parse_set_selector(ctx, SelectorMode::SquentialXStart)?
+            }
+        } else {
+            if self.strict_selectors {
+                ctx.add_diag(
+                    Diagnostic::new(
+                        String::from("expected a selector for the memory operand"),
+                        self.current.span(),
+                    )
+                    .with_note(format!(
+                        "write `[{}.x]` to use only the `x` element as the address",
+                        reg
+                    )),
+                );
+                // recover with the default selector
+            }
+            SetSelector::from_bits(0b0001, reg.span())
+        };
+
+        Ok(SetRegSelector::new(
+            reg,
+            selector,
+            Span::between(reg.span(), selector.span()),
+        ))
+    }
+
     fn parse_swizzle_reg(&mut self, ctx: &mut Context) -> Result<SwizzleRegSelector, ()> {
         let reg = self.parse_reg().map_err(|d| {
             ctx.add_diag(d);
@@ -848,13 +1791,15 @@ impl<'a> Parser<'a> {
                 let mut selector = SetSelector::empty(ident_span);
                 let mut last_idx = -1;
 
-                for c in select_str.chars() {
-                    let idx: i32 = match c {
-                        'x' => 0b00,
-                        'y' => 0b01,
-                        'z' => 0b10,
-                        'w' => 0b11,
-                        _ => {
+                for (offset, c) in select_str.chars().enumerate() {
+                    let elem_span = Span::new_in_file(
+                        ident_span.low() + offset as u32,
+                        ident_span.low() + offset as u32 + 1,
+                        ident_span.file(),
+                    );
+                    let idx = match instruction::elem_idx(c) {
+                        Some(idx) => idx as i32,
+                        None => {
                             ctx.add_diag(Diagnostic::new(
                                 format!("invalid register selector {}", select_str),
                                 ident_span,
@@ -863,17 +1808,15 @@ impl<'a> Parser<'a> {
                         }
                     };
 
-                    // an element must either be first (last was -1) or preceded by the
-                    // previous element in order
-                    if last_idx == -1 || last_idx == (idx - 1) {
-                        if selector.set(idx as u8) {
-                            // NOTE: duplicate selectors are not an immediate return, just ignored for recovery
-                            ctx.add_diag(Diagnostic::new(
-                                format!("`{}` already present in selector", c),
-                                ident_span,
-                            ));
-                        }
-                    } else {
+                    if selector.set(idx as u8) {
+                        // NOTE: duplicate selectors are not an immediate return, just ignored for recovery
+                        ctx.add_diag(Diagnostic::new(
+                            format!("`{}` already present in selector", c),
+                            elem_span,
+                        ));
+                    } else if !(last_idx == -1 || last_idx == (idx - 1)) {
+                        // an element must either be first (last was -1) or preceded by the
+                        // previous element in order
                         ctx.add_diag(Diagnostic::new(
                             String::from("register selector must have its elements in order"),
                             ident_span,
@@ -923,12 +1866,9 @@ impl<'a> Parser<'a> {
 
         let mut selector = SwizzleSelector::empty(ident_span);
         for (idx, c) in select_str.chars().enumerate() {
-            match c {
-                'x' => selector.set(idx as u8, 0b00),
-                'y' => selector.set(idx as u8, 0b01),
-                'z' => selector.set(idx as u8, 0b10),
-                'w' => selector.set(idx as u8, 0b11),
-                _ => {
+            match instruction::elem_idx(c) {
+                Some(selected) => selector.set(idx as u8, selected),
+                None => {
                     ctx.add_diag(Diagnostic::new(
                         String::from("invalid register selector"),
                         ident_span,
@@ -941,14 +1881,41 @@ impl<'a> Parser<'a> {
         Ok(selector)
     }
 
+    /// eats all tokens until the end of the current line
+    fn eat_line(&mut self) {
+        while !matches!(self.current.kind(), TokenKind::Newline | TokenKind::EoF) {
+            self.bump();
+        }
+    }
+
+    /// whether the current token ends a line. every token does if newlines are ignored.
+    fn at_line_end(&self) -> bool {
+        self.ignore_newlines || matches!(self.current.kind(), TokenKind::Newline | TokenKind::EoF)
+    }
+
     fn bump(&mut self) {
-        let current = self.lexer.next_token();
-        self.current = current;
+        loop {
+            let current = self
+                .queued
+                .pop_front()
+                .unwrap_or_else(|| self.lexer.next_token());
+            self.current = current;
+            if !(self.ignore_newlines && self.current.kind() == &TokenKind::Newline) {
+                break;
+            }
+        }
     }
 
     fn expect_ident(&self) -> Result<String, Diagnostic> {
         match self.current.kind() {
             TokenKind::Ident(s) => Ok(s.clone()),
+            TokenKind::NonAsciiIdent(s) => Err(Diagnostic::new(
+                format!(
+                    "identifiers must only contain ASCII characters, found `{}`",
+                    s
+                ),
+                self.current.span(),
+            )),
             other => Err(Diagnostic::new(
                 format!("expected identifier, found `{}`", other),
                 self.current.span(),
@@ -968,6 +1935,247 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// sorts references into source order, removing the duplicates from expanding the
+/// same macro body more than once
+fn sorted_refs(mut spans: Vec<Span>) -> Vec<Span> {
+    spans.sort_by_key(|span| (span.file(), span.low(), span.high()));
+    spans.dedup_by_key(|span| (span.file(), span.low(), span.high()));
+    spans
+}
+
+/// warns on constant shifts that move every bit out of the element, which are
+/// almost always logic errors. rotates are not checked since they do not lose bits.
+/// an arithmetic shift by that much fills the element with its sign bit instead of
+/// emptying it, so it is worded differently.
+fn lint_shift_out_all(ctx: &mut Context, size: OpSize, amount: ShiftAmount, arithmetic: bool) {
+    let ShiftAmount::Const(val, span) = amount else {
+        return;
+    };
+    let width = size.bits();
+    if val >= width {
+        let msg = if arithmetic {
+            format!(
+                "shift amount {} fills every bit of the element with its sign bit",
+                val
+            )
+        } else {
+            format!("shift amount {} shifts out every bit of the element", val)
+        };
+        ctx.add_diag(
+            Diagnostic::new_warning(msg, span)
+                .with_note(format!("elements of this size are {} bits wide", width)),
+        );
+    }
+}
+
+/// the kind of an operand of an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    /// a register without a selector, such as `r0`
+    Register,
+    /// a register with elements selected in order, such as `r0.xy`
+    SetRegister,
+    /// a register with a selector for every element, such as `r0.wzyx`
+    SwizzleRegister,
+    /// a memory address, such as `[r0.x]`, `[r0.*]`, or `[r0.x]+`
+    Memory,
+    /// a register or a constant from 0 to 15
+    ShiftAmount,
+}
+
+type ParseFn = fn(&mut Parser<'_>, &mut Context) -> Result<Instruction, ()>;
+
+/// describes the syntax of an instruction that the parser accepts
+#[derive(Debug, Clone, Copy)]
+pub struct InstructionSpec {
+    pub mnemonic: &'static str,
+    /// other mnemonics that are parsed the same way
+    pub aliases: &'static [&'static str],
+    /// every list of operands that is accepted, in order
+    pub forms: &'static [&'static [OperandKind]],
+    /// the sizes that can follow the mnemonic, such as `add.b`.
+    /// this is empty if the instruction does not take a size.
+    pub sizes: &'static [OpSize],
+    parse: ParseFn,
+}
+
+const SIZES: &[OpSize] = &[OpSize::Byte, OpSize::Word];
+const MATH_FORMS: &[&[OperandKind]] = &[&[
+    OperandKind::Register,
+    OperandKind::Register,
+    OperandKind::Register,
+]];
+const SHIFT_FORMS: &[&[OperandKind]] = &[&[OperandKind::Register, OperandKind::ShiftAmount]];
+const BITOP_FORMS: &[&[OperandKind]] = &[&[OperandKind::Register, OperandKind::Register]];
+
+/// every instruction the parser accepts. the parser looks up mnemonics in this table,
+/// so it is always in sync with what can be parsed.
+pub(crate) const INSTRUCTION_SET: &[InstructionSpec] = &[
+    InstructionSpec {
+        mnemonic: mnemonic::MOV,
+        aliases: &[],
+        forms: &[
+            &[OperandKind::SetRegister, OperandKind::SetRegister],
+            &[OperandKind::SetRegister, OperandKind::Memory],
+            &[OperandKind::Memory, OperandKind::SetRegister],
+        ],
+        sizes: &[],
+        parse: |p, ctx| p.parse_move(ctx),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::SWIZZLE,
+        aliases: &[],
+        forms: &[&[OperandKind::SwizzleRegister]],
+        sizes: &[],
+        parse: |p, ctx| p.parse_swizzle(ctx),
+    },
+    // =========
+    // math ops
+    // =========
+    InstructionSpec {
+        mnemonic: mnemonic::ADD,
+        aliases: &[],
+        forms: MATH_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_add(ctx, AddMode::Normal),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::ADD_SAT,
+        aliases: &[],
+        forms: MATH_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_add(ctx, AddMode::Saturate),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::SUB,
+        aliases: &[],
+        forms: MATH_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_sub(ctx, SubMode::Normal),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::SUB_SAT,
+        aliases: &[],
+        forms: MATH_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_sub(ctx, SubMode::Saturate),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::CMPEQ,
+        aliases: &[],
+        forms: MATH_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_cmp(ctx, CmpMode::Eq),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::CMPNEQ,
+        aliases: &[],
+        forms: MATH_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_cmp(ctx, CmpMode::Neq),
+    },
+    // ==========
+    // shift ops
+    // ==========
+    InstructionSpec {
+        mnemonic: mnemonic::LSL,
+        aliases: &["asl"],
+        forms: SHIFT_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_lsl(ctx),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::ROL,
+        aliases: &[],
+        forms: SHIFT_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_rol(ctx),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::ASR,
+        aliases: &[],
+        forms: SHIFT_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_asr(ctx),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::LSR,
+        aliases: &[],
+        forms: SHIFT_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_lsr(ctx),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::ROR,
+        aliases: &[],
+        forms: SHIFT_FORMS,
+        sizes: SIZES,
+        parse: |p, ctx| p.parse_ror(ctx),
+    },
+    // ============
+    // bitwise ops
+    // ============
+    InstructionSpec {
+        mnemonic: mnemonic::AND,
+        aliases: &[],
+        forms: BITOP_FORMS,
+        sizes: &[],
+        parse: |p, ctx| Ok(p.parse_and(ctx)),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::OR,
+        aliases: &[],
+        forms: BITOP_FORMS,
+        sizes: &[],
+        parse: |p, ctx| Ok(p.parse_or(ctx)),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::XOR,
+        aliases: &[],
+        forms: BITOP_FORMS,
+        sizes: &[],
+        parse: |p, ctx| Ok(p.parse_xor(ctx)),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::NAND,
+        aliases: &[],
+        forms: BITOP_FORMS,
+        sizes: &[],
+        parse: |p, ctx| Ok(p.parse_nand(ctx)),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::NOR,
+        aliases: &[],
+        forms: BITOP_FORMS,
+        sizes: &[],
+        parse: |p, ctx| Ok(p.parse_nor(ctx)),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::XNOR,
+        aliases: &[],
+        forms: BITOP_FORMS,
+        sizes: &[],
+        parse: |p, ctx| Ok(p.parse_xnor(ctx)),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::NOT,
+        aliases: &[],
+        forms: &[
+            &[OperandKind::Register],
+            &[OperandKind::Register, OperandKind::Register],
+        ],
+        sizes: &[],
+        parse: |p, ctx| Ok(p.parse_not(ctx)),
+    },
+    InstructionSpec {
+        mnemonic: mnemonic::ZERO,
+        aliases: &[],
+        forms: &[&[OperandKind::Register]],
+        sizes: &[],
+        parse: |p, ctx| Ok(p.parse_zero(ctx)),
+    },
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum AddMode {
     Normal,
@@ -1000,3 +2208,765 @@ enum LoadStoreOp {
     MemOp(MemoryOperand),
     RegOp(SetRegSelector),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diag::Severity;
+
+    /// parses every instruction in `src`, returning the instructions and every diagnostic
+    fn parse(src: &str) -> (Vec<Instruction>, Vec<Diagnostic>) {
+        parse_with(src, |parser| parser)
+    }
+
+    /// parses every instruction in `src` with a parser set up by `configure`
+    fn parse_with<'a>(
+        src: &'a str,
+        configure: impl FnOnce(Parser<'a>) -> Parser<'a>,
+    ) -> (Vec<Instruction>, Vec<Diagnostic>) {
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let mut parser = configure(Parser::new(src));
+        let mut insts = Vec::new();
+        while let Ok(Some(inst)) = parser.parse_inst(&mut ctx) {
+            insts.push(inst);
+        }
+        (insts, ctx.take_diags())
+    }
+
+    fn messages(diags: &[Diagnostic]) -> Vec<&str> {
+        diags.iter().map(|d| d.msg()).collect()
+    }
+
+    #[test]
+    fn shift_out_all_warns() {
+        let (insts, diags) = parse("lsl.b r0, 8\n");
+        assert_eq!(insts.len(), 1);
+        assert_eq!(
+            messages(&diags),
+            ["shift amount 8 shifts out every bit of the element"]
+        );
+        assert_eq!(diags[0].severity(), Severity::Warning);
+
+        let (_, diags) = parse("lsr.b r0, 12\n");
+        assert_eq!(
+            messages(&diags),
+            ["shift amount 12 shifts out every bit of the element"]
+        );
+    }
+
+    #[test]
+    fn shift_within_element_does_not_warn() {
+        for src in ["lsl.w r0, 15\n", "lsl.b r0, 7\n", "lsr.w r0, r1\n"] {
+            let (insts, diags) = parse(src);
+            assert_eq!(insts.len(), 1, "{}", src);
+            assert!(diags.is_empty(), "{}: {:?}", src, messages(&diags));
+        }
+    }
+
+    #[test]
+    fn rotate_out_all_does_not_warn() {
+        let (_, diags) = parse("rol.b r0, 8\nror.b r0, 15\n");
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+    }
+
+    #[test]
+    fn arithmetic_shift_out_all_is_worded_for_sign() {
+        let (_, diags) = parse("asr.b r0, 8\n");
+        assert_eq!(
+            messages(&diags),
+            ["shift amount 8 fills every bit of the element with its sign bit"]
+        );
+        assert_eq!(diags[0].severity(), Severity::Warning);
+    }
+
+    /// parses `src`, returning the const registers it sets and every diagnostic
+    fn parse_consts(src: &str) -> (ConstRegs, Vec<Diagnostic>) {
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let mut parser = Parser::new(src);
+        while let Ok(Some(_)) = parser.parse_inst(&mut ctx) {}
+        (parser.consts().clone(), ctx.take_diags())
+    }
+
+    #[test]
+    fn const_signed_values() {
+        let (consts, diags) = parse_consts(".const c0, -1, 2, -3, 4\n.const c1, -32768\n");
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(consts.get(0), Some([0xFFFF, 2, 0xFFFD, 4]));
+        assert_eq!(consts.get(1), Some([0x8000, 0, 0, 0]));
+    }
+
+    #[test]
+    fn const_unsigned_values() {
+        let (consts, diags) =
+            parse_consts(".const c2, 65535, 0x8000, 0b11\n.const c3, {1, 2, 3, 4}\n");
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        // missing elements are 0
+        assert_eq!(consts.get(2), Some([0xFFFF, 0x8000, 3, 0]));
+        assert_eq!(consts.get(3), Some([1, 2, 3, 4]));
+        assert_eq!(consts.get(4), None);
+    }
+
+    #[test]
+    fn const_value_range_errors() {
+        let src = ".const c0, 1, -32769\n";
+        let (_, diags) = parse_consts(src);
+        assert_eq!(messages(&diags), ["value -32769 does not fit in 16 bits"]);
+        assert_eq!(diags[0].span().span_text(src), "-32769");
+
+        let src = ".const c0, 65536\n";
+        let (_, diags) = parse_consts(src);
+        assert_eq!(messages(&diags), ["value 65536 does not fit in 16 bits"]);
+        assert_eq!(diags[0].span().span_text(src), "65536");
+
+        let (_, diags) = parse_consts(".const c0, 1, 2, 3, 4, 5\n");
+        assert_eq!(messages(&diags), ["const registers only have 4 elements"]);
+    }
+
+    #[test]
+    fn register_sigils() {
+        let src = "add.w %r0, $r0, %c1\nmov $ri.x, %r2.x\n";
+        let (insts, diags) = parse_with(src, |p| p.with_register_sigils(true));
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(insts[0].kind().to_string(), "add.w r0, r0, c1");
+        assert_eq!(insts[1].kind().to_string(), "mov ri.x, r2.x");
+        // the sigil is part of the register's span
+        let InstructionKind::Add { dst, .. } = *insts[0].kind() else {
+            panic!("expected an add");
+        };
+        assert_eq!(dst.span().span_text(src), "%r0");
+    }
+
+    #[test]
+    fn numbered_register_sigils() {
+        let src = "add.w $0, %0, c1\nmov $1.xy, [%7]\n";
+        let (insts, diags) = parse_with(src, |p| p.with_register_sigils(true));
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(insts[0].kind().to_string(), "add.w r0, r0, c1");
+        assert_eq!(insts[1].kind().to_string(), "mov r1.xy, [ri.x]");
+        let InstructionKind::Add { dst, .. } = *insts[0].kind() else {
+            panic!("expected an add");
+        };
+        assert_eq!(dst.span().span_text(src), "$0");
+
+        let src = "not $8\n";
+        let (_, diags) = parse_with(src, |p| p.with_register_sigils(true));
+        assert_eq!(messages(&diags), ["invalid register `$8`"]);
+        assert_eq!(diags[0].span().span_text(src), "$8");
+
+        // numbers are only registers after a sigil
+        let (_, diags) = parse_with("not 0\n", |p| p.with_register_sigils(true));
+        assert_eq!(messages(&diags), ["expected register name got 0"]);
+        let (_, diags) = parse("not $0\n");
+        assert_eq!(messages(&diags), ["unexpected `$` before register name"]);
+    }
+
+    #[test]
+    fn register_sigils_need_option() {
+        let (_, diags) = parse("not %r0\n");
+        assert_eq!(messages(&diags), ["unexpected `%` before register name"]);
+        assert_eq!(diags[0].notes()[0].msg(), "register sigils are not enabled");
+    }
+
+    #[test]
+    fn register_sigil_must_touch_name() {
+        let (_, diags) = parse_with("not % r0\n", |p| p.with_register_sigils(true));
+        assert_eq!(
+            messages(&diags),
+            ["expected register name immediately after `%`"]
+        );
+    }
+
+    #[test]
+    fn duplicate_selector_element() {
+        let src = "mov r0.xx, r1.x\n";
+        let (_, diags) = parse(src);
+        assert_eq!(messages(&diags), ["`x` already present in selector"]);
+        // the span is the second `x`
+        assert_eq!(diags[0].span().low(), 8);
+        assert_eq!(diags[0].span().span_text(src), "x");
+    }
+
+    #[test]
+    fn multiple_duplicate_selector_elements() {
+        let src = "mov r0.xyxy, r1.xy\n";
+        let (insts, diags) = parse(src);
+        assert_eq!(
+            messages(&diags),
+            [
+                "`x` already present in selector",
+                "`y` already present in selector"
+            ]
+        );
+        assert_eq!(diags[0].span().low(), 9);
+        assert_eq!(diags[1].span().low(), 10);
+        // the duplicates are ignored for recovery
+        assert_eq!(insts[0].kind().to_string(), "mov r0.xy, r1.xy");
+    }
+
+    /// writes each of `files` into a new directory, returning the path of the directory
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wavevm_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, src) in files {
+            fs::write(dir.join(file), src).unwrap();
+        }
+        dir
+    }
+
+    /// parses the file at `path` and anything it includes, returning the context that
+    /// holds every file and diagnostic
+    fn parse_file(path: &std::path::Path) -> (Vec<Instruction>, Context) {
+        let source = Source::new_from_file(path).unwrap();
+        let src = source.src().to_owned();
+        let mut ctx = Context::new(source);
+        let mut parser = Parser::new(&src);
+        let mut insts = Vec::new();
+        while let Ok(Some(inst)) = parser.parse_inst(&mut ctx) {
+            insts.push(inst);
+        }
+        (insts, ctx)
+    }
+
+    #[test]
+    fn include_file() {
+        let dir = write_files(
+            "include_file",
+            &[
+                ("main.s", "not r0\n.include \"inc.s\"\nnot r2\n"),
+                ("inc.s", ".const c1, 5\nnot r1\n"),
+            ],
+        );
+        let (insts, mut ctx) = parse_file(&dir.join("main.s"));
+        let diags = ctx.take_diags();
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        let text = insts
+            .iter()
+            .map(|inst| inst.kind().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(text, ["not r0", "not r1", "not r2"]);
+        // the included instruction has a span in the included file
+        assert_eq!(insts[1].span().file(), 1);
+        assert!(ctx.file(1).filename().ends_with("inc.s"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_sets_default_size() {
+        let dir = write_files(
+            "include_size",
+            &[
+                ("main.s", ".include \"size.s\"\nadd r0, r0, c1\n"),
+                ("size.s", ".size b\n"),
+            ],
+        );
+        let (insts, mut ctx) = parse_file(&dir.join("main.s"));
+        let diags = ctx.take_diags();
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(insts[0].kind().to_string(), "add.b r0, r0, c1");
+        fs::remove_dir_all(dir).unwrap();
+
+        // the included file also starts with the size of the including file
+        let dir = write_files(
+            "include_size_inherit",
+            &[
+                ("main.s", ".size w\n.include \"inc.s\"\n"),
+                ("inc.s", "lsl r0, 3\n"),
+            ],
+        );
+        let (insts, mut ctx) = parse_file(&dir.join("main.s"));
+        let diags = ctx.take_diags();
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(insts[0].kind().to_string(), "lsl.w r0, 3");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = write_files(
+            "include_cycle",
+            &[("a.s", ".include \"b.s\"\n"), ("b.s", ".include \"a.s\"\n")],
+        );
+        let (_, mut ctx) = parse_file(&dir.join("a.s"));
+        let diags = ctx.take_diags();
+        assert_eq!(messages(&diags), ["`a.s` is included recursively"]);
+        assert!(diags[0].notes()[0].msg().starts_with("include chain: "));
+        // the error is at the include in the second file
+        assert_eq!(diags[0].span().file(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn error_location_in_included_file() {
+        let dir = write_files(
+            "include_error",
+            &[
+                ("main.s", "not r0\nnot r1\n.include \"inc.s\"\n"),
+                ("inc.s", "mov r0.xx, r1.x\n"),
+            ],
+        );
+        let (_, mut ctx) = parse_file(&dir.join("main.s"));
+        let diags = ctx.take_diags();
+        assert_eq!(messages(&diags), ["`x` already present in selector"]);
+        let span = diags[0].span();
+        assert_eq!(span.file(), 1);
+        assert_eq!(span.span_text(ctx.file(1).src()), "x");
+        let location = crate::diag::location(&ctx, span);
+        assert!(location.contains("inc.s:1:8"), "{}", location);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn non_ascii_identifier_is_rejected() {
+        let src = "not ré\n";
+        let (_, diags) = parse(src);
+        assert_eq!(
+            messages(&diags),
+            ["identifiers must only contain ASCII characters, found `ré`"]
+        );
+        assert_eq!(diags[0].span().span_text(src), "ré");
+
+        let (_, diags) = parse("möv r0.x, r1.x\n");
+        assert_eq!(
+            messages(&diags)[0],
+            "identifiers must only contain ASCII characters, found `möv`"
+        );
+    }
+
+    #[test]
+    fn addressing_forms() {
+        for (src, display, scatter, increment) in [
+            ("mov r0.x, [r1]", "mov r0.x, [r1.x]", false, false),
+            ("mov r0.x, [r1.x]", "mov r0.x, [r1.x]", false, false),
+            ("mov r0.x, [r1]+", "mov r0.x, [r1.x]+", false, true),
+            ("mov r0.x, [r1.x]+", "mov r0.x, [r1.x]+", false, true),
+            ("mov r0.xyzw, [r1.*]", "mov r0.xyzw, [r1.xyzw]", true, false),
+            (
+                "mov r0.xyzw, [r1.xyzw]",
+                "mov r0.xyzw, [r1.xyzw]",
+                true,
+                false,
+            ),
+            (
+                "mov [r1.*]+, r0.xyzw",
+                "mov [r1.xyzw]+, r0.xyzw",
+                true,
+                true,
+            ),
+        ] {
+            let (insts, diags) = parse(src);
+            assert!(diags.is_empty(), "{}: {:?}", src, messages(&diags));
+            let (InstructionKind::Load { mem, .. } | InstructionKind::Store { mem, .. }) =
+                *insts[0].kind()
+            else {
+                panic!("expected a memory move for {}", src);
+            };
+            assert_eq!(
+                (mem.scatter(), mem.increment()),
+                (scatter, increment),
+                "{}",
+                src
+            );
+            assert_eq!(insts[0].kind().to_string(), display);
+
+            // the displayed form parses back to the same operand
+            let (reparsed, _) = parse(display);
+            assert_eq!(reparsed[0].kind().to_string(), display);
+        }
+    }
+
+    fn texts(insts: &[Instruction]) -> Vec<String> {
+        insts.iter().map(|inst| inst.kind().to_string()).collect()
+    }
+
+    #[test]
+    fn simple_macro() {
+        let src = ".macro clear\n    xor r0, r0\n    not r0\n.endm\nclear\nnot r1\nclear\n";
+        let (insts, diags) = parse(src);
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(
+            texts(&insts),
+            ["xor r0, r0", "not r0", "not r1", "xor r0, r0", "not r0"]
+        );
+    }
+
+    #[test]
+    fn macro_argument_substitution() {
+        let src =
+            ".macro addto dst, src\n    add.w dst, dst, src\n.endm\naddto r1, c2\naddto ri, r3\n";
+        let (insts, diags) = parse(src);
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(texts(&insts), ["add.w r1, r1, c2", "add.w ri, ri, r3"]);
+    }
+
+    #[test]
+    fn macro_arity_mismatch() {
+        let src =
+            ".macro addto dst, src\n    add.w dst, dst, src\n.endm\naddto r1\naddto r1, r2, r3\n";
+        let (insts, diags) = parse(src);
+        assert!(insts.is_empty());
+        assert_eq!(
+            messages(&diags),
+            [
+                "macro `addto` takes 2 arguments, but 1 was given",
+                "macro `addto` takes 2 arguments, but 3 were given",
+            ]
+        );
+        assert_eq!(diags[0].span().span_text(src), "addto r1");
+    }
+
+    #[test]
+    fn recursive_macro() {
+        let (_, diags) = parse(".macro loop\n    loop\n.endm\nloop\n");
+        assert_eq!(
+            messages(&diags),
+            ["macro expansion exceeds the maximum depth of 64"]
+        );
+    }
+
+    #[test]
+    fn ri_written_twice() {
+        let (_, diags) = parse("mov ri.x, [ri]+\n");
+        assert_eq!(
+            messages(&diags),
+            ["`ri` is written more than once by this instruction"]
+        );
+        assert_eq!(diags[0].severity(), Severity::Error);
+        let notes = diags[0].notes().iter().map(|n| n.msg()).collect::<Vec<_>>();
+        assert_eq!(notes, ["it is also written here", "writing to `ri` jumps"]);
+    }
+
+    #[test]
+    fn ri_written_once() {
+        let (_, diags) = parse("mov ri.x, [r0]+\nmov ri.x, [ri]\n");
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+
+        let (_, diags) = parse("mov r0.x, [ri]+\n");
+        assert_eq!(messages(&diags), ["incrementing `ri` jumps"]);
+        assert_eq!(diags[0].severity(), Severity::Warning);
+    }
+
+    #[test]
+    fn braced_const_values() {
+        let (consts, diags) = parse_consts(".const c0, {1, -2}\n.const c1, { 0x10 }\n");
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(consts.get(0), Some([1, 0xFFFE, 0, 0]));
+        assert_eq!(consts.get(1), Some([0x10, 0, 0, 0]));
+    }
+
+    #[test]
+    fn unclosed_braces() {
+        let (_, diags) = parse_consts(".const c0, {1, 2\n");
+        assert_eq!(messages(&diags), ["expected `,` or `}`, found `<\\n>`"]);
+
+        let (_, diags) = parse_consts(".const c0, {1, 2} 3\n");
+        assert_eq!(messages(&diags), ["expected end of line, found `3`"]);
+    }
+
+    #[test]
+    fn strict_selectors_require_memory_selectors() {
+        let strict = |parser: Parser<'static>| parser.with_strict_selectors(true);
+
+        let (_, diags) = parse("mov r0.x, [r1]\n");
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        let (insts, diags) = parse_with("mov r0.x, [r1]\n", strict);
+        assert_eq!(
+            messages(&diags),
+            ["expected a selector for the memory operand"]
+        );
+        assert_eq!(
+            diags[0].notes()[0].msg(),
+            "write `[r1.x]` to use only the `x` element as the address"
+        );
+        // the default selector is used to recover
+        assert_eq!(texts(&insts), ["mov r0.x, [r1.x]"]);
+
+        for src in ["mov r0.x, [r1.x]\n", "mov [r1.*]+, r0.xyzw\n"] {
+            let (_, diags) = parse_with(src, strict);
+            assert!(diags.is_empty(), "{}: {:?}", src, messages(&diags));
+        }
+    }
+
+    /// the number of `InstructionKind` variants, which must be updated when one is added
+    const VARIANT_COUNT: usize = 25;
+
+    /// a unique index for each variant, which fails to compile when a variant is added
+    fn variant_idx(kind: &InstructionKind) -> usize {
+        use InstructionKind::*;
+        match kind {
+            Move { .. } => 0,
+            Swizzle { .. } => 1,
+            Load { .. } => 2,
+            Store { .. } => 3,
+            Add { .. } => 4,
+            Sub { .. } => 5,
+            SubRev { .. } => 6,
+            CmpEq { .. } => 7,
+            CmpNeq { .. } => 8,
+            AddSaturate { .. } => 9,
+            SubSaturate { .. } => 10,
+            SubRevSaturate { .. } => 11,
+            ShiftLeft { .. } => 12,
+            ShiftRightLogical { .. } => 13,
+            ShiftRightArithmetic { .. } => 14,
+            RotateLeft { .. } => 15,
+            RotateRight { .. } => 16,
+            BitAnd { .. } => 17,
+            BitOr { .. } => 18,
+            BitXor { .. } => 19,
+            BitNand { .. } => 20,
+            BitNor { .. } => 21,
+            BitXnor { .. } => 22,
+            UnaryBitNot { .. } => 23,
+            BitNot { .. } => 24,
+        }
+    }
+
+    #[test]
+    fn every_variant_has_a_spec() {
+        let src = "mov r0.x, r1.x\nswizzle r0.wzyx\nmov r0.x, [r1]\nmov [r1], r0.x\n\
+                   add.w r0, r0, c1\nsub.w r0, c1, r0\nsub.w r0, r0, c1\n\
+                   cmpeq.w r0, r0, c1\ncmpneq.w r0, r0, c1\nadd_sat.w r0, r0, c1\n\
+                   sub_sat.w r0, c1, r0\nsub_sat.w r0, r0, c1\n\
+                   lsl.w r0, 1\nlsr.w r0, 1\nasr.w r0, 1\nrol.w r0, 1\nror.w r0, 1\n\
+                   and r0, r1\nor r0, r1\nxor r0, r1\nnand r0, r1\nnor r0, r1\n\
+                   xnor r0, r1\nnot r0\nnot r0, r1\nzero r0\n";
+        let (insts, diags) = parse(src);
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+
+        let mut seen = [false; VARIANT_COUNT];
+        for inst in &insts {
+            seen[variant_idx(inst.kind())] = true;
+            let mnemonic = inst.kind().mnemonic();
+            // the text is written with the same mnemonic
+            let text = inst.kind().to_string();
+            assert_eq!(text.split(['.', ' ']).next(), Some(mnemonic), "{}", text);
+            assert!(
+                INSTRUCTION_SET.iter().any(|spec| spec.mnemonic == mnemonic),
+                "no spec for `{}`",
+                mnemonic
+            );
+        }
+        assert!(seen.iter().all(|&seen| seen), "{:?}", seen);
+
+        // every spec is used by the source above
+        for spec in INSTRUCTION_SET {
+            assert!(
+                src.lines()
+                    .any(|line| line.split(['.', ' ']).next() == Some(spec.mnemonic)),
+                "`{}` is not tested",
+                spec.mnemonic
+            );
+        }
+    }
+
+    #[test]
+    fn move_to_elements_missing_from_the_source() {
+        let src = "mov r0.xyzw, r1.xy\n";
+        let (_, diags) = parse(src);
+        assert_eq!(
+            messages(&diags),
+            ["lhs and rhs of move must select the same elements"]
+        );
+        assert_eq!(diags[0].span().span_text(src), "r0.xyzw, r1.xy");
+        assert_eq!(
+            diags[0].notes()[0].msg(),
+            "elements `zw` are not selected in the source, so there is no value to move"
+        );
+
+        // the source selecting more elements than the dst has nothing missing to name
+        let (_, diags) = parse("mov r0.xy, r1.xyzw\n");
+        assert_eq!(
+            messages(&diags),
+            ["lhs and rhs of move must select the same elements"]
+        );
+        assert!(diags[0].notes().is_empty());
+    }
+
+    #[test]
+    fn ri_as_an_address() {
+        let (insts, diags) = parse("mov r0.x, [ri]\nmov r0.x, [ri]+\n");
+        // ri is only incremented once, which jumps, so only the increment warns
+        assert_eq!(messages(&diags), ["incrementing `ri` jumps"]);
+        assert_eq!(diags[0].severity(), Severity::Warning);
+        assert_eq!(texts(&insts), ["mov r0.x, [ri.x]", "mov r0.x, [ri.x]+"]);
+
+        let plain = crate::codegen::gen_inst(insts[0]);
+        let incremented = crate::codegen::gen_inst(insts[1]);
+        let (InstructionKind::Load { mem, .. }, InstructionKind::Load { mem: inc_mem, .. }) =
+            (*insts[0].kind(), *insts[1].kind())
+        else {
+            panic!("expected loads");
+        };
+        assert!(mem.reg().is_ri() && inc_mem.reg().is_ri());
+        assert!(!mem.increment() && inc_mem.increment());
+        // the encodings only differ in the increment
+        assert_eq!(plain.len(), 1);
+        assert_eq!((plain[0], incremented[0]), (0x8FC6, 0x8FD6));
+    }
+
+    #[test]
+    fn swizzle_registers() {
+        let (insts, diags) = parse("swizzle r3.wzyx\n");
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(texts(&insts), ["swizzle r3.wzyx"]);
+
+        // a swizzle writes its register back, so const registers cannot be swizzled
+        let src = "swizzle c1.wzyx\n";
+        let (_, diags) = parse(src);
+        assert_eq!(
+            messages(&diags)[0],
+            "expected dst to be a writable register, got c1"
+        );
+        assert_eq!(diags[0].severity(), Severity::Error);
+        assert_eq!(diags[0].span().span_text(src), "c1.wzyx");
+
+        let (insts, diags) = parse("swizzle ri.xyzw\n");
+        assert_eq!(messages(&diags), ["swizzling `ri` jumps"]);
+        assert_eq!(diags[0].severity(), Severity::Warning);
+        assert_eq!(texts(&insts), ["swizzle ri.xyzw"]);
+    }
+
+    #[test]
+    fn ignored_newlines_match_normal_parsing() {
+        let ignore = |parser: Parser<'static>| parser.with_ignore_newlines(true);
+        let lines = "not r0\nadd.w r0, r0, c1\nmov r1.xy, [r0]+\nswizzle r1.yxzw\n";
+        let (normal, diags) = parse(lines);
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+
+        let joined = "not r0 add.w r0, r0, c1 mov r1.xy, [r0]+ swizzle r1.yxzw";
+        let (ignored, diags) = parse_with(joined, ignore);
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(texts(&ignored), texts(&normal));
+
+        // operands may be split over lines
+        let (split, diags) = parse_with("add.w\nr0,\nr0,\nc1\n\nnot\nr0", ignore);
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(texts(&split), ["add.w r0, r0, c1", "not r0"]);
+    }
+
+    #[test]
+    fn newlines_separate_instructions_normally() {
+        // an instruction cannot be split over lines unless newlines are ignored
+        let (_, diags) = parse("add.w\nr0,\nr0,\nc1\n");
+        assert_eq!(messages(&diags)[0], "`add` takes 3 operands, found 0");
+
+        let (_, diags) = parse_with(".macro m\nnot r0\n.endm\n", |parser| {
+            parser.with_ignore_newlines(true)
+        });
+        assert_eq!(
+            messages(&diags)[0],
+            "macros cannot be defined when newlines are ignored"
+        );
+    }
+
+    #[test]
+    fn too_few_operands() {
+        let src = "add.w r0, r0\n";
+        let (_, diags) = parse(src);
+        assert_eq!(messages(&diags), ["`add` takes 3 operands, found 2"]);
+        assert_eq!(
+            diags[0].notes()[0].msg(),
+            "math operands are of the form `op dst, lhs, rhs`"
+        );
+        // points at where the missing operand was expected
+        assert_eq!(diags[0].span().low() as usize, src.trim_end().len());
+
+        let (_, diags) = parse("mov r0\n");
+        assert_eq!(messages(&diags), ["`mov` takes 2 operands, found 1"]);
+        assert!(diags[0].notes().is_empty());
+    }
+
+    #[test]
+    fn too_many_operands() {
+        let src = "add.w r0, r0, c1, r1\n";
+        let (_, diags) = parse(src);
+        assert_eq!(messages(&diags), ["`add` takes 3 operands, found 4"]);
+        assert_eq!(diags[0].span().span_text(src), "r1");
+
+        let src = "mov r0, r1, r2, r3\n";
+        let (_, diags) = parse(src);
+        assert_eq!(messages(&diags), ["`mov` takes 2 operands, found 4"]);
+        // every extra operand is covered
+        assert_eq!(diags[0].span().span_text(src), "r2, r3");
+    }
+
+    #[test]
+    fn shift_amount_out_of_range() {
+        for (src, text) in [
+            ("lsl.w r0, 16\n", "16"),
+            ("lsr.b r0, 255\n", "255"),
+            ("rol.w r0, 0x10000\n", "0x10000"),
+        ] {
+            let (_, diags) = parse(src);
+            assert_eq!(
+                messages(&diags),
+                ["shift amount must not be greater than 15"],
+                "{}",
+                src
+            );
+            assert_eq!(diags[0].span().span_text(src), text, "{}", src);
+        }
+
+        let src = "asr.w r0, -1\n";
+        let (_, diags) = parse(src);
+        assert_eq!(messages(&diags), ["shift amount must not be negative"]);
+        assert_eq!(diags[0].span().span_text(src), "-1");
+    }
+
+    #[test]
+    fn size_directive_sets_default() {
+        let (insts, diags) =
+            parse(".size b\nadd r0, r0, c1\nlsl r1, 3\n.size w\nnot r2\nsub r0, r0, r1\n");
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(
+            texts(&insts),
+            [
+                "add.b r0, r0, c1",
+                "lsl.b r1, 3",
+                "not r2",
+                "sub.w r0, r0, r1"
+            ]
+        );
+    }
+
+    #[test]
+    fn explicit_size_overrides_default() {
+        let (insts, diags) = parse(".size b\nadd.w r0, r0, c1\nadd r0, r0, c1\n");
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        assert_eq!(texts(&insts), ["add.w r0, r0, c1", "add.b r0, r0, c1"]);
+    }
+
+    #[test]
+    fn size_directive_in_a_stream() {
+        let src = ".size b\nadd r0, r0, c1\nadd.w r0, r0, c1\n.size w\nlsl r1, 3\n";
+        let mut bytes = Vec::new();
+        let consts = crate::assemble_stream(
+            "test.s",
+            src.as_bytes(),
+            &crate::options::AssembleOptions::default(),
+            |code| bytes.extend_from_slice(code),
+        )
+        .unwrap();
+        assert!(consts.is_some());
+        let (insts, diags) = parse(src);
+        assert!(diags.is_empty(), "{:?}", messages(&diags));
+        let words = crate::codegen::gen(&insts);
+        assert_eq!(
+            bytes,
+            crate::codegen::to_bytes(&words, crate::options::Endianness::Little)
+        );
+        assert_eq!(words[0], 0x8108);
+    }
+
+    #[test]
+    fn missing_size_without_default() {
+        let (_, diags) = parse("add r0, r0, c1\n");
+        assert_eq!(
+            messages(&diags),
+            ["math operands need a `.b` or `.w` to specify size"]
+        );
+
+        let (_, diags) = parse(".size q\n");
+        assert_eq!(messages(&diags), ["expected a size, found `q`"]);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    codegen, consts::ConstRegs, diag::Diagnostic, instruction::Instruction, lexer::Span,
+    options::Endianness, validate,
+};
+
+/// a parsed program, along with the source it was parsed from
+#[derive(Debug, Clone)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+    consts: ConstRegs,
+    /// the span of every definition and invocation of each macro, which are the only
+    /// symbols
+    symbols: HashMap<String, Vec<Span>>,
+    src: String,
+}
+
+impl Program {
+    pub fn new(instructions: Vec<Instruction>, consts: ConstRegs, src: String) -> Self {
+        Self::with_symbols(instructions, consts, HashMap::new(), src)
+    }
+
+    pub(crate) fn with_symbols(
+        instructions: Vec<Instruction>,
+        consts: ConstRegs,
+        symbols: HashMap<String, Vec<Span>>,
+        src: String,
+    ) -> Self {
+        Self {
+            instructions,
+            consts,
+            symbols,
+            src,
+        }
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        self.instructions.as_slice()
+    }
+
+    pub fn instructions_mut(&mut self) -> &mut Vec<Instruction> {
+        &mut self.instructions
+    }
+
+    /// the values of the const registers set by the program
+    pub fn consts(&self) -> &ConstRegs {
+        &self.consts
+    }
+
+    pub fn src(&self) -> &str {
+        self.src.as_str()
+    }
+
+    /// the spans of every definition and invocation of the macro `name`, in source order.
+    /// this is empty if `name` is not a macro.
+    pub fn references(&self, name: &str) -> &[Span] {
+        self.symbols.get(name).map_or(&[], |spans| spans.as_slice())
+    }
+
+    /// checks that every instruction can be encoded, see [`validate::validate`]
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        validate::validate(self.instructions())
+    }
+
+    /// encodes the code in the given byte order, or returns the diagnostics if any
+    /// instruction cannot be encoded. warnings do not stop the program from being encoded.
+    pub fn encode(&self, endianness: Endianness) -> Result<Vec<u8>, Vec<Diagnostic>> {
+        let mut out = Vec::new();
+        codegen::encode_into(self.instructions(), &mut out, endianness)?;
+        Ok(out)
+    }
+}
+
+/// displays the program as assembly that parses back to the same const registers and
+/// instructions, with one directive or instruction on each line
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, [x, y, z, w]) in self.consts.iter() {
+            writeln!(f, ".const c{}, {:#X}, {:#X}, {:#X}, {:#X}", idx, x, y, z, w)?;
+        }
+        for inst in &self.instructions {
+            writeln!(f, "{}", inst.kind())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        diag::{Context, Severity},
+        instruction::{InstructionKind, RegSelector},
+        options::AssembleOptions,
+        source::Source,
+    };
+
+    fn parse(src: &str) -> Program {
+        let mut ctx = Context::new(Source::new(String::from("test.s"), src.to_string()));
+        let program = crate::parse(&mut ctx, &AssembleOptions::default());
+        assert!(!ctx.had_errs(), "{:?}", ctx.take_diags());
+        program
+    }
+
+    #[test]
+    fn construct_and_query() {
+        let inst = Instruction::new(
+            InstructionKind::UnaryBitNot {
+                dst: RegSelector::new_gpr(0, Span::DUMMY),
+            },
+            Span::DUMMY,
+        );
+        let mut consts = ConstRegs::default();
+        consts.set(1, [1, 2, 3, 4]);
+        let program = Program::new(vec![inst], consts.clone(), String::from("not r0\n"));
+        assert_eq!(program.instructions().len(), 1);
+        assert_eq!(program.consts(), &consts);
+        assert_eq!(program.src(), "not r0\n");
+        assert!(program.references("not").is_empty());
+        assert!(program.validate().is_empty());
+        assert_eq!(program.encode(Endianness::Little).unwrap(), [0x3C, 0x80]);
+        assert_eq!(program.encode(Endianness::Big).unwrap(), [0x80, 0x3C]);
+    }
+
+    #[test]
+    fn parsed_program() {
+        let src = ".const c1, 1\n.macro inv r\n    not r\n.endm\ninv r0\nadd.w r0, r0, c1\n";
+        let mut program = parse(src);
+        assert_eq!(program.src(), src);
+        assert_eq!(program.consts().get(1), Some([1, 0, 0, 0]));
+        assert_eq!(program.references("inv").len(), 2);
+        assert_eq!(
+            program.to_string(),
+            ".const c1, 0x1, 0x0, 0x0, 0x0\nnot r0\nadd.w r0, r0, c1\n"
+        );
+        // the displayed program parses back to the same program
+        let reparsed = parse(&program.to_string());
+        assert_eq!(reparsed.to_string(), program.to_string());
+        assert_eq!(reparsed.consts(), program.consts());
+
+        program.instructions_mut().pop();
+        assert_eq!(program.encode(Endianness::Little).unwrap(), [0x3C, 0x80]);
+    }
+
+    #[test]
+    fn invalid_program() {
+        let inst = Instruction::new(
+            InstructionKind::UnaryBitNot {
+                dst: RegSelector::new_const(0, Span::DUMMY),
+            },
+            Span::DUMMY,
+        );
+        let program = Program::new(vec![inst], ConstRegs::default(), String::new());
+        assert_eq!(program.validate().len(), 1);
+        assert!(program.encode(Endianness::Little).is_err());
+    }
+
+    #[test]
+    fn warning_only_program_encodes() {
+        let program = parse("swizzle ri.yzwx\nmov r0.x, [ri.x]+\n");
+        let diags = program.validate();
+        assert_eq!(diags.len(), 2);
+        assert!(diags.iter().all(|d| d.severity() == Severity::Warning));
+        assert_eq!(
+            program.encode(Endianness::Little).unwrap(),
+            codegen::to_bytes(&codegen::gen(program.instructions()), Endianness::Little)
+        );
+    }
+}
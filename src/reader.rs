@@ -18,7 +18,7 @@ impl<'a> Reader<'a> {
         }
     }
 
-    pub fn next(&mut self) -> Token {
+    pub fn next_token(&mut self) -> Token {
         let Some(start_c) = self.chars.next() else {
             return Token::new(TokenKind::EoF, 0);
         };
@@ -35,34 +35,68 @@ impl<'a> Reader<'a> {
             '.' => TokenKind::Dot,
             '[' => TokenKind::LeftBracket,
             ']' => TokenKind::RightBracket,
+            '{' => TokenKind::LeftBrace,
+            '}' => TokenKind::RightBrace,
             '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '*' => TokenKind::Star,
+            '%' | '$' => TokenKind::Sigil,
+            '"' => self.string(),
 
-            _ => {
-                panic!("unexpected start of token {}", start_c)
-            }
+            _ => TokenKind::Unknown,
         };
         let token = Token::new(kind, self.token_len());
         self.reset_len();
         token
     }
 
+    /// the source text that has not been read yet
+    pub fn remaining(&self) -> &'a str {
+        self.chars.as_str()
+    }
+
+    /// the current byte offset into a source that was `total_len` bytes long
+    pub fn offset(&self, total_len: usize) -> usize {
+        total_len - self.remaining().len()
+    }
+
     fn comment(&mut self) -> TokenKind {
-        self.eat_while(|c| c != '\n');
+        // searching for the newline directly is much faster than checking every char
+        let rest = self.chars.as_str();
+        let end = rest.find('\n').unwrap_or(rest.len());
+        self.chars = rest[end..].chars();
         TokenKind::Comment
     }
 
+    /// eats a string up to and including the closing `"`. strings may not contain
+    /// newlines, so a string without a closing `"` ends at the end of the line.
+    fn string(&mut self) -> TokenKind {
+        self.eat_while(|c| c != '"' && c != '\n');
+        if self.remaining().starts_with('"') {
+            self.chars.next();
+        }
+        TokenKind::Str
+    }
+
     fn eat_whitespace(&mut self) -> TokenKind {
+        // most whitespace is ASCII, so only fall back to checking chars at the first
+        // non-ASCII character
+        self.eat_ascii_while(|b| b != b'\n' && b.is_ascii_whitespace());
         self.eat_while(|c| c != '\n' && c.is_whitespace());
         TokenKind::Whitespace
     }
 
     fn ident(&mut self) -> TokenKind {
+        self.eat_ascii_while(|b| is_ident_continue(char::from(b)));
         self.eat_while(is_ident_continue);
         TokenKind::Ident
     }
 
+    /// eats a number, including any base prefix. this also eats letters so that a number
+    /// with invalid digits, like `0o8` or `12ab`, is a single token.
     fn number(&mut self) -> TokenKind {
-        self.eat_while(|c: char| c.is_ascii_digit());
+        self.eat_ascii_while(|b| is_ident_continue(char::from(b)));
+        self.eat_while(is_ident_continue);
         TokenKind::Number
     }
 
@@ -74,23 +108,33 @@ impl<'a> Reader<'a> {
         self.len_at_start = self.chars.as_str().len()
     }
 
-    fn at_eof(&self) -> bool {
-        self.chars.as_str().is_empty()
+    fn eat_while(&mut self, mut f: impl FnMut(char) -> bool) {
+        let rest = self.chars.as_str();
+        let end = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.chars = rest[end..].chars();
     }
 
-    fn eat_while(&mut self, mut f: impl FnMut(char) -> bool) {
-        while f(self.chars.clone().next().unwrap_or('\0')) && !self.at_eof() {
-            self.chars.next();
-        }
+    /// eats bytes until `f` returns false or a non-ASCII byte is reached. this avoids
+    /// decoding chars, so it is faster than `eat_while` for ASCII text.
+    fn eat_ascii_while(&mut self, f: impl Fn(u8) -> bool) {
+        let rest = self.chars.as_str();
+        // stopping at non-ASCII bytes means that this always stops on a char boundary
+        let end = rest
+            .bytes()
+            .position(|b| !b.is_ascii() || !f(b))
+            .unwrap_or(rest.len());
+        self.chars = rest[end..].chars();
     }
 }
 
+/// identifiers may only be ASCII, but non-ASCII letters are read as part of an identifier
+/// so that the whole identifier can be rejected at once
 fn is_ident_start(c: char) -> bool {
-    matches!(c, 'a'..='z'|'A'..='Z'|'_')
+    matches!(c, 'a'..='z'|'A'..='Z'|'_') || (!c.is_ascii() && c.is_alphabetic())
 }
 
 fn is_ident_continue(c: char) -> bool {
-    matches!(c, 'a'..='z'|'A'..='Z'|'0'..='9'|'_')
+    matches!(c, 'a'..='z'|'A'..='Z'|'0'..='9'|'_') || (!c.is_ascii() && c.is_alphanumeric())
 }
 
 #[derive(Debug)]
@@ -108,6 +152,10 @@ impl Token {
         self.len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     fn new(kind: TokenKind, len: usize) -> Self {
         Self { kind, len }
     }
@@ -123,7 +171,108 @@ pub enum TokenKind {
     Dot,
     LeftBracket,
     RightBracket,
+    LeftBrace,
+    RightBrace,
     Plus,
+    Minus,
+    Star,
+    /// `%` or `$`, which may be used before register names
+    Sigil,
     Ident,
     Number,
+    /// a string in double quotes, which may be missing its closing quote
+    Str,
+    /// a character that does not start any valid token
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_shrinks_as_tokens_are_read() {
+        let src = "mov r0.xy, [r1.x]+ # comment\nadd.w r0, r0, c1\n";
+        let mut reader = Reader::new(src);
+        assert_eq!(reader.remaining(), src);
+        assert_eq!(reader.offset(src.len()), 0);
+
+        let mut prev = reader.remaining().len();
+        loop {
+            let token = reader.next_token();
+            let remaining = reader.remaining();
+            assert_eq!(prev - remaining.len(), token.len());
+            assert!(src.ends_with(remaining));
+            assert_eq!(reader.offset(src.len()), src.len() - remaining.len());
+            if token.kind() == TokenKind::EoF {
+                break;
+            }
+            assert!(remaining.len() < prev);
+            prev = remaining.len();
+        }
+        assert_eq!(reader.remaining(), "");
+        assert_eq!(reader.offset(src.len()), src.len());
+    }
+
+    /// reads every token of `src` up to the end of the file, paired with its text
+    fn tokens(src: &str) -> Vec<(TokenKind, &str)> {
+        let mut reader = Reader::new(src);
+        let mut tokens = Vec::new();
+        loop {
+            let start = reader.offset(src.len());
+            let token = reader.next_token();
+            if token.kind() == TokenKind::EoF {
+                return tokens;
+            }
+            tokens.push((token.kind(), &src[start..start + token.len()]));
+        }
+    }
+
+    #[test]
+    fn ascii_runs_stop_at_non_ascii_chars() {
+        use TokenKind::*;
+        assert_eq!(
+            tokens("ab\u{e9}c \t\u{3000} 12\u{e9} # c\u{e9}\n"),
+            [
+                (Ident, "ab\u{e9}c"),
+                (Whitespace, " \t\u{3000} "),
+                (Number, "12\u{e9}"),
+                (Whitespace, " "),
+                (Comment, "# c\u{e9}"),
+                (Newline, "\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn whitespace_stops_at_newlines() {
+        use TokenKind::*;
+        assert_eq!(
+            tokens(" \t\n\u{3000}\n"),
+            [
+                (Whitespace, " \t"),
+                (Newline, "\n"),
+                (Whitespace, "\u{3000}"),
+                (Newline, "\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn token_lengths_cover_the_source() {
+        for src in [
+            "mov r0.xy, [r1.x]+\n",
+            ".include \"f\u{e9}.s\"\n",
+            "\"unterminated \u{1f980}\nnot r0",
+            "\u{e9}\u{e9}\u{e9} \u{3000}# \u{1f980}",
+            "0x1ffff 0o8 12ab\u{e9}",
+        ] {
+            let tokens = tokens(src);
+            assert!(tokens.iter().all(|(_, text)| !text.is_empty()));
+            assert_eq!(
+                tokens.iter().map(|(_, text)| *text).collect::<String>(),
+                src
+            );
+        }
+    }
 }
@@ -5,6 +5,9 @@ use std::{fs, io, path::Path};
 pub struct Source {
     filename: String,
     src: String,
+    /// the 0-indexed line of the file that this source starts at, for sources
+    /// that only contain part of a file
+    first_line: u32,
     /// the byte offset into the source that corresponds to the start of each line
     line_starts: Vec<u32>,
 }
@@ -13,13 +16,22 @@ impl Source {
     pub fn new_from_file(path: impl AsRef<Path>) -> io::Result<Source> {
         let filename = path.as_ref().display().to_string();
         let src = fs::read_to_string(path)?;
-        let line_starts = gen_lines(src.as_str());
+        Ok(Self::new(filename, src))
+    }
+
+    pub fn new(filename: String, src: String) -> Self {
+        Self::new_partial(filename, src, 0)
+    }
 
-        Ok(Self {
+    /// creates a source for a piece of a file that starts at the 0-indexed `first_line`
+    pub fn new_partial(filename: String, src: String, first_line: u32) -> Self {
+        let line_starts = gen_lines(src.as_str());
+        Self {
             filename,
             src,
+            first_line,
             line_starts,
-        })
+        }
     }
 
     pub fn filename(&self) -> &str {
@@ -30,6 +42,10 @@ impl Source {
         self.src.as_str()
     }
 
+    pub fn first_line(&self) -> u32 {
+        self.first_line
+    }
+
     pub fn line_starts(&self) -> &[u32] {
         self.line_starts.as_slice()
     }
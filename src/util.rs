@@ -1,4 +1,4 @@
-use std::fmt::UpperHex;
+use std::fmt::{self, Display, UpperHex};
 
 pub struct ArrayPrinter<'a>(pub &'a [u16]);
 
@@ -17,3 +17,165 @@ impl<'a> UpperHex for ArrayPrinter<'a> {
         Ok(())
     }
 }
+
+/// displays bytes as a conventional hex dump, with the offset of each row of 16 bytes
+/// and the bytes as ASCII where they are printable. the final line is the total length.
+pub struct HexDump<'a>(pub &'a [u8]);
+
+impl<'a> Display for HexDump<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (row, chunk) in self.0.chunks(16).enumerate() {
+            write!(f, "{:08x} ", row * 16)?;
+            for idx in 0..16 {
+                // an extra space separates the two halves of the row
+                if idx == 8 {
+                    write!(f, " ")?;
+                }
+                match chunk.get(idx) {
+                    Some(byte) => write!(f, " {byte:02x}")?,
+                    None => write!(f, "   ")?,
+                }
+            }
+
+            write!(f, "  |")?;
+            for &byte in chunk {
+                let c = if byte.is_ascii_graphic() || byte == b' ' {
+                    char::from(byte)
+                } else {
+                    '.'
+                };
+                write!(f, "{c}")?;
+            }
+            writeln!(f, "|")?;
+        }
+        writeln!(f, "{:08x}", self.0.len())
+    }
+}
+
+/// displays bytes in the Intel HEX format, starting at address 0, with 16 bytes of data
+/// per record and a final end of file record
+pub struct IntelHex<'a>(pub &'a [u8]);
+
+impl<'a> IntelHex<'a> {
+    const DATA: u8 = 0x00;
+    const END_OF_FILE: u8 = 0x01;
+    const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+
+    fn record(f: &mut fmt::Formatter<'_>, address: u16, kind: u8, data: &[u8]) -> fmt::Result {
+        let [addr_high, addr_low] = address.to_be_bytes();
+        write!(f, ":{:02X}{:04X}{:02X}", data.len(), address, kind)?;
+        let mut sum = (data.len() as u8)
+            .wrapping_add(addr_high)
+            .wrapping_add(addr_low)
+            .wrapping_add(kind);
+        for &byte in data {
+            write!(f, "{byte:02X}")?;
+            sum = sum.wrapping_add(byte);
+        }
+        // the checksum makes the sum of every byte in the record 0
+        writeln!(f, "{:02X}", sum.wrapping_neg())
+    }
+}
+
+impl<'a> Display for IntelHex<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (row, chunk) in self.0.chunks(16).enumerate() {
+            let address = row * 16;
+            // data records only have 16 bits of address, so the upper 16 bits are set by
+            // a separate record whenever they change
+            if address > 0 && address % 0x1_0000 == 0 {
+                let upper = (address >> 16) as u16;
+                Self::record(f, 0, Self::EXTENDED_LINEAR_ADDRESS, &upper.to_be_bytes())?;
+            }
+            Self::record(f, address as u16, Self::DATA, chunk)?;
+        }
+        Self::record(f, 0, Self::END_OF_FILE, &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_dump_rows() {
+        let dump = HexDump(b"wavevm assembler\x00\x01\xff").to_string();
+        assert_eq!(
+            dump,
+            "00000000  77 61 76 65 76 6d 20 61  73 73 65 6d 62 6c 65 72  |wavevm assembler|\n\
+             00000010  00 01 ff                                          |...|\n\
+             00000013\n"
+        );
+    }
+
+    #[test]
+    fn hex_dump_of_nothing() {
+        assert_eq!(HexDump(&[]).to_string(), "00000000\n");
+    }
+
+    /// parses each record of an Intel HEX file into its address, kind, and data, checking
+    /// its length and checksum
+    fn records(ihex: &str) -> Vec<(u16, u8, Vec<u8>)> {
+        ihex.lines()
+            .map(|line| {
+                let hex = line.strip_prefix(':').expect("records start with `:`");
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap())
+                    .collect::<Vec<_>>();
+                let len = usize::from(bytes[0]);
+                assert_eq!(bytes.len(), len + 5, "{}", line);
+                let sum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+                assert_eq!(sum, 0, "bad checksum in {}", line);
+                let address = u16::from_be_bytes([bytes[1], bytes[2]]);
+                (address, bytes[3], bytes[4..4 + len].to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn intel_hex_records() {
+        let data = (0..20).collect::<Vec<u8>>();
+        let ihex = IntelHex(&data).to_string();
+        assert_eq!(
+            ihex,
+            ":10000000000102030405060708090A0B0C0D0E0F78\n\
+             :0400100010111213A6\n\
+             :00000001FF\n"
+        );
+        assert_eq!(
+            records(&ihex),
+            [
+                (0x0000, IntelHex::DATA, data[..16].to_vec()),
+                (0x0010, IntelHex::DATA, data[16..].to_vec()),
+                (0x0000, IntelHex::END_OF_FILE, Vec::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn intel_hex_extended_address() {
+        let data = vec![0xA5; 0x1_0010];
+        let records = records(&IntelHex(&data).to_string());
+        let extended = records
+            .iter()
+            .filter(|(_, kind, _)| *kind == IntelHex::EXTENDED_LINEAR_ADDRESS)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            extended,
+            [&(0x0000, IntelHex::EXTENDED_LINEAR_ADDRESS, vec![0x00, 0x01])]
+        );
+        // the data after the extended address record starts over at address 0
+        let idx = records
+            .iter()
+            .position(|(_, kind, _)| *kind == IntelHex::EXTENDED_LINEAR_ADDRESS)
+            .unwrap();
+        assert_eq!(records[idx + 1], (0x0000, IntelHex::DATA, vec![0xA5; 16]));
+        let total = records
+            .iter()
+            .filter(|(_, kind, _)| *kind == IntelHex::DATA)
+            .map(|(_, _, data)| data.len())
+            .sum::<usize>();
+        assert_eq!(total, data.len());
+    }
+}
@@ -0,0 +1,429 @@
+use crate::{
+    diag::Diagnostic,
+    instruction::{
+        Instruction, InstructionKind, MemoryOperand, RegSelector, SetRegSelector, SetSelector,
+        ShiftAmount, SwizzleRegSelector,
+    },
+    lexer::Span,
+};
+
+/// checks that every instruction can be encoded and follows the rules the parser
+/// enforces, without generating any code. this is for instructions that were not
+/// parsed, such as ones that were built or changed by other tools, since the parser
+/// already reports these errors for parsed instructions.
+pub fn validate(program: &[Instruction]) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    for inst in program {
+        validate_inst(inst, &mut diags);
+    }
+    diags
+}
+
+fn validate_inst(inst: &Instruction, diags: &mut Vec<Diagnostic>) {
+    use InstructionKind::*;
+    match *inst.kind() {
+        Move { src, dst } => {
+            diags.extend(check_move_selectors(src, dst));
+            diags.extend(check_elem_count(src.selector(), 1, 4, "move"));
+            writable(dst.reg(), diags);
+        }
+        Swizzle { reg } => {
+            writable(reg.reg(), diags);
+            diags.extend(check_swizzle_ri(reg));
+        }
+        Load { mem, dst } => {
+            diags.extend(check_mem_value(dst));
+            diags.extend(check_conflicting_writes(inst.kind()).or_else(|| check_ri_increment(mem)));
+            writable(dst.reg(), diags);
+        }
+        Store { src, mem } => {
+            diags.extend(check_mem_value(src));
+            diags.extend(check_scatter_store(src, mem));
+            diags.extend(check_ri_increment(mem));
+        }
+
+        Add { dst, .. }
+        | Sub { dst, .. }
+        | SubRev { dst, .. }
+        | CmpEq { dst, .. }
+        | CmpNeq { dst, .. }
+        | AddSaturate { dst, .. }
+        | SubSaturate { dst, .. }
+        | SubRevSaturate { dst, .. } => writable(dst, diags),
+
+        ShiftLeft { dst, amount, .. }
+        | ShiftRightLogical { dst, amount, .. }
+        | ShiftRightArithmetic { dst, amount, .. }
+        | RotateLeft { dst, amount, .. }
+        | RotateRight { dst, amount, .. } => {
+            if let ShiftAmount::Const(val, span) = amount {
+                if val > 0b1111 {
+                    diags.push(Diagnostic::new(
+                        String::from("shift amount must not be greater than 15"),
+                        span,
+                    ));
+                }
+            }
+            writable(dst, diags);
+        }
+
+        BitAnd { dst, .. }
+        | BitOr { dst, .. }
+        | BitXor { dst, .. }
+        | BitNand { dst, .. }
+        | BitNor { dst, .. }
+        | BitXnor { dst, .. }
+        | UnaryBitNot { dst }
+        | BitNot { dst, .. } => writable(dst, diags),
+    }
+}
+
+fn writable(dst: RegSelector, diags: &mut Vec<Diagnostic>) {
+    if !dst.is_gpr() {
+        diags.push(Diagnostic::new(
+            format!("expected dst to be a writable register, got {}", dst),
+            dst.span(),
+        ));
+    }
+}
+
+/// checks that a register to register move selects the same elements of both registers.
+/// if the dst selects elements that the src does not, those elements are named, since
+/// they would have no value to move.
+pub(crate) fn check_move_selectors(src: SetRegSelector, dst: SetRegSelector) -> Option<Diagnostic> {
+    if src.selector() == dst.selector() {
+        return None;
+    }
+    let diag = Diagnostic::new(
+        String::from("lhs and rhs of move must select the same elements"),
+        Span::between(dst.span(), src.span()),
+    );
+    let missing = dst.selector().bits() & !src.selector().bits();
+    Some(if missing != 0 {
+        let missing = SetSelector::from_bits(missing, Span::DUMMY);
+        diag.with_note(format!(
+            "{} `{}` {} not selected in the source, so there is no value to move",
+            if missing.count() == 1 {
+                "element"
+            } else {
+                "elements"
+            },
+            missing,
+            if missing.count() == 1 { "is" } else { "are" },
+        ))
+    } else {
+        diag
+    })
+}
+
+/// checks that `selector` selects from `min` to `max` elements, where `what` names the
+/// instruction or operand it is for
+pub(crate) fn check_elem_count(
+    selector: SetSelector,
+    min: u8,
+    max: u8,
+    what: &str,
+) -> Option<Diagnostic> {
+    let count = selector.count();
+    let (bound, limit) = if min == max && count != min {
+        (min, "exactly")
+    } else if count < min {
+        (min, "at least")
+    } else if count > max {
+        (max, "at most")
+    } else {
+        return None;
+    };
+    Some(Diagnostic::new(
+        format!(
+            "{} must select {} {} element{}",
+            what,
+            limit,
+            bound,
+            if bound == 1 { "" } else { "s" }
+        ),
+        selector.span(),
+    ))
+}
+
+/// checks that the register of a load or store selects sequential elements starting
+/// with `x`, since only the number of elements is encoded
+pub(crate) fn check_mem_value(value: SetRegSelector) -> Option<Diagnostic> {
+    if let Some(d) = check_elem_count(value.selector(), 1, 4, "the register of a memory move") {
+        return Some(d);
+    }
+    let bits = value.selector().bits();
+    (bits & (bits + 1) != 0).then(|| {
+        Diagnostic::new(
+            String::from(
+                "the register of a memory move must select sequential elements starting with `x`",
+            ),
+            value.selector().span(),
+        )
+    })
+}
+
+/// checks that no register is written by more than one operand, such as a load into the
+/// register that its address is incremented in, since the order of the writes is not
+/// defined
+pub(crate) fn check_conflicting_writes(kind: &InstructionKind) -> Option<Diagnostic> {
+    let writes = kind
+        .operands()
+        .into_iter()
+        .filter(|(_, role)| role.writes())
+        .map(|(reg, _)| reg)
+        .collect::<Vec<_>>();
+    writes.iter().enumerate().find_map(|(idx, first)| {
+        let second = writes[idx + 1..].iter().find(|reg| *reg == first)?;
+        let diag = Diagnostic::new(
+            format!("`{}` is written more than once by this instruction", second),
+            second.span(),
+        )
+        .with_note_at(String::from("it is also written here"), first.span());
+        Some(if second.is_ri() {
+            diag.with_note(String::from("writing to `ri` jumps"))
+        } else {
+            diag
+        })
+    })
+}
+
+/// warns about incrementing `ri` in a memory operand. `ri` may be used as an address like
+/// any other register, but incrementing it is a write to `ri`, which jumps. it is only
+/// incremented once, by the same amount as any other address register.
+pub(crate) fn check_ri_increment(mem: MemoryOperand) -> Option<Diagnostic> {
+    (mem.increment() && mem.reg().is_ri()).then(|| {
+        Diagnostic::new_warning(String::from("incrementing `ri` jumps"), mem.span()).with_note(
+            String::from("remove the `+` to use `ri` as an address without jumping"),
+        )
+    })
+}
+
+/// warns about swizzling `ri`. every register can be read, but a swizzle also writes
+/// the register, and writing to `ri` jumps, even if the swizzle does not move any elements.
+pub(crate) fn check_swizzle_ri(reg: SwizzleRegSelector) -> Option<Diagnostic> {
+    reg.reg().is_ri().then(|| {
+        Diagnostic::new_warning(String::from("swizzling `ri` jumps"), reg.span()).with_note(
+            String::from("the swizzled value is written back to `ri`, even for `ri.xyzw`"),
+        )
+    })
+}
+
+/// checks that a scattered store writes an element to each of the 4 addresses
+pub(crate) fn check_scatter_store(src: SetRegSelector, mem: MemoryOperand) -> Option<Diagnostic> {
+    let count = src.selector().count();
+    (mem.scatter() && count != 4).then(|| {
+        Diagnostic::new(
+            format!(
+                "scattered store writes to 4 addresses, but {} element{} selected",
+                count,
+                if count == 1 { " is" } else { "s are" }
+            ),
+            Span::between(mem.span(), src.span()),
+        )
+        .with_note(String::from(
+            "use `reg.xyzw` to store every element, or `[reg.x]` to store to a single address",
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        diag::Severity,
+        instruction::{OpSize, SwizzleSelector},
+    };
+
+    fn messages(diags: &[Diagnostic]) -> Vec<&str> {
+        diags.iter().map(|d| d.msg()).collect()
+    }
+
+    fn set_reg(idx: u8, bits: u8) -> SetRegSelector {
+        SetRegSelector::new(
+            RegSelector::new_gpr(idx, Span::DUMMY),
+            SetSelector::from_bits(bits, Span::DUMMY),
+            Span::DUMMY,
+        )
+    }
+
+    fn mem(idx: u8, scatter: bool, increment: bool) -> MemoryOperand {
+        MemoryOperand::new(
+            RegSelector::new_gpr(idx, Span::DUMMY),
+            scatter,
+            increment,
+            Span::DUMMY,
+        )
+    }
+
+    fn store(bits: u8, scatter: bool) -> Instruction {
+        Instruction::new(
+            InstructionKind::Store {
+                src: set_reg(1, bits),
+                mem: mem(0, scatter, false),
+            },
+            Span::DUMMY,
+        )
+    }
+
+    #[test]
+    fn matched_stores() {
+        for inst in [
+            store(0b0001, false),
+            store(0b0011, false),
+            store(0b1111, false),
+            store(0b1111, true),
+        ] {
+            assert!(validate(&[inst]).is_empty(), "{}", inst.kind());
+        }
+    }
+
+    #[test]
+    fn mismatched_stores() {
+        assert_eq!(
+            messages(&validate(&[store(0b0011, true)])),
+            ["scattered store writes to 4 addresses, but 2 elements are selected"]
+        );
+        assert_eq!(
+            messages(&validate(&[store(0b0001, true)])),
+            ["scattered store writes to 4 addresses, but 1 element is selected"]
+        );
+        assert_eq!(
+            messages(&validate(&[store(0b0110, false)])),
+            ["the register of a memory move must select sequential elements starting with `x`"]
+        );
+    }
+
+    fn validate_one(kind: InstructionKind) -> Vec<Diagnostic> {
+        validate(&[Instruction::new(kind, Span::DUMMY)])
+    }
+
+    #[test]
+    fn unwritable_dst() {
+        let diags = validate_one(InstructionKind::Add {
+            size: OpSize::Word,
+            src: RegSelector::new_gpr(0, Span::DUMMY),
+            dst: RegSelector::new_const(1, Span::DUMMY),
+        });
+        assert_eq!(
+            messages(&diags),
+            ["expected dst to be a writable register, got c1"]
+        );
+    }
+
+    #[test]
+    fn shift_amount_out_of_range() {
+        let shift = |val| {
+            validate_one(InstructionKind::ShiftLeft {
+                size: OpSize::Word,
+                dst: RegSelector::new_gpr(0, Span::DUMMY),
+                amount: ShiftAmount::Const(val, Span::DUMMY),
+            })
+        };
+        assert!(shift(15).is_empty());
+        assert_eq!(
+            messages(&shift(16)),
+            ["shift amount must not be greater than 15"]
+        );
+    }
+
+    #[test]
+    fn move_selectors() {
+        let mov = |src, dst| {
+            validate_one(InstructionKind::Move {
+                src: set_reg(1, src),
+                dst: set_reg(0, dst),
+            })
+        };
+        assert!(mov(0b0101, 0b0101).is_empty());
+        assert_eq!(
+            messages(&mov(0, 0)),
+            ["move must select at least 1 element"]
+        );
+        let diags = mov(0b0001, 0b0011);
+        assert_eq!(
+            messages(&diags),
+            ["lhs and rhs of move must select the same elements"]
+        );
+        assert_eq!(
+            diags[0].notes()[0].msg(),
+            "element `y` is not selected in the source, so there is no value to move"
+        );
+    }
+
+    #[test]
+    fn empty_memory_register() {
+        let diags = validate_one(InstructionKind::Load {
+            mem: mem(1, false, false),
+            dst: set_reg(0, 0),
+        });
+        assert_eq!(
+            messages(&diags),
+            ["the register of a memory move must select at least 1 element"]
+        );
+    }
+
+    #[test]
+    fn conflicting_writes() {
+        let diags = validate_one(InstructionKind::Load {
+            mem: mem(0, false, true),
+            dst: set_reg(0, 0b0001),
+        });
+        assert_eq!(
+            messages(&diags),
+            ["`r0` is written more than once by this instruction"]
+        );
+    }
+
+    #[test]
+    fn ri_writes_warn() {
+        let diags = validate_one(InstructionKind::Store {
+            src: set_reg(0, 0b0001),
+            mem: mem(7, false, true),
+        });
+        assert_eq!(messages(&diags), ["incrementing `ri` jumps"]);
+        assert_eq!(diags[0].severity(), Severity::Warning);
+
+        let diags = validate_one(InstructionKind::Swizzle {
+            reg: SwizzleRegSelector::new(
+                RegSelector::new_gpr(7, Span::DUMMY),
+                SwizzleSelector::from_bits(0b11_10_01_00, Span::DUMMY),
+                Span::DUMMY,
+            ),
+        });
+        assert_eq!(messages(&diags), ["swizzling `ri` jumps"]);
+        assert_eq!(diags[0].severity(), Severity::Warning);
+    }
+
+    fn elem_count_error(bits: u8, min: u8, max: u8) -> Option<String> {
+        check_elem_count(SetSelector::from_bits(bits, Span::DUMMY), min, max, "test")
+            .map(|d| d.msg().to_string())
+    }
+
+    #[test]
+    fn elem_count_range() {
+        // below the range
+        assert_eq!(
+            elem_count_error(0b0001, 2, 3).as_deref(),
+            Some("test must select at least 2 elements")
+        );
+        assert_eq!(
+            elem_count_error(0, 1, 4).as_deref(),
+            Some("test must select at least 1 element")
+        );
+        // within the range
+        for bits in [0b0011, 0b0101, 0b0111, 0b1110] {
+            assert_eq!(elem_count_error(bits, 2, 3), None);
+        }
+        // above the range
+        assert_eq!(
+            elem_count_error(0b1111, 2, 3).as_deref(),
+            Some("test must select at most 3 elements")
+        );
+        assert_eq!(
+            elem_count_error(0b0011, 1, 1).as_deref(),
+            Some("test must select exactly 1 element")
+        );
+        assert_eq!(elem_count_error(0b1000, 1, 1), None);
+    }
+}